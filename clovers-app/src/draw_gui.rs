@@ -1,4 +1,7 @@
-use crate::{color::Color, colorize::colorize, scenes::Scene, Float};
+use crate::{
+    bidirectional::Splat, color::Color, colorize::PathStats, integrator::Integrator,
+    random::sample_rng, scenes::Scene, Float,
+};
 
 use indicatif::{ProgressBar, ProgressStyle};
 use rand::prelude::*;
@@ -20,7 +23,8 @@ pub fn draw_gui(
     height: u32,
     samples: u32,
     max_depth: u32,
-    gamma: Float,
+    integrator: Integrator,
+    seed: u64,
     scene: Scene,
 ) -> Result<(), Error> {
     let event_loop = EventLoop::new();
@@ -39,7 +43,7 @@ pub fn draw_gui(
         Pixels::new(width, height, surface_texture)?
     };
 
-    let mut world = World::new(width, height, samples, max_depth, gamma, scene);
+    let mut world = World::new(width, height, samples, max_depth, integrator, seed, scene);
     let mut frame_num = 0;
 
     event_loop.run(move |event, _, control_flow| {
@@ -85,7 +89,8 @@ struct World {
     bar: ProgressBar,
     samples: u32,
     max_depth: u32,
-    gamma: Float,
+    integrator: Integrator,
+    seed: u64,
 }
 
 impl World {
@@ -94,7 +99,8 @@ impl World {
         height: u32,
         samples: u32,
         max_depth: u32,
-        gamma: Float,
+        integrator: Integrator,
+        seed: u64,
         scene: Scene,
     ) -> Self {
         // Progress bar
@@ -111,7 +117,8 @@ impl World {
             bar,
             samples,
             max_depth,
-            gamma,
+            integrator,
+            seed,
         }
     }
 
@@ -126,22 +133,33 @@ impl World {
         let camera = &self.scene.camera;
         let scene = &self.scene;
         let max_depth = self.max_depth;
+        let integrator = self.integrator;
+        let seed = self.seed;
 
         // Update internal float-based pixel buffer with new samples
-        self.float_buffer
+        let splats: Vec<Splat> = self
+            .float_buffer
             .par_chunks_exact_mut(4)
             .enumerate()
-            .for_each(|(i, pixel)| {
+            .flat_map_iter(|(i, pixel)| {
                 let x = (i % width) as i16;
                 let y = height as i16 - (i / width) as i16; // flip y-axis
 
-                let mut rng = rand::thread_rng();
+                let mut rng = sample_rng(seed, i as u64, frame_num as u64);
                 let mut color: Color = Color::new(0.0, 0.0, 0.0);
 
                 let u = (x as Float + rng.gen::<Float>()) / width as Float;
                 let v = (y as Float + rng.gen::<Float>()) / height as Float;
-                let ray = camera.get_ray(u, v, rng);
-                let new_color = colorize(&ray, &scene, 0, max_depth, rng);
+                let ray = camera.get_ray(u, v, &mut rng);
+                let mut splats = Vec::new();
+                let new_color = integrator.sample(
+                    &ray,
+                    scene,
+                    max_depth,
+                    &mut PathStats::default(),
+                    &mut splats,
+                    &mut rng,
+                );
                 // skip NaN and Infinity
                 if new_color.r.is_finite() && new_color.g.is_finite() && new_color.b.is_finite() {
                     color += new_color;
@@ -154,7 +172,27 @@ impl World {
                 // write
                 let rgba = &[color.r, color.g, color.b, 1.0];
                 pixel.copy_from_slice(rgba);
-            });
+                splats
+            })
+            .collect();
+
+        // Every frame traces one light path per pixel, so the splats are added as they are
+        for splat in splats {
+            if !(splat.color.r.is_finite()
+                && splat.color.g.is_finite()
+                && splat.color.b.is_finite())
+            {
+                continue;
+            }
+            let x = ((splat.u * width as Float) as usize).min(width - 1);
+            let y = (splat.v * height as Float) as usize;
+            // flip y-axis, like the camera rays above
+            let row = height.saturating_sub(y).min(height - 1);
+            let i = 4 * (row * width + x);
+            self.float_buffer[i] += splat.color.r;
+            self.float_buffer[i + 1] += splat.color.g;
+            self.float_buffer[i + 2] += splat.color.b;
+        }
 
         // Write to actual framebuffer
         frame
@@ -167,8 +205,8 @@ impl World {
                 let _a = self.float_buffer[i * 4 + 3];
                 // NOTE: divided because internal floatbuffer keeps summing values
                 let color = Color::new(r, g, b) / frame_num as Float;
-                // gamma correction
-                let color = color.gamma_correction(self.gamma);
+                // tone mapping and gamma correction
+                let color = self.scene.tone_mapping.apply(color);
                 let rgb = color.to_rgb_u8();
                 // weight the pixel down based on frame number
                 let rgba = [rgb[0], rgb[1], rgb[2], 0xFF]; //TODO: alpha in color?
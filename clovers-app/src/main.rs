@@ -6,13 +6,14 @@
 // External imports
 use clap::Clap;
 use std::error::Error;
-use std::fs::File;
 
 // Internal imports
 use clovers::*;
 mod draw_gui;
 use draw_gui::draw_gui;
+use integrator::Integrator;
 use scenes::Scene;
+use tonemap::{ToneMapping, Transfer};
 
 // Configure CLI parameters
 #[derive(Clap)]
@@ -36,9 +37,15 @@ struct Opts {
     /// Maximum evaluated bounce depth for each ray
     #[clap(short, long, default_value = "100")]
     max_depth: u32,
-    /// Gamma correction value
-    #[clap(short, long, default_value = "2.0")]
-    gamma: Float,
+    /// Integrator: path for the physically based path tracer, bdpt for the bidirectional path tracer, or a debug view of the first hits: normals, uv, albedo, ao or bvh-cost
+    #[clap(long, default_value = "path")]
+    integrator: Integrator,
+    /// Gamma correction value [default: from the scene file, or 2.0]
+    #[clap(short, long)]
+    gamma: Option<Float>,
+    /// Seed for the random number generator. Renders with the same scene and seed are identical
+    #[clap(long, default_value = "0")]
+    seed: u64,
 }
 
 fn main() -> Result<(), Box<dyn Error>> {
@@ -48,15 +55,26 @@ fn main() -> Result<(), Box<dyn Error>> {
     println!("width:        {}", opts.width);
     println!("height:       {}", opts.height);
     println!("samples:      {}", opts.samples);
+    println!("integrator:   {}", opts.integrator);
     println!("max depth:    {}", opts.max_depth);
+    println!("seed:         {}", opts.seed);
     let rays: u64 =
         opts.width as u64 * opts.height as u64 * opts.samples as u64 * opts.max_depth as u64;
     println!("approx. rays: {}", rays);
     println!(); // Empty line before progress bar
 
     // Read the given scene file
-    let file = File::open(opts.input)?;
-    let scene: Scene = scenes::initialize(file, opts.width, opts.height)?;
+    let mut scene: Scene = scenes::initialize(&opts.input, opts.width, opts.height)?;
+    if opts.integrator.is_debug() {
+        // Debug views are shown as they are
+        scene.tone_mapping = ToneMapping {
+            transfer: Transfer::Gamma(1.0),
+            ..ToneMapping::default()
+        };
+    }
+    if let Some(gamma) = opts.gamma {
+        scene.tone_mapping.transfer = Transfer::Gamma(gamma);
+    }
 
     // gui version
     let _result = draw_gui(
@@ -64,7 +82,8 @@ fn main() -> Result<(), Box<dyn Error>> {
         opts.height,
         opts.samples,
         opts.max_depth,
-        opts.gamma,
+        opts.integrator,
+        opts.seed,
         scene,
     );
 
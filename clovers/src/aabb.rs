@@ -51,4 +51,15 @@ impl AABB {
 
         AABB::new(small, big)
     }
+
+    /// Returns the surface area of the bounding box
+    pub fn surface_area(&self) -> Float {
+        let extent: Vec3 = self.max - self.min;
+        2.0 * (extent.x * extent.y + extent.y * extent.z + extent.z * extent.x)
+    }
+
+    /// Returns the center point of the bounding box
+    pub fn centroid(&self) -> Vec3 {
+        0.5 * (self.min + self.max)
+    }
 }
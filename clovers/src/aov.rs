@@ -0,0 +1,285 @@
+//! Arbitrary output variables (AOVs): auxiliary render passes holding data about the first hit of the camera rays, for denoising and compositing.
+
+use crate::{
+    color::Color, hitable::Hitable, materials::Material, random::sample_rng, ray::Ray,
+    scenes::Scene, Float, RandomGenerator, Vec3,
+};
+use rand::prelude::*;
+use std::{fmt, str::FromStr};
+
+/// The available auxiliary render passes
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Aov {
+    /// Color of the material at the first hit, ignoring lighting. The environment color for rays that miss. Volumes are white: whether a ray scatters in a volume or passes through it is random, so their color would only add noise.
+    Albedo,
+    /// Shading normal at the first hit. Zero for rays that miss. Volumes have no surface, so their normal points back towards the camera.
+    Normal,
+    /// Distance from the camera to the first hit. Infinite for rays that miss.
+    Depth,
+    /// Identifier of the object at the first hit: its index in the scene file plus one. Zero for rays that miss. As every object has its own material, this also identifies the material.
+    ObjectId,
+    /// Texture coordinates at the first hit
+    Uv,
+}
+
+impl Aov {
+    /// Name of the pass, used for the EXR layers and the file names of the separate images
+    pub fn name(&self) -> &'static str {
+        match self {
+            Aov::Albedo => "albedo",
+            Aov::Normal => "normal",
+            Aov::Depth => "depth",
+            Aov::ObjectId => "object_id",
+            Aov::Uv => "uv",
+        }
+    }
+
+    /// Names of the channels of the pass
+    pub fn channels(&self) -> &'static [&'static str] {
+        match self {
+            Aov::Albedo => &["R", "G", "B"],
+            Aov::Normal => &["X", "Y", "Z"],
+            Aov::Depth => &["Z"],
+            Aov::ObjectId => &["id"],
+            Aov::Uv => &["U", "V"],
+        }
+    }
+}
+
+impl FromStr for Aov {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "albedo" => Ok(Aov::Albedo),
+            "normal" => Ok(Aov::Normal),
+            "depth" => Ok(Aov::Depth),
+            "object_id" | "id" => Ok(Aov::ObjectId),
+            "uv" => Ok(Aov::Uv),
+            _ => Err(format!(
+                "unknown AOV: {}. Valid values: albedo, normal, depth, object_id, uv",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Aov {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// Data about the first hit of a camera ray
+pub struct FirstHit {
+    pub albedo: Color,
+    pub normal: Vec3,
+    pub depth: Float,
+    pub object_id: Float,
+    pub uv: (Float, Float),
+}
+
+/// Finds the first hit of a camera ray and returns the data for the AOVs
+pub fn first_hit(ray: &Ray, scene: &Scene, rng: &mut RandomGenerator) -> FirstHit {
+    let distance_min = crate::EPSILON_SHADOW_ACNE;
+    let hit = match &scene.objects {
+        Hitable::FlatBVH(bvh) => bvh
+            .hit_object(ray, distance_min, Float::INFINITY, rng)
+            .map(|(id, hit_record)| (id as Float + 1.0, hit_record)),
+        objects => objects
+            .hit(ray, distance_min, Float::INFINITY, rng)
+            .map(|hit_record| (1.0, hit_record)),
+    };
+
+    match hit {
+        Some((object_id, hit_record)) => {
+            let (albedo, normal) = match hit_record.material {
+                Material::Isotropic(_) | Material::HenyeyGreenstein(_) => {
+                    (Color::new(1.0, 1.0, 1.0), -ray.direction.normalize())
+                }
+                _ => (hit_record.material.albedo(&hit_record), hit_record.normal),
+            };
+            FirstHit {
+                albedo,
+                normal,
+                depth: hit_record.distance * ray.direction.norm(),
+                object_id,
+                uv: (hit_record.u, hit_record.v),
+            }
+        }
+        None => FirstHit {
+            albedo: scene.environment.color(ray.direction),
+            normal: Vec3::new(0.0, 0.0, 0.0),
+            depth: Float::INFINITY,
+            object_id: 0.0,
+            uv: (0.0, 0.0),
+        },
+    }
+}
+
+/// Buffers for all the AOVs of a render, in row-major order
+pub struct AovBuffers {
+    pub albedo: Vec<Color>,
+    pub normal: Vec<Vec3>,
+    pub depth: Vec<Float>,
+    pub object_id: Vec<Float>,
+    pub uv: Vec<(Float, Float)>,
+}
+
+impl AovBuffers {
+    /// Renders the AOVs for all pixels. The values are averaged over the given number of samples per pixel, except for the depth, which is averaged over the samples that hit something, and the object id, which comes from the first sample. Sample `n` of each pixel uses the same random number stream and thus the same camera ray as the sample `n` of the main render.
+    pub fn render(width: u32, height: u32, samples: u32, seed: u64, scene: &Scene) -> AovBuffers {
+        use rayon::prelude::*;
+
+        let samples = samples.max(1);
+        let pixels: Vec<FirstHit> = (0..width * height)
+            .into_par_iter()
+            .map(|index| {
+                let x = index % width;
+                let y = index / width;
+                let mut albedo = Color::new(0.0, 0.0, 0.0);
+                let mut normal = Vec3::new(0.0, 0.0, 0.0);
+                let mut depth = 0.0;
+                let mut depth_hits = 0;
+                let mut object_id = 0.0;
+                let mut uv = (0.0, 0.0);
+                for sample_index in 0..samples {
+                    let mut rng = sample_rng(seed, index as u64, sample_index as u64);
+                    // Same jitter as in the main render
+                    let u = (x as Float + rng.gen::<Float>()) / width as Float;
+                    let v = (y as Float + rng.gen::<Float>()) / height as Float;
+                    let ray: Ray = scene.camera.get_ray(u, v, &mut rng);
+                    let hit = first_hit(&ray, scene, &mut rng);
+                    albedo += hit.albedo;
+                    normal += hit.normal;
+                    if hit.depth.is_finite() {
+                        depth += hit.depth;
+                        depth_hits += 1;
+                    }
+                    if sample_index == 0 {
+                        object_id = hit.object_id;
+                    }
+                    uv.0 += hit.uv.0;
+                    uv.1 += hit.uv.1;
+                }
+                let samples = samples as Float;
+                FirstHit {
+                    albedo: albedo / samples,
+                    normal: normal / samples,
+                    depth: if depth_hits > 0 {
+                        depth / depth_hits as Float
+                    } else {
+                        Float::INFINITY
+                    },
+                    object_id,
+                    uv: (uv.0 / samples, uv.1 / samples),
+                }
+            })
+            .collect();
+
+        AovBuffers {
+            albedo: pixels.iter().map(|pixel| pixel.albedo).collect(),
+            normal: pixels.iter().map(|pixel| pixel.normal).collect(),
+            depth: pixels.iter().map(|pixel| pixel.depth).collect(),
+            object_id: pixels.iter().map(|pixel| pixel.object_id).collect(),
+            uv: pixels.iter().map(|pixel| pixel.uv).collect(),
+        }
+    }
+
+    /// Reverses the order of the pixels in all the buffers. Used for fixing the orientation of the image, like for the main render.
+    pub fn reverse(&mut self) {
+        self.albedo.reverse();
+        self.normal.reverse();
+        self.depth.reverse();
+        self.object_id.reverse();
+        self.uv.reverse();
+    }
+
+    /// Returns the values of a single channel of the given AOV, see [Aov::channels()]
+    pub fn channel(&self, aov: Aov, channel: usize) -> Vec<Float> {
+        match aov {
+            Aov::Albedo => self
+                .albedo
+                .iter()
+                .map(|color| [color.r, color.g, color.b][channel])
+                .collect(),
+            Aov::Normal => self.normal.iter().map(|normal| normal[channel]).collect(),
+            Aov::Depth => self.depth.clone(),
+            Aov::ObjectId => self.object_id.clone(),
+            Aov::Uv => self.uv.iter().map(|uv| [uv.0, uv.1][channel]).collect(),
+        }
+    }
+
+    /// Returns the raw values of the given AOV as colors, e.g. for saving as a high dynamic range image
+    pub fn colors(&self, aov: Aov) -> Vec<Color> {
+        match aov {
+            Aov::Albedo => self.albedo.clone(),
+            Aov::Normal => self
+                .normal
+                .iter()
+                .map(|normal| Color::new(normal.x, normal.y, normal.z))
+                .collect(),
+            Aov::Depth => self
+                .depth
+                .iter()
+                .map(|&depth| Color::new(depth, depth, depth))
+                .collect(),
+            Aov::ObjectId => self
+                .object_id
+                .iter()
+                .map(|&id| Color::new(id, id, id))
+                .collect(),
+            Aov::Uv => self
+                .uv
+                .iter()
+                .map(|uv| Color::new(uv.0, uv.1, 0.0))
+                .collect(),
+        }
+    }
+
+    /// Returns the given AOV as displayable colors in the `0.0..=1.0` range: normals are remapped from `-1.0..=1.0`, depth is normalized to the farthest hit, and every object id gets a random color.
+    pub fn visualize(&self, aov: Aov) -> Vec<Color> {
+        match aov {
+            Aov::Albedo | Aov::Uv => self.colors(aov),
+            Aov::Normal => self
+                .normal
+                .iter()
+                .map(|normal| {
+                    let normal = 0.5 * (normal + Vec3::new(1.0, 1.0, 1.0));
+                    Color::new(normal.x, normal.y, normal.z)
+                })
+                .collect(),
+            Aov::Depth => {
+                let max = self
+                    .depth
+                    .iter()
+                    .copied()
+                    .filter(|depth| depth.is_finite())
+                    .fold(0.0, Float::max);
+                self.depth
+                    .iter()
+                    .map(|&depth| {
+                        // Near is bright, far and missed rays are black
+                        let value = if depth.is_finite() && max > 0.0 {
+                            1.0 - depth / max
+                        } else {
+                            0.0
+                        };
+                        Color::new(value, value, value)
+                    })
+                    .collect()
+            }
+            Aov::ObjectId => self
+                .object_id
+                .iter()
+                .map(|&id| {
+                    if id == 0.0 {
+                        Color::new(0.0, 0.0, 0.0)
+                    } else {
+                        Color::random(&mut RandomGenerator::seed_from_u64(id as u64))
+                    }
+                })
+                .collect(),
+        }
+    }
+}
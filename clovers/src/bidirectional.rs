@@ -0,0 +1,757 @@
+//! A bidirectional path tracer. Given a [Ray](crate::ray::Ray) from the camera and a [Scene](crate::scenes::Scene), traces a subpath from the camera and another one from a light source, and connects every vertex of one to every vertex of the other. Each connection is a different sampling strategy for the same light transport path, and the strategies are weighted with the power heuristic of [multiple importance sampling](https://graphics.stanford.edu/courses/cs348b-03/papers/veach-chapter9.pdf). See [Veach's thesis, chapter 10](https://graphics.stanford.edu/papers/veach_thesis/chapter10.pdf) and [pbrt](https://www.pbr-book.org/3ed-2018/Light_Transport_III_Bidirectional_Methods/Bidirectional_Path_Tracing).
+//!
+//! Light paths start from the emissive objects among the `priority_objects` of the scene. Connecting their vertices directly to the camera finds caustics and lights seen through small openings, which the [unidirectional path tracer](crate::colorize) rarely finds. These connections can land anywhere on the image, so they are returned as [Splats](Splat) instead of being added to the color of the pixel.
+//!
+//! Light arriving from the environment and from emissive objects that are not in the `priority_objects` can only be found by the camera subpaths.
+
+use crate::{
+    camera::Camera,
+    color::Color,
+    colorize::{survival_probability, PathStats},
+    hitable::{HitRecord, Hitable, SurfaceSample},
+    materials::{Material, MaterialType},
+    onb::ONB,
+    random::random_cosine_direction,
+    ray::Ray,
+    scenes::Scene,
+    Float, RandomGenerator, Vec3, EPSILON_SHADOW_ACNE, PI,
+};
+use rand::prelude::*;
+use std::sync::Arc;
+
+/// Light found by connecting a light subpath directly to the camera. It belongs to the point of the image at the `(u, v)` coordinates of [get_ray()](Camera::get_ray), not to the pixel of the camera ray. The image is the sum of the pixel colors and the splats, divided by the number of light paths per pixel: with one light path per sample, the splats are scaled by the number of pixels divided by the total number of samples.
+#[derive(Copy, Clone, Debug)]
+pub struct Splat {
+    pub u: Float,
+    pub v: Float,
+    pub color: Color,
+}
+
+/// Evaluates the light arriving along the camera ray with bidirectional path tracing. Like the paths of [colorize()](crate::colorize::colorize), the camera subpath has at most `max_depth + 1` bounces, and the connected paths can have one more vertex, like the light samples at the last bounce. Records the length of the camera subpath in the statistics, and pushes the light subpath connections to the camera into `splats`.
+pub fn bidirectional(
+    ray: &Ray,
+    scene: &Scene,
+    max_depth: u32,
+    stats: &mut PathStats,
+    splats: &mut Vec<Splat>,
+    rng: &mut RandomGenerator,
+) -> Color {
+    let camera = &scene.camera;
+    let emitters = &scene.emitters;
+    // The camera vertex and the bounces, the last one of which may be on a light source
+    let max_camera_vertices = max_depth as usize + 2;
+    let max_vertices = max_camera_vertices + 1;
+
+    let mut camera_path = vec![Vertex::camera(ray.origin)];
+    let mut radiance = random_walk(
+        scene,
+        *ray,
+        Color::new(1.0, 1.0, 1.0),
+        camera.pdf_direction(ray.direction),
+        max_camera_vertices,
+        &mut camera_path,
+        rng,
+    );
+    stats.paths += 1;
+    stats.bounces += camera_path.len() as u64 - 1;
+
+    let mut light_path = Vec::new();
+    if let Some(light) = emitters.sample(&scene.objects, ray, rng) {
+        if let Some(normal) = light.normal {
+            let direction = ONB::build_from_w(normal).local(random_cosine_direction(rng));
+            let cosine = direction.normalize().dot(&normal);
+            let light_ray =
+                Ray::new(light.position, direction, ray.time).with_wavelength(ray.wavelength);
+            // Emitted radiance times the cosine, divided by the densities of the point and the cosine weighted direction
+            let beta = light.beta * light.emission() * PI;
+            light_path.push(light);
+            random_walk(
+                scene,
+                light_ray,
+                beta,
+                cosine / PI,
+                max_vertices - 1,
+                &mut light_path,
+                rng,
+            );
+        }
+    }
+
+    for t in 1..=camera_path.len() {
+        for s in 0..=light_path.len() {
+            if s + t < 2 || s + t > max_vertices {
+                continue;
+            }
+            let connection = Connection {
+                camera_path: &camera_path,
+                light_path: &light_path,
+                s,
+                t,
+                max_camera_vertices,
+            };
+            if t == 1 {
+                if let Some(splat) = connection.splat(scene, emitters, ray, rng) {
+                    splats.push(splat);
+                }
+            } else {
+                radiance += connection.radiance(scene, emitters, ray, rng);
+            }
+        }
+    }
+
+    radiance
+}
+
+/// The kinds of the vertices of the subpaths
+enum VertexKind<'a> {
+    /// A point on the lens, where camera subpaths start
+    Camera,
+    /// A point sampled on an emissive object, where light subpaths start
+    Light { emission: Color },
+    /// A hit of a subpath. Materials that scattered the ray diffusely have the attenuation of their [ScatterRecord](crate::materials::ScatterRecord).
+    Surface {
+        ray: Ray,
+        hit_record: HitRecord<'a>,
+        attenuation: Option<Color>,
+    },
+}
+
+/// A vertex of a camera or light subpath
+struct Vertex<'a> {
+    kind: VertexKind<'a>,
+    position: Vec3,
+    /// Unit surface normal. None for the camera and for hits in participating media, which have no cosine terms.
+    normal: Option<Vec3>,
+    /// Product of the scattering colors divided by the sampling densities along the subpath up to this vertex, including the weights of Russian roulette
+    beta: Color,
+    /// Density of sampling this vertex from the previous one of its subpath, per unit area
+    pdf_forward: Float,
+    /// Density of sampling this vertex from the next one of its subpath, if the subpath was traced in the opposite direction, per unit area
+    pdf_reverse: Float,
+    /// Whether the vertex scattered specularly, in a direction no other strategy can sample
+    delta: bool,
+}
+
+impl<'a> Vertex<'a> {
+    fn camera(position: Vec3) -> Vertex<'a> {
+        Vertex {
+            kind: VertexKind::Camera,
+            position,
+            normal: None,
+            beta: Color::new(1.0, 1.0, 1.0),
+            pdf_forward: 1.0,
+            pdf_reverse: 0.0,
+            delta: false,
+        }
+    }
+
+    /// Returns the radiance emitted by a light vertex, black for other vertices
+    fn emission(&self) -> Color {
+        match self.kind {
+            VertexKind::Light { emission } => emission,
+            _ => Color::new(0.0, 0.0, 0.0),
+        }
+    }
+
+    /// Returns the light emitted by a hit on an emissive object towards the origin of its ray
+    fn emitted(&self) -> Color {
+        match &self.kind {
+            VertexKind::Surface {
+                ray, hit_record, ..
+            } => hit_record.material.emit(
+                ray,
+                hit_record,
+                hit_record.u,
+                hit_record.v,
+                hit_record.position,
+            ),
+            _ => Color::new(0.0, 0.0, 0.0),
+        }
+    }
+
+    /// Whether the vertex can be connected to a vertex of the other subpath
+    fn connectible(&self) -> bool {
+        match self.kind {
+            VertexKind::Camera | VertexKind::Light { .. } => true,
+            VertexKind::Surface { attenuation, .. } => attenuation.is_some(),
+        }
+    }
+
+    /// Returns the scattering color towards the given point, including the cosine term: for hits, the light arriving from the point and scattered along the subpath; for light vertices, the radiance emitted towards the point.
+    fn scattering(&self, point: Vec3, rng: &mut RandomGenerator) -> Color {
+        let direction = (point - self.position).normalize();
+        match &self.kind {
+            VertexKind::Camera => Color::new(0.0, 0.0, 0.0),
+            VertexKind::Light { emission } => {
+                let cosine = self.normal.map_or(1.0, |normal| direction.dot(&normal));
+                if cosine > 0.0 {
+                    *emission * cosine
+                } else {
+                    Color::new(0.0, 0.0, 0.0)
+                }
+            }
+            VertexKind::Surface {
+                ray,
+                hit_record,
+                attenuation,
+            } => match attenuation {
+                Some(attenuation) => {
+                    let scattered = Ray::new(self.position, direction, ray.time)
+                        .with_wavelength(ray.wavelength);
+                    hit_record.material.scattering_color(
+                        ray,
+                        hit_record,
+                        *attenuation,
+                        &scattered,
+                        rng,
+                    )
+                }
+                None => Color::new(0.0, 0.0, 0.0),
+            },
+        }
+    }
+
+    /// Returns the density of sampling the `next` vertex from this one, per unit area, for a subpath arriving from the `previous` vertex. Light vertices have no previous vertex, and the density is that of their cosine weighted emission.
+    fn pdf(
+        &self,
+        camera: &Camera,
+        previous: Option<&Vertex>,
+        next: &Vertex,
+        rng: &mut RandomGenerator,
+    ) -> Float {
+        let direction = next.position - self.position;
+        let pdf = match &self.kind {
+            VertexKind::Camera => camera.pdf_direction(direction),
+            VertexKind::Light { .. } => self.pdf_emission(direction),
+            VertexKind::Surface {
+                ray, hit_record, ..
+            } => {
+                if !self.connectible() {
+                    return 0.0;
+                }
+                let incoming = match previous {
+                    Some(previous) => self.position - previous.position,
+                    None => ray.direction,
+                };
+                scattering_pdf(hit_record, ray, incoming, direction, rng)
+            }
+        };
+        density_to_area(pdf, self.position, next)
+    }
+
+    /// Density of cosine weighted emission in the given direction from the surface of the vertex, per unit solid angle
+    fn pdf_emission(&self, direction: Vec3) -> Float {
+        let cosine = match self.normal {
+            Some(normal) => direction.normalize().dot(&normal),
+            None => 1.0,
+        };
+        (cosine / PI).max(0.0)
+    }
+}
+
+/// Converts the density of sampling a direction from the origin towards the vertex, per unit solid angle, to the density of sampling the vertex, per unit area
+fn density_to_area(pdf: Float, origin: Vec3, vertex: &Vertex) -> Float {
+    area_density(pdf, origin, vertex.position, vertex.normal)
+}
+
+/// Converts the density of sampling a direction from the origin towards the point, per unit solid angle, to the density of sampling the point on a surface with the given normal, per unit area
+fn area_density(pdf: Float, origin: Vec3, position: Vec3, normal: Option<Vec3>) -> Float {
+    let direction = position - origin;
+    let distance_squared = direction.norm_squared();
+    if distance_squared <= 0.0 {
+        return 0.0;
+    }
+    let cosine = match normal {
+        Some(normal) => (direction.dot(&normal) / distance_squared.sqrt()).abs(),
+        None => 1.0,
+    };
+    pdf * cosine / distance_squared
+}
+
+/// Returns the density of the material at the hit scattering light arriving in the `incoming` direction towards the `outgoing` direction, per unit solid angle. Zero for specular materials.
+fn scattering_pdf(
+    hit_record: &HitRecord,
+    ray: &Ray,
+    incoming: Vec3,
+    outgoing: Vec3,
+    rng: &mut RandomGenerator,
+) -> Float {
+    let incoming_ray = Ray::new(hit_record.position - incoming, incoming, ray.time)
+        .with_wavelength(ray.wavelength);
+    match hit_record.material.scatter(&incoming_ray, hit_record, rng) {
+        Some(scatter_record) => match scatter_record.material_type {
+            MaterialType::Diffuse => scatter_record.pdf_ptr.value(outgoing, ray.time, rng),
+            MaterialType::Specular => 0.0,
+        },
+        None => 0.0,
+    }
+}
+
+/// Returns the unit normal of a hit for the cosine terms, or None for hits in participating media
+fn surface_normal(hit_record: &HitRecord) -> Option<Vec3> {
+    match hit_record.material {
+        Material::Isotropic(_) | Material::HenyeyGreenstein(_) => None,
+        _ => Some(hit_record.normal.normalize()),
+    }
+}
+
+/// Extends the subpath with a random walk, starting with the ray sampled from its last vertex with the given density per unit solid angle. `beta` is the weight of the light carried along the ray. The walk ends when the subpath has `max_vertices` vertices, when the ray escapes or hits an emitter, or with Russian roulette. Returns the light arriving from the environment along the ray that escaped the scene, weighted by the subpath: black if the walk ended at a hit.
+fn random_walk<'a>(
+    scene: &'a Scene,
+    mut ray: Ray,
+    mut beta: Color,
+    mut pdf: Float,
+    max_vertices: usize,
+    path: &mut Vec<Vertex<'a>>,
+    rng: &mut RandomGenerator,
+) -> Color {
+    // Product of the scattering colors divided by the sampling densities along the walk, for Russian roulette
+    let mut throughput = Color::new(1.0, 1.0, 1.0);
+    let mut depth = 0;
+
+    while path.len() < max_vertices {
+        let hit_record = match scene
+            .objects
+            .hit(&ray, EPSILON_SHADOW_ACNE, Float::MAX, rng)
+        {
+            None => return beta * scene.environment.color(ray.direction),
+            Some(hit_record) => hit_record,
+        };
+        let position = hit_record.position;
+        let normal = surface_normal(&hit_record);
+        let previous = match path.last_mut() {
+            Some(previous) => previous,
+            None => return Color::new(0.0, 0.0, 0.0),
+        };
+        let pdf_forward = area_density(pdf, previous.position, position, normal);
+
+        let (scattered, attenuation, delta) =
+            match hit_record.material.scatter(&ray, &hit_record, rng) {
+                // No scatter: an emitter, or an absorbing material
+                None => (None, None, false),
+                Some(scatter_record) => match scatter_record.material_type {
+                    MaterialType::Specular => {
+                        previous.pdf_reverse = 0.0;
+                        pdf = 0.0;
+                        (
+                            scatter_record
+                                .specular_ray
+                                .map(|specular_ray| (specular_ray, scatter_record.attenuation)),
+                            None,
+                            true,
+                        )
+                    }
+                    MaterialType::Diffuse => {
+                        let material_pdf = scatter_record.pdf_ptr;
+                        let scattered = Ray::new(position, material_pdf.generate(rng), ray.time)
+                            .with_wavelength(ray.wavelength);
+                        pdf = material_pdf.value(scattered.direction, ray.time, rng);
+                        // The density of the reverse walk arriving along the scattered ray and leaving towards the previous vertex
+                        let pdf_reverse = scattering_pdf(
+                            &hit_record,
+                            &ray,
+                            -scattered.direction,
+                            -ray.direction,
+                            rng,
+                        );
+                        previous.pdf_reverse = density_to_area(pdf_reverse, position, previous);
+                        let scattering = hit_record.material.scattering_color(
+                            &ray,
+                            &hit_record,
+                            scatter_record.attenuation,
+                            &scattered,
+                            rng,
+                        ) / pdf;
+                        (
+                            Some((scattered, scattering)),
+                            Some(scatter_record.attenuation),
+                            false,
+                        )
+                    }
+                },
+            };
+        path.push(Vertex {
+            kind: VertexKind::Surface {
+                ray,
+                hit_record,
+                attenuation,
+            },
+            position,
+            normal,
+            beta,
+            pdf_forward,
+            pdf_reverse: 0.0,
+            delta,
+        });
+        let (next_ray, scattering) = match scattered {
+            Some(scattered) if delta || pdf > 0.0 => scattered,
+            _ => break,
+        };
+
+        depth += 1;
+        beta = beta * scattering;
+        throughput = throughput * scattering;
+        if let Some(survival) = survival_probability(scene, depth, throughput) {
+            if rng.gen::<Float>() >= survival {
+                break;
+            }
+            beta /= survival;
+        }
+        ray = next_ray;
+    }
+
+    Color::new(0.0, 0.0, 0.0)
+}
+
+/// Number of points probed on each of the `priority_objects` when looking for the [Emitters] of the scene
+const EMITTER_PROBES: usize = 16;
+
+/// An emissive object of the scene, for starting light subpaths
+struct Emitter {
+    object: Arc<Hitable>,
+    area: Float,
+}
+
+/// The emissive objects among the `priority_objects` of the scene: objects that support [sampling their surface](Hitable::sample_surface), where the objects of the scene emit light. Like in [colorize()](crate::colorize::colorize), the priority objects only guide the sampling: the emitted light is found by probing the objects of the scene at the sampled points. Found once when the [Scene] is built.
+#[derive(Default)]
+pub struct Emitters {
+    emitters: Vec<Emitter>,
+}
+
+impl Emitters {
+    /// Finds the emissive objects among the priority objects, by probing the objects of the scene at a few points sampled on each of them at the given time. An object is an emitter if any of the points emits light.
+    pub fn new(
+        objects: &Hitable,
+        priority_objects: &Hitable,
+        time: Float,
+        rng: &mut RandomGenerator,
+    ) -> Emitters {
+        let mut emitters = Emitters::default();
+        if let Hitable::HitableList(list) = priority_objects {
+            // Only the time of the ray is used by the probes
+            let ray = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0), time);
+            for object in list.0.iter() {
+                emitters.collect(objects, object, &ray, rng);
+            }
+        }
+        emitters
+    }
+
+    fn collect(
+        &mut self,
+        objects: &Hitable,
+        hitable: &Arc<Hitable>,
+        ray: &Ray,
+        rng: &mut RandomGenerator,
+    ) {
+        if let Hitable::HitableList(list) = hitable.as_ref() {
+            for object in list.0.iter() {
+                self.collect(objects, object, ray, rng);
+            }
+            return;
+        }
+        for _ in 0..EMITTER_PROBES {
+            let sample = match hitable.sample_surface(rng) {
+                Some(sample) => sample,
+                None => return,
+            };
+            if probe_emission(objects, &sample, ray, rng).is_some() {
+                self.emitters.push(Emitter {
+                    object: Arc::clone(hitable),
+                    area: sample.area,
+                });
+                return;
+            }
+        }
+    }
+
+    /// Samples a point uniformly on a randomly chosen emitter. Returns the light vertex, with the normal of its emitting side, or None if there are no emitters or the point does not emit light. The `beta` of the vertex is the inverse of its density.
+    fn sample<'a>(
+        &self,
+        objects: &'a Hitable,
+        ray: &Ray,
+        rng: &mut RandomGenerator,
+    ) -> Option<Vertex<'a>> {
+        if self.emitters.is_empty() {
+            return None;
+        }
+        let emitter = &self.emitters[rng.gen_range(0, self.emitters.len())];
+        let sample = emitter.object.sample_surface(rng)?;
+        let pdf = 1.0 / (self.emitters.len() as Float * emitter.area);
+        let (normal, emission) = probe_emission(objects, &sample, ray, rng)?;
+        Some(Vertex {
+            kind: VertexKind::Light { emission },
+            position: sample.position,
+            normal: Some(normal),
+            beta: Color::new(1.0, 1.0, 1.0) / pdf,
+            pdf_forward: pdf,
+            pdf_reverse: 0.0,
+            delta: false,
+        })
+    }
+
+    /// Returns the density of [sample()](Emitters::sample) choosing the hit of the vertex, per unit area. Zero if the hit is not on an emitter.
+    fn pdf(&self, vertex: &Vertex, rng: &mut RandomGenerator) -> Float {
+        let (ray, hit_record) = match &vertex.kind {
+            VertexKind::Surface {
+                ray, hit_record, ..
+            } => (ray, hit_record),
+            _ => return 0.0,
+        };
+        for emitter in self.emitters.iter() {
+            if let Some(emitter_hit) = emitter
+                .object
+                .hit(ray, EPSILON_SHADOW_ACNE, Float::MAX, rng)
+            {
+                if (emitter_hit.distance - hit_record.distance).abs()
+                    <= EPSILON_SHADOW_ACNE * hit_record.distance.max(1.0)
+                {
+                    return 1.0 / (self.emitters.len() as Float * emitter.area);
+                }
+            }
+        }
+        0.0
+    }
+}
+
+/// Finds the light emitted by the objects of the scene at a point sampled on a priority object, trying both sides of the surface. The probes have the time and wavelength of the given ray. Returns the normal of the emitting side and the emitted radiance, or None if the point does not emit light.
+fn probe_emission(
+    objects: &Hitable,
+    sample: &SurfaceSample,
+    ray: &Ray,
+    rng: &mut RandomGenerator,
+) -> Option<(Vec3, Color)> {
+    for &normal in [sample.normal, -sample.normal].iter() {
+        let probe = Ray::new(
+            sample.position + EPSILON_SHADOW_ACNE * normal,
+            -normal,
+            ray.time,
+        )
+        .with_wavelength(ray.wavelength);
+        if let Some(hit_record) = objects.hit(&probe, 0.0, 2.0 * EPSILON_SHADOW_ACNE, rng) {
+            let emission = hit_record.material.emit(
+                &probe,
+                &hit_record,
+                hit_record.u,
+                hit_record.v,
+                hit_record.position,
+            );
+            if emission.luminance() > 0.0 {
+                return Some((normal, emission));
+            }
+        }
+    }
+    None
+}
+
+/// Returns the fraction of light passing along the segment between the points, see [Hitable::transmittance()]
+fn transmittance(
+    scene: &Scene,
+    from: Vec3,
+    to: Vec3,
+    ray: &Ray,
+    rng: &mut RandomGenerator,
+) -> Float {
+    let direction = to - from;
+    let distance = direction.norm();
+    let shadow_ray = Ray::new(from, direction / distance, ray.time).with_wavelength(ray.wavelength);
+    scene.objects.transmittance(
+        &shadow_ray,
+        EPSILON_SHADOW_ACNE,
+        distance - EPSILON_SHADOW_ACNE,
+        rng,
+    )
+}
+
+/// The densities of a vertex, for the multiple importance sampling weights
+#[derive(Copy, Clone)]
+struct Densities {
+    forward: Float,
+    reverse: Float,
+    delta: bool,
+}
+
+impl Densities {
+    fn of(vertex: &Vertex) -> Densities {
+        Densities {
+            forward: vertex.pdf_forward,
+            reverse: vertex.pdf_reverse,
+            delta: vertex.delta,
+        }
+    }
+}
+
+/// The strategy connecting the first `s` vertices of the light subpath to the first `t` vertices of the camera subpath
+struct Connection<'a, 'b> {
+    camera_path: &'b [Vertex<'a>],
+    light_path: &'b [Vertex<'a>],
+    s: usize,
+    t: usize,
+    /// Length limit of the camera subpaths: longer paths cannot be sampled by the camera subpath alone
+    max_camera_vertices: usize,
+}
+
+impl<'a, 'b> Connection<'a, 'b> {
+    /// Returns the light found with the strategy, for strategies with at least two camera vertices. With no light vertices, the camera subpath itself hit an emitter. With one light vertex, a new point is sampled on the emitters.
+    fn radiance(
+        &self,
+        scene: &Scene,
+        emitters: &Emitters,
+        ray: &Ray,
+        rng: &mut RandomGenerator,
+    ) -> Color {
+        let black = Color::new(0.0, 0.0, 0.0);
+        let pt = &self.camera_path[self.t - 1];
+        if self.s == 0 {
+            let emitted = pt.emitted();
+            if emitted.luminance() <= 0.0 {
+                return black;
+            }
+            return pt.beta * emitted * self.weight(scene, emitters, pt, None, rng);
+        }
+
+        if !pt.connectible() {
+            return black;
+        }
+        let sampled;
+        let qs = if self.s == 1 {
+            sampled = match emitters.sample(&scene.objects, ray, rng) {
+                Some(vertex) => vertex,
+                None => return black,
+            };
+            &sampled
+        } else {
+            &self.light_path[self.s - 1]
+        };
+        if !qs.connectible() {
+            return black;
+        }
+        let distance_squared = (qs.position - pt.position).norm_squared();
+        let color =
+            qs.beta * qs.scattering(pt.position, rng) * pt.scattering(qs.position, rng) * pt.beta
+                / distance_squared;
+        if color.luminance() <= 0.0 {
+            return black;
+        }
+        let transmittance = transmittance(scene, pt.position, qs.position, ray, rng);
+        if transmittance <= 0.0 {
+            return black;
+        }
+        color * transmittance * self.weight(scene, emitters, pt, Some(qs), rng)
+    }
+
+    /// Returns the light found by connecting a light subpath vertex to a new point sampled on the lens, for strategies with one camera vertex
+    fn splat(
+        &self,
+        scene: &Scene,
+        emitters: &Emitters,
+        ray: &Ray,
+        rng: &mut RandomGenerator,
+    ) -> Option<Splat> {
+        let camera = &scene.camera;
+        let qs = &self.light_path[self.s - 1];
+        if !qs.connectible() {
+            return None;
+        }
+        let lens_point = camera.sample_lens(rng);
+        let (u, v) = camera.project(lens_point, qs.position)?;
+        let pt = Vertex::camera(lens_point);
+        let direction = qs.position - lens_point;
+        let distance_squared = direction.norm_squared();
+        let cosine = -direction.normalize().dot(&camera.w);
+        // The importance of the camera: the inverse of the density of the ray directions, converted to the area at the light subpath vertex
+        let importance = 1.0 / (camera.image_area() * cosine * cosine * cosine * distance_squared);
+        let color = qs.beta * qs.scattering(lens_point, rng) * importance;
+        if color.luminance() <= 0.0 {
+            return None;
+        }
+        let transmittance = transmittance(scene, qs.position, lens_point, ray, rng);
+        if transmittance <= 0.0 {
+            return None;
+        }
+        Some(Splat {
+            u,
+            v,
+            color: color * transmittance * self.weight(scene, emitters, &pt, Some(qs), rng),
+        })
+    }
+
+    /// Returns the multiple importance sampling weight of the strategy with the power heuristic, comparing its density to the densities of all the other strategies that could have sampled the same path. `pt` and `qs` are the last vertices of the camera and light subpaths, which may be newly sampled instead of the ones in the subpaths.
+    fn weight(
+        &self,
+        scene: &Scene,
+        emitters: &Emitters,
+        pt: &Vertex,
+        qs: Option<&Vertex>,
+        rng: &mut RandomGenerator,
+    ) -> Float {
+        let (s, t) = (self.s, self.t);
+        let camera = &scene.camera;
+        let pt_minus = if t > 1 {
+            Some(&self.camera_path[t - 2])
+        } else {
+            None
+        };
+        let qs_minus = if s > 1 {
+            Some(&self.light_path[s - 2])
+        } else {
+            None
+        };
+
+        let mut camera_densities: Vec<Densities> =
+            self.camera_path[..t].iter().map(Densities::of).collect();
+        let mut light_densities: Vec<Densities> =
+            self.light_path[..s].iter().map(Densities::of).collect();
+
+        // The connected vertices and their neighbors get the densities of sampling them from the other subpath
+        camera_densities[t - 1] = Densities::of(pt);
+        camera_densities[t - 1].delta = false;
+        camera_densities[t - 1].reverse = match qs {
+            Some(qs) => qs.pdf(camera, qs_minus, pt, rng),
+            None => emitters.pdf(pt, rng),
+        };
+        if camera_densities[t - 1].reverse <= 0.0 && qs.is_none() {
+            // Emitters that light subpaths cannot start from
+            return 1.0;
+        }
+        if let Some(pt_minus) = pt_minus {
+            camera_densities[t - 2].reverse = match qs {
+                Some(qs) => pt.pdf(camera, Some(qs), pt_minus, rng),
+                None => density_to_area(
+                    pt.pdf_emission(pt_minus.position - pt.position),
+                    pt.position,
+                    pt_minus,
+                ),
+            };
+        }
+        if let Some(qs) = qs {
+            light_densities[s - 1] = Densities::of(qs);
+            light_densities[s - 1].delta = false;
+            light_densities[s - 1].reverse = pt.pdf(camera, pt_minus, qs, rng);
+            if let Some(qs_minus) = qs_minus {
+                light_densities[s - 2].reverse = qs.pdf(camera, Some(pt), qs_minus, rng);
+            }
+        }
+
+        // Delta vertices have zero densities, which cancel out in the ratios
+        let remap = |pdf: Float| if pdf == 0.0 { 1.0 } else { pdf };
+        let mut sum = 0.0;
+        let mut ratio = 1.0;
+        for i in (1..t).rev() {
+            ratio *= remap(camera_densities[i].reverse) / remap(camera_densities[i].forward);
+            if !camera_densities[i].delta && !camera_densities[i - 1].delta {
+                sum += ratio * ratio;
+            }
+        }
+        ratio = 1.0;
+        for i in (0..s).rev() {
+            ratio *= remap(light_densities[i].reverse) / remap(light_densities[i].forward);
+            let delta_before = i > 0 && light_densities[i - 1].delta;
+            let possible = i > 0 || s + t <= self.max_camera_vertices;
+            if !light_densities[i].delta && !delta_before && possible {
+                sum += ratio * ratio;
+            }
+        }
+        1.0 / (1.0 + sum)
+    }
+}
@@ -1,33 +1,67 @@
 //! Bounding Volume Hierarchy Node.
 
-use std::{cmp::Ordering, sync::Arc};
+use std::{cmp::Ordering, fmt, sync::Arc};
 
 use rand::prelude::*;
+use serde::{Deserialize, Serialize};
 
 use crate::{
     aabb::AABB,
-    hitable::{HitRecord, Hitable},
+    hitable::{HitRecord, Hitable, HitableList},
     ray::Ray,
-    Float,
+    Float, RandomGenerator, Vec3,
 };
 
+/// The method used for splitting the objects into two halves when building a [BVHNode] tree
+#[derive(Copy, Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+pub enum SplitMethod {
+    /// Sort the objects along a random axis and split at the median. Fast to build, but can produce poor trees.
+    #[default]
+    Median,
+    /// Binned [Surface Area Heuristic](https://www.pbr-book.org/3ed-2018/Primitives_and_Intersection_Acceleration/Bounding_Volume_Hierarchies#TheSurfaceAreaHeuristic): pick the split that minimizes the expected cost of intersecting a ray with the tree. Small groups of objects are kept together in leaves.
+    Sah,
+}
+
+/// Number of bins per axis used for evaluating the split candidates in the SAH builder
+const SAH_BINS: usize = 12;
+/// Estimated cost of testing a ray against a node bounding box, relative to [SAH_INTERSECTION_COST]
+pub(crate) const SAH_TRAVERSAL_COST: Float = 0.125;
+/// Estimated cost of intersecting a ray with a single object
+pub(crate) const SAH_INTERSECTION_COST: Float = 1.0;
+/// Maximum number of objects in a leaf of the SAH builder
+const SAH_MAX_LEAF_SIZE: usize = 4;
+
 /// Bounding Volume Hierarchy Node. A node in a tree structure defining a hierarchy of objects in a scene: a node knows its bounding box, and has two children which are also BVHNodes. This is used for accelerating the ray-object intersection calculation in the ray tracer. See [Bounding Volume hierarchies](https://raytracing.github.io/books/RayTracingTheNextWeek.html)
 pub struct BVHNode {
-    left: Arc<Hitable>,
-    right: Arc<Hitable>,
-    bounding_box: AABB,
+    pub(crate) left: Arc<Hitable>,
+    pub(crate) right: Arc<Hitable>,
+    pub(crate) bounding_box: AABB,
 }
 
 impl BVHNode {
-    /// Create a new BVHNode tree from a given list of [Objects](crate::objects::Object)
+    /// Create a new BVHNode tree from a given list of [Objects](crate::objects::Object), using the given [SplitMethod]
+    pub fn new(
+        objects: Vec<Arc<Hitable>>,
+        time_0: Float,
+        time_1: Float,
+        split_method: SplitMethod,
+        rng: &mut RandomGenerator,
+    ) -> BVHNode {
+        match split_method {
+            SplitMethod::Median => BVHNode::from_list(objects, time_0, time_1, rng),
+            SplitMethod::Sah => BVHNode::from_list_sah(objects, time_0, time_1),
+        }
+    }
+
+    /// Create a new BVHNode tree from a given list of [Objects](crate::objects::Object), splitting at the median along a random axis
     pub fn from_list(
         mut objects: Vec<Arc<Hitable>>,
         time_0: Float,
         time_1: Float,
-        mut rng: ThreadRng,
+        rng: &mut RandomGenerator,
     ) -> BVHNode {
         {
-            let axis: usize = rng.gen_range(0, 2);
+            let axis: usize = rng.gen_range(0, 3);
             let comparators = [box_x_compare, box_y_compare, box_z_compare];
             let comparator = comparators[axis];
 
@@ -96,7 +130,7 @@ impl BVHNode {
         ray: &Ray,
         distance_min: Float,
         distance_max: Float,
-        rng: ThreadRng,
+        rng: &mut RandomGenerator,
     ) -> Option<HitRecord> {
         match self.bounding_box.hit(&ray, distance_min, distance_max) {
             false => None,
@@ -129,9 +163,264 @@ impl BVHNode {
             }
         }
     }
+
+    /// Returns the product of the transmittances of the children, see [Hitable::transmittance()]
+    pub fn transmittance(
+        &self,
+        ray: &Ray,
+        distance_min: Float,
+        distance_max: Float,
+        rng: &mut RandomGenerator,
+    ) -> Float {
+        if !self.bounding_box.hit(ray, distance_min, distance_max) {
+            return 1.0;
+        }
+        let left = self
+            .left
+            .transmittance(ray, distance_min, distance_max, rng);
+        if left <= 0.0 || Arc::ptr_eq(&self.left, &self.right) {
+            return left;
+        }
+        left * self
+            .right
+            .transmittance(ray, distance_min, distance_max, rng)
+    }
+
     pub fn bounding_box(&self, _t0: Float, _t11: Float) -> Option<AABB> {
         Some(self.bounding_box)
     }
+
+    /// Create a new BVHNode tree from a given list of [Objects](crate::objects::Object), using the binned [Surface Area Heuristic](SplitMethod::Sah)
+    pub fn from_list_sah(objects: Vec<Arc<Hitable>>, time_0: Float, time_1: Float) -> BVHNode {
+        if objects.is_empty() {
+            panic!("Cannot create a BVHNode with no objects");
+        }
+        let items: Vec<BuildItem> = objects
+            .into_iter()
+            .map(|object| BuildItem::new(object, time_0, time_1))
+            .collect();
+        let bounding_box = surrounding_box(&items);
+        let split = find_sah_split(&items, &bounding_box);
+        sah_node(items, bounding_box, split)
+    }
+}
+
+/// Statistics about the quality of a bounding volume hierarchy, see [FlatBVH::stats()](crate::flatbvh::FlatBVH::stats)
+#[derive(Clone, Copy, Debug)]
+pub struct BVHStats {
+    /// Number of interior nodes
+    pub nodes: usize,
+    /// Number of leaf nodes
+    pub leaves: usize,
+    /// Total number of objects in the leaves
+    pub objects: usize,
+    /// Maximum depth of the tree, counted in nodes including the leaves
+    pub depth: usize,
+    /// Number of objects in the smallest leaf
+    pub min_leaf_size: usize,
+    /// Number of objects in the largest leaf
+    pub max_leaf_size: usize,
+    /// Expected cost of intersecting a ray with the tree according to the Surface Area Heuristic, in units of object intersections. Lower is better.
+    pub sah_cost: Float,
+}
+
+impl BVHStats {
+    /// Average number of objects per leaf
+    pub fn mean_leaf_size(&self) -> Float {
+        self.objects as Float / self.leaves as Float
+    }
+}
+
+impl fmt::Display for BVHStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "bvh nodes:    {}", self.nodes)?;
+        writeln!(f, "bvh leaves:   {}", self.leaves)?;
+        writeln!(f, "bvh depth:    {}", self.depth)?;
+        writeln!(
+            f,
+            "leaf sizes:   min {}, max {}, mean {:.2}",
+            self.min_leaf_size,
+            self.max_leaf_size,
+            self.mean_leaf_size()
+        )?;
+        write!(f, "sah cost:     {:.3}", self.sah_cost)
+    }
+}
+
+/// An object with its bounding box precomputed, for the SAH builder
+struct BuildItem {
+    object: Arc<Hitable>,
+    bounding_box: AABB,
+    centroid: Vec3,
+}
+
+impl BuildItem {
+    fn new(object: Arc<Hitable>, time_0: Float, time_1: Float) -> BuildItem {
+        let bounding_box = match object.bounding_box(time_0, time_1) {
+            Some(bounding_box) => bounding_box,
+            None => panic!("No bounding box in BVHNode constructor."),
+        };
+        BuildItem {
+            object,
+            bounding_box,
+            centroid: bounding_box.centroid(),
+        }
+    }
+}
+
+/// A candidate split plane for the SAH builder
+#[derive(Clone, Copy)]
+struct Split {
+    axis: usize,
+    /// Objects with their centroid in bins below this index go to the left child
+    bin: usize,
+    /// Minimum value of the centroids along the axis
+    offset: Float,
+    /// Size of the centroid bounds along the axis
+    extent: Float,
+    cost: Float,
+}
+
+impl Split {
+    fn is_left(&self, item: &BuildItem) -> bool {
+        bin_index(item.centroid[self.axis], self.offset, self.extent) < self.bin
+    }
+}
+
+fn bin_index(value: Float, offset: Float, extent: Float) -> usize {
+    let bin = ((value - offset) / extent * SAH_BINS as Float) as usize;
+    bin.min(SAH_BINS - 1)
+}
+
+fn surrounding_box(items: &[BuildItem]) -> AABB {
+    items
+        .iter()
+        .skip(1)
+        .fold(items[0].bounding_box, |bounding_box, item| {
+            AABB::surrounding_box(bounding_box, item.bounding_box)
+        })
+}
+
+/// Evaluates the SAH cost of the split planes between the bins along each axis, returning the cheapest one. Returns `None` if the objects cannot be split by their centroids.
+fn find_sah_split(items: &[BuildItem], bounding_box: &AABB) -> Option<Split> {
+    let area = bounding_box.surface_area();
+    let mut best: Option<Split> = None;
+
+    for axis in 0..3 {
+        let (offset, max) = items.iter().fold(
+            (Float::INFINITY, Float::NEG_INFINITY),
+            |(min, max), item| (min.min(item.centroid[axis]), max.max(item.centroid[axis])),
+        );
+        let extent = max - offset;
+        if extent <= 0.0 {
+            continue;
+        }
+
+        let mut counts = [0_usize; SAH_BINS];
+        let mut boxes: [Option<AABB>; SAH_BINS] = [None; SAH_BINS];
+        for item in items {
+            let bin = bin_index(item.centroid[axis], offset, extent);
+            counts[bin] += 1;
+            boxes[bin] = Some(match boxes[bin] {
+                Some(bounding_box) => AABB::surrounding_box(bounding_box, item.bounding_box),
+                None => item.bounding_box,
+            });
+        }
+
+        // Sweep from the right to get the area and count on the right side of each split plane
+        let mut right_areas = [0.0; SAH_BINS];
+        let mut right_counts = [0_usize; SAH_BINS];
+        let mut right_box: Option<AABB> = None;
+        let mut right_count = 0;
+        for bin in (1..SAH_BINS).rev() {
+            right_box = merge(right_box, boxes[bin]);
+            right_count += counts[bin];
+            right_areas[bin] = right_box.map_or(0.0, |bounding_box| bounding_box.surface_area());
+            right_counts[bin] = right_count;
+        }
+
+        // Sweep from the left, evaluating the cost of each split plane
+        let mut left_box: Option<AABB> = None;
+        let mut left_count = 0;
+        for bin in 1..SAH_BINS {
+            left_box = merge(left_box, boxes[bin - 1]);
+            left_count += counts[bin - 1];
+            if left_count == 0 || right_counts[bin] == 0 {
+                continue;
+            }
+            let left_area = left_box.map_or(0.0, |bounding_box| bounding_box.surface_area());
+            let cost = SAH_TRAVERSAL_COST
+                + SAH_INTERSECTION_COST
+                    * (left_area * left_count as Float
+                        + right_areas[bin] * right_counts[bin] as Float)
+                    / area;
+            if best.is_none_or(|split| cost < split.cost) {
+                best = Some(Split {
+                    axis,
+                    bin,
+                    offset,
+                    extent,
+                    cost,
+                });
+            }
+        }
+    }
+
+    best
+}
+
+fn merge(a: Option<AABB>, b: Option<AABB>) -> Option<AABB> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(AABB::surrounding_box(a, b)),
+        (a, None) => a,
+        (None, b) => b,
+    }
+}
+
+/// Builds a subtree for the SAH builder. Returns either a single object, a leaf with a [HitableList] of a few objects, or a new [BVHNode].
+fn sah_subtree(mut items: Vec<BuildItem>) -> Arc<Hitable> {
+    if items.len() == 1 {
+        return items.remove(0).object;
+    }
+
+    let bounding_box = surrounding_box(&items);
+    let split = find_sah_split(&items, &bounding_box);
+    let leaf_cost = SAH_INTERSECTION_COST * items.len() as Float;
+    let split_is_worse = split.is_none_or(|split| split.cost >= leaf_cost);
+    if items.len() <= SAH_MAX_LEAF_SIZE && split_is_worse {
+        let objects = items.into_iter().map(|item| item.object).collect();
+        return Arc::new(Hitable::HitableList(HitableList(objects)));
+    }
+
+    Arc::new(Hitable::BVHNode(sah_node(items, bounding_box, split)))
+}
+
+/// Builds a node for the SAH builder, splitting the objects with the given split plane. Without a split plane, the objects are split in half.
+fn sah_node(mut items: Vec<BuildItem>, bounding_box: AABB, split: Option<Split>) -> BVHNode {
+    if items.len() == 1 {
+        // If we only have one object, return itself. Note: no explicit leaf type in our tree
+        let object = items.remove(0).object;
+        return BVHNode {
+            left: object.clone(),
+            right: object,
+            bounding_box,
+        };
+    }
+
+    let (left_items, right_items): (Vec<BuildItem>, Vec<BuildItem>) = match split {
+        Some(split) => items.into_iter().partition(|item| split.is_left(item)),
+        None => {
+            // All centroids are in the same spot, no plane can separate them
+            let right_items = items.split_off(items.len() / 2);
+            (items, right_items)
+        }
+    };
+
+    BVHNode {
+        left: sah_subtree(left_items),
+        right: sah_subtree(right_items),
+        bounding_box,
+    }
 }
 
 fn box_compare(a: &Hitable, b: &Hitable, axis: usize) -> Ordering {
@@ -159,3 +448,65 @@ fn box_y_compare(a: &Hitable, b: &Hitable) -> Ordering {
 fn box_z_compare(a: &Hitable, b: &Hitable) -> Ordering {
     box_compare(a, b, 2)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{materials::Material, objects::Sphere};
+
+    fn random_spheres(count: usize, rng: &mut RandomGenerator) -> Vec<Arc<Hitable>> {
+        (0..count)
+            .map(|_| {
+                let center = Vec3::new(
+                    rng.gen_range(-10.0, 10.0),
+                    rng.gen_range(-10.0, 10.0),
+                    rng.gen_range(-10.0, 10.0),
+                );
+                let radius = rng.gen_range(0.1, 1.0);
+                Arc::new(Sphere::new(center, radius, Material::default()))
+            })
+            .collect()
+    }
+
+    fn random_ray(rng: &mut RandomGenerator) -> Ray {
+        let origin = Vec3::new(
+            rng.gen_range(-15.0, 15.0),
+            rng.gen_range(-15.0, 15.0),
+            rng.gen_range(-15.0, 15.0),
+        );
+        let target = Vec3::new(
+            rng.gen_range(-10.0, 10.0),
+            rng.gen_range(-10.0, 10.0),
+            rng.gen_range(-10.0, 10.0),
+        );
+        Ray::new(origin, target - origin, 0.0)
+    }
+
+    #[test]
+    fn sah_hit_agrees_with_median() {
+        let mut rng = RandomGenerator::seed_from_u64(0);
+        let objects = random_spheres(200, &mut rng);
+        let median = BVHNode::from_list(objects.clone(), 0.0, 1.0, &mut rng);
+        let sah = BVHNode::from_list_sah(objects, 0.0, 1.0);
+        let mut hits = 0;
+        for _ in 0..1000 {
+            let ray = random_ray(&mut rng);
+            let expected = median.hit(&ray, 0.001, Float::INFINITY, &mut rng);
+            let actual = sah.hit(&ray, 0.001, Float::INFINITY, &mut rng);
+            match (expected, actual) {
+                (Some(expected), Some(actual)) => {
+                    hits += 1;
+                    assert!((expected.distance - actual.distance).abs() < 1e-5);
+                    assert!((expected.position - actual.position).norm() < 1e-4);
+                }
+                (None, None) => {}
+                (expected, actual) => panic!(
+                    "median split hit {:?}, SAH hit {:?}",
+                    expected.map(|hit| hit.distance),
+                    actual.map(|hit| hit.distance)
+                ),
+            }
+        }
+        assert!(hits > 0);
+    }
+}
@@ -1,8 +1,9 @@
 //! Camera. Used for creating [Rays](crate::ray::Ray) towards the scene, with directions defined by the camera properties.
 
-use crate::{random::random_in_unit_disk, ray::Ray, Float, Vec3, PI};
+use crate::{random::random_in_unit_disk, ray::Ray, Float, RandomGenerator, Vec3, PI};
 use rand::prelude::*;
 use serde::{Deserialize, Serialize};
+use std::ops::{Add, Mul, Sub};
 
 #[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 pub struct Camera {
@@ -28,6 +29,71 @@ pub struct CameraInit {
     pub vertical_fov: Float,
     pub aperture: Float,
     pub focus_distance: Float,
+    /// Optional keyframes for animating the camera. Each property is interpolated linearly between the keyframes that set it, and holds its value before the first and after the last one. Properties without keyframes keep the values above. The camera is evaluated once per frame, at the opening time of the shutter, so camera animation changes only between frames and does not cause motion blur within a frame, unlike a [KeyframedTransform](crate::objects::KeyframedTransform).
+    #[serde(default)]
+    pub keyframes: Vec<CameraKeyframe>,
+}
+
+/// A keyframe for the animated properties of the camera. Properties left out are not keyed at this time.
+#[derive(Copy, Clone, Serialize, Deserialize, Debug)]
+pub struct CameraKeyframe {
+    pub time: Float,
+    #[serde(default)]
+    pub look_from: Option<Vec3>,
+    #[serde(default)]
+    pub look_at: Option<Vec3>,
+    #[serde(default)]
+    pub vertical_fov: Option<Float>,
+    #[serde(default)]
+    pub aperture: Option<Float>,
+}
+
+impl CameraInit {
+    /// Creates the camera, with the animated properties evaluated at the opening time of the shutter. The camera stays fixed for the whole shutter interval; the rays only carry their time for the motion blur of the objects.
+    pub fn build(&self, aspect_ratio: Float, time_0: Float, time_1: Float) -> Camera {
+        Camera::new(
+            self.track(time_0, self.look_from, |keyframe| keyframe.look_from),
+            self.track(time_0, self.look_at, |keyframe| keyframe.look_at),
+            self.up,
+            self.track(time_0, self.vertical_fov, |keyframe| keyframe.vertical_fov),
+            aspect_ratio,
+            self.track(time_0, self.aperture, |keyframe| keyframe.aperture),
+            self.focus_distance,
+            time_0,
+            time_1,
+        )
+    }
+
+    /// Evaluates the track of a single property at the given time
+    fn track<T>(&self, time: Float, base: T, value: impl Fn(&CameraKeyframe) -> Option<T>) -> T
+    where
+        T: Copy + Add<Output = T> + Sub<Output = T> + Mul<Float, Output = T>,
+    {
+        let mut keys: Vec<(Float, T)> = self
+            .keyframes
+            .iter()
+            .filter_map(|keyframe| value(keyframe).map(|value| (keyframe.time, value)))
+            .collect();
+        keys.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+        let (first, last) = match (keys.first(), keys.last()) {
+            (Some(&first), Some(&last)) => (first, last),
+            _ => return base,
+        };
+        if time <= first.0 {
+            return first.1;
+        }
+        if time >= last.0 {
+            return last.1;
+        }
+        // Index of the first key after the time; never the first one, due to the check above
+        let next = keys
+            .iter()
+            .position(|key| key.0 > time)
+            .unwrap_or(keys.len() - 1);
+        let (time_a, a) = keys[next - 1];
+        let (time_b, b) = keys[next];
+        a + (b - a) * ((time - time_a) / (time_b - time_a))
+    }
 }
 
 impl Camera {
@@ -74,9 +140,9 @@ impl Camera {
     }
 
     // TODO: fix the mysterious (u,v) vs (s,t) change that came from the tutorial
-    pub fn get_ray(self, s: Float, t: Float, mut rng: ThreadRng) -> Ray {
+    pub fn get_ray(self, s: Float, t: Float, rng: &mut RandomGenerator) -> Ray {
         // TODO: add a better defocus blur / depth of field implementation
-        let rd: Vec3 = self.lens_radius * random_in_unit_disk(&mut rng);
+        let rd: Vec3 = self.lens_radius * random_in_unit_disk(rng);
         let offset: Vec3 = self.u * rd.x + self.v * rd.y;
         // Randomized time used for motion blur
         let time: Float = rng.gen_range(self.time_0, self.time_1);
@@ -86,4 +152,48 @@ impl Camera {
             time,
         )
     }
+
+    /// Distance from the lens to the plane in focus, where the image of [get_ray()](Camera::get_ray) is spanned
+    fn focus_distance(&self) -> Float {
+        (self.origin - self.lower_left_corner).dot(&self.w)
+    }
+
+    /// Area of the image when projected on a plane at unit distance in front of the lens
+    pub fn image_area(&self) -> Float {
+        let focus_distance = self.focus_distance();
+        self.horizontal.norm() * self.vertical.norm() / (focus_distance * focus_distance)
+    }
+
+    /// Samples a point on the lens, like the origins of the rays of [get_ray()](Camera::get_ray)
+    pub fn sample_lens(&self, rng: &mut RandomGenerator) -> Vec3 {
+        let rd: Vec3 = self.lens_radius * random_in_unit_disk(rng);
+        self.origin + self.u * rd.x + self.v * rd.y
+    }
+
+    /// The inverse of [get_ray()](Camera::get_ray): returns the `(s, t)` image coordinates of the ray from the given point on the lens through the given point in the scene. Returns None if the point is behind the camera or outside the image.
+    pub fn project(&self, lens_point: Vec3, point: Vec3) -> Option<(Float, Float)> {
+        let direction = point - lens_point;
+        let forward = -direction.dot(&self.w);
+        if forward <= 0.0 {
+            return None;
+        }
+        let focus_point = lens_point + direction * (self.focus_distance() / forward);
+        let offset = focus_point - self.lower_left_corner;
+        let s = offset.dot(&self.horizontal) / self.horizontal.norm_squared();
+        let t = offset.dot(&self.vertical) / self.vertical.norm_squared();
+        if (0.0..1.0).contains(&s) && (0.0..1.0).contains(&t) {
+            Some((s, t))
+        } else {
+            None
+        }
+    }
+
+    /// Probability density of [get_ray()](Camera::get_ray) generating a ray in the given direction, per unit solid angle: `1 / (A * cos³θ)`, where `A` is the [image_area()](Camera::image_area) and `θ` is the angle from the viewing direction.
+    pub fn pdf_direction(&self, direction: Vec3) -> Float {
+        let cosine = -direction.normalize().dot(&self.w);
+        if cosine <= 0.0 {
+            return 0.0;
+        }
+        1.0 / (self.image_area() * cosine * cosine * cosine)
+    }
 }
@@ -0,0 +1,336 @@
+//! Checkpoints for resumable rendering: the accumulated state of a render in progress, which can be saved to a file and loaded again to continue rendering.
+
+use crate::{color::Color, Float};
+use std::{
+    fs::{self, File},
+    io::{BufReader, BufWriter, Error, ErrorKind, Read, Write},
+    mem::size_of,
+};
+
+/// Identifies a checkpoint file, followed by the format version
+const MAGIC: &[u8; 8] = b"CLOVERS\0";
+const VERSION: u32 = 4;
+/// Size of the header: the magic bytes, version, width, height, seed and fingerprint
+const HEADER_SIZE: u64 = 8 + 4 + 4 + 4 + 8 + 8;
+/// Size of the state of a single pixel: the sum, count, mean, squared deviations and splats
+const PIXEL_SIZE: u64 = 8 * size_of::<Float>() as u64 + 4;
+
+/// Luminance added to the mean when estimating the relative error of a pixel, so that nearly black pixels do not need an unbounded number of samples
+const ERROR_LUMINANCE_FLOOR: Float = 0.01;
+
+/// The accumulated state of a render in progress. For each pixel, holds the sum of all samples taken so far and the number of samples taken. The mean and variance of the luminance of the samples are tracked with [Welford's algorithm](https://en.wikipedia.org/wiki/Algorithms_for_calculating_variance#Welford's_online_algorithm), for estimating the remaining noise in each pixel.
+///
+/// The samples of each pixel are numbered, and each numbered sample gets its own random number stream derived from the seed, see [sample_rng()](crate::random::sample_rng). This makes a resumed render identical to one that was never interrupted.
+pub struct Checkpoint {
+    pub width: u32,
+    pub height: u32,
+    pub seed: u64,
+    /// Identifies the scene file and the render settings the samples were taken with, see [fingerprint()]
+    pub fingerprint: u64,
+    /// Sum of the samples of each pixel, in row-major order
+    pub sums: Vec<Color>,
+    /// Number of samples taken for each pixel, in row-major order
+    pub counts: Vec<u32>,
+    /// Running mean of the luminance of the samples of each pixel
+    pub means: Vec<Float>,
+    /// Running sum of squared differences from the mean of the luminance of each pixel
+    pub squared_deviations: Vec<Float>,
+    /// Sum of the [Splats](crate::bidirectional::Splat) landing on each pixel, from the light paths of the bidirectional path tracer. Every sample traces one light path. The splats are not included in the error estimates of the pixels.
+    pub splats: Vec<Color>,
+}
+
+impl Checkpoint {
+    /// Creates a new, empty checkpoint for a render of the given size, with the [fingerprint()] of its scene file and settings
+    pub fn new(width: u32, height: u32, seed: u64, fingerprint: u64) -> Checkpoint {
+        let pixels = width as usize * height as usize;
+        Checkpoint {
+            width,
+            height,
+            seed,
+            fingerprint,
+            sums: vec![Color::new(0.0, 0.0, 0.0); pixels],
+            counts: vec![0; pixels],
+            means: vec![0.0; pixels],
+            squared_deviations: vec![0.0; pixels],
+            splats: vec![Color::new(0.0, 0.0, 0.0); pixels],
+        }
+    }
+
+    /// Adds a sample to the given pixel. Samples that could not be computed, e.g. due to NaN values, are counted as black.
+    pub fn add_sample(&mut self, index: usize, sample: Option<Color>) {
+        let luminance = match sample {
+            Some(color) => {
+                self.sums[index] += color;
+                color.luminance()
+            }
+            None => 0.0,
+        };
+        self.counts[index] += 1;
+        let count = self.counts[index] as Float;
+        let delta = luminance - self.means[index];
+        self.means[index] += delta / count;
+        self.squared_deviations[index] += delta * (luminance - self.means[index]);
+    }
+
+    /// Adds the light of a splat to the given pixel
+    pub fn add_splat(&mut self, index: usize, color: Color) {
+        self.splats[index] += color;
+    }
+
+    /// Returns the estimated relative error of the given pixel: the standard error of the mean luminance, relative to the mean luminance. Pixels with less than two samples have an infinite error.
+    pub fn relative_error(&self, index: usize) -> Float {
+        let count = self.counts[index];
+        if count < 2 {
+            return Float::INFINITY;
+        }
+        let count = count as Float;
+        let variance = self.squared_deviations[index] / (count - 1.0);
+        let standard_error = (variance / count).sqrt();
+        standard_error / (self.means[index].max(0.0) + ERROR_LUMINANCE_FLOOR)
+    }
+
+    /// Returns the estimated variance of the mean luminance of each pixel, i.e. the squared standard error. Pixels with less than two samples have an infinite variance.
+    pub fn variances(&self) -> Vec<Float> {
+        self.counts
+            .iter()
+            .zip(self.squared_deviations.iter())
+            .map(|(&count, &squared_deviation)| {
+                if count < 2 {
+                    return Float::INFINITY;
+                }
+                let count = count as Float;
+                squared_deviation / (count - 1.0) / count
+            })
+            .collect()
+    }
+
+    /// Returns the average color of each pixel, plus the splats divided by the number of light paths per pixel
+    pub fn pixels(&self) -> Vec<Color> {
+        let total_samples = self.total_samples();
+        let splat_scale = if total_samples == 0 {
+            0.0
+        } else {
+            self.counts.len() as Float / total_samples as Float
+        };
+        self.sums
+            .iter()
+            .zip(self.counts.iter())
+            .zip(self.splats.iter())
+            .map(|((&sum, &count), &splat)| {
+                let average = if count == 0 {
+                    sum
+                } else {
+                    sum / count as Float
+                };
+                average + splat * splat_scale
+            })
+            .collect()
+    }
+
+    /// Returns the total number of samples taken over all pixels
+    pub fn total_samples(&self) -> u64 {
+        self.counts.iter().map(|&count| count as u64).sum()
+    }
+
+    /// Loads a checkpoint from a file written by [save()](Checkpoint::save). Returns an error if the checkpoint was saved with a different [fingerprint()], i.e. a different scene file or render settings, as continuing it would mix the samples of two different renders.
+    pub fn load(path: &str, fingerprint: u64) -> Result<Checkpoint, Error> {
+        let file = File::open(path)?;
+        let file_size = file.metadata()?.len();
+        let mut reader = BufReader::new(file);
+
+        let mut magic = [0_u8; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("Not a checkpoint file: {}", path),
+            ));
+        }
+        let version = read_u32(&mut reader)?;
+        if version != VERSION {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("Unsupported checkpoint version {}: {}", version, path),
+            ));
+        }
+
+        let width = read_u32(&mut reader)?;
+        let height = read_u32(&mut reader)?;
+        let seed = read_u64(&mut reader)?;
+        let saved_fingerprint = read_u64(&mut reader)?;
+        if saved_fingerprint != fingerprint {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "Checkpoint was saved with a different scene file or render settings: {}",
+                    path
+                ),
+            ));
+        }
+        // Check the size before allocating, so that a corrupt header cannot request huge buffers
+        let expected_size = (width as u64 * height as u64)
+            .checked_mul(PIXEL_SIZE)
+            .and_then(|size| size.checked_add(HEADER_SIZE));
+        if expected_size != Some(file_size) {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "Checkpoint size does not match its {}x{} pixels: {}",
+                    width, height, path
+                ),
+            ));
+        }
+        let mut checkpoint = Checkpoint::new(width, height, seed, fingerprint);
+        for index in 0..checkpoint.counts.len() {
+            let r = read_float(&mut reader)?;
+            let g = read_float(&mut reader)?;
+            let b = read_float(&mut reader)?;
+            checkpoint.sums[index] = Color::new(r, g, b);
+            checkpoint.counts[index] = read_u32(&mut reader)?;
+            checkpoint.means[index] = read_float(&mut reader)?;
+            checkpoint.squared_deviations[index] = read_float(&mut reader)?;
+            let r = read_float(&mut reader)?;
+            let g = read_float(&mut reader)?;
+            let b = read_float(&mut reader)?;
+            checkpoint.splats[index] = Color::new(r, g, b);
+        }
+
+        Ok(checkpoint)
+    }
+
+    /// Saves the checkpoint to a file. The file is first written under a temporary name and then renamed, so that an interrupted save never destroys the previous checkpoint.
+    pub fn save(&self, path: &str) -> Result<(), Error> {
+        let temporary_path = format!("{}.tmp", path);
+        {
+            let mut writer = BufWriter::new(File::create(&temporary_path)?);
+            writer.write_all(MAGIC)?;
+            writer.write_all(&VERSION.to_le_bytes())?;
+            writer.write_all(&self.width.to_le_bytes())?;
+            writer.write_all(&self.height.to_le_bytes())?;
+            writer.write_all(&self.seed.to_le_bytes())?;
+            writer.write_all(&self.fingerprint.to_le_bytes())?;
+            for index in 0..self.counts.len() {
+                let sum = self.sums[index];
+                writer.write_all(&sum.r.to_le_bytes())?;
+                writer.write_all(&sum.g.to_le_bytes())?;
+                writer.write_all(&sum.b.to_le_bytes())?;
+                writer.write_all(&self.counts[index].to_le_bytes())?;
+                writer.write_all(&self.means[index].to_le_bytes())?;
+                writer.write_all(&self.squared_deviations[index].to_le_bytes())?;
+                let splat = self.splats[index];
+                writer.write_all(&splat.r.to_le_bytes())?;
+                writer.write_all(&splat.g.to_le_bytes())?;
+                writer.write_all(&splat.b.to_le_bytes())?;
+            }
+            writer.flush()?;
+        }
+        fs::rename(&temporary_path, path)
+    }
+}
+
+/// Returns a fingerprint of the inputs of a render: the contents of the scene file, and a description of the settings that change the samples, e.g. the integrator and the maximum depth. Uses the [FNV-1a](http://www.isthe.com/chongo/tech/comp/fnv/) hash, which stays the same across builds and platforms.
+pub fn fingerprint(scene_file: &[u8], settings: &str) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    // The zero byte separates the scene file from the settings
+    for &byte in scene_file.iter().chain(&[0]).chain(settings.as_bytes()) {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    hash
+}
+
+fn read_u32(reader: &mut impl Read) -> Result<u32, Error> {
+    let mut bytes = [0_u8; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64(reader: &mut impl Read) -> Result<u64, Error> {
+    let mut bytes = [0_u8; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn read_float(reader: &mut impl Read) -> Result<Float, Error> {
+    let mut bytes = [0_u8; size_of::<Float>()];
+    reader.read_exact(&mut bytes)?;
+    Ok(Float::from_le_bytes(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FINGERPRINT: u64 = 42;
+
+    /// A small checkpoint with a few samples and splats, saved under a name unique to the test
+    fn saved_checkpoint(name: &str) -> (Checkpoint, String) {
+        let mut checkpoint = Checkpoint::new(3, 2, 7, FINGERPRINT);
+        checkpoint.add_sample(0, Some(Color::new(0.25, 0.5, 1.0)));
+        checkpoint.add_sample(0, Some(Color::new(1.0, 0.5, 0.25)));
+        checkpoint.add_sample(4, None);
+        checkpoint.add_splat(5, Color::new(0.1, 0.2, 0.3));
+        let path = std::env::temp_dir()
+            .join(format!("clovers_checkpoint_{}.bin", name))
+            .to_string_lossy()
+            .into_owned();
+        checkpoint.save(&path).unwrap();
+        (checkpoint, path)
+    }
+
+    fn assert_invalid_data(result: Result<Checkpoint, Error>) {
+        assert_eq!(result.err().unwrap().kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn round_trip() {
+        let (saved, path) = saved_checkpoint("round_trip");
+        let loaded = Checkpoint::load(&path, FINGERPRINT).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            (loaded.width, loaded.height, loaded.seed, loaded.fingerprint),
+            (saved.width, saved.height, saved.seed, saved.fingerprint)
+        );
+        assert_eq!(loaded.counts, saved.counts);
+        assert_eq!(loaded.means, saved.means);
+        assert_eq!(loaded.squared_deviations, saved.squared_deviations);
+        for (loaded, saved) in [(&loaded.sums, &saved.sums), (&loaded.splats, &saved.splats)] {
+            for (a, b) in loaded.iter().zip(saved.iter()) {
+                assert_eq!((a.r, a.g, a.b), (b.r, b.g, b.b));
+            }
+        }
+    }
+
+    #[test]
+    fn truncated_file_is_rejected() {
+        let (_, path) = saved_checkpoint("truncated");
+        let contents = fs::read(&path).unwrap();
+        // Cut inside the pixels, and inside the header
+        fs::write(&path, &contents[..contents.len() - 1]).unwrap();
+        assert_invalid_data(Checkpoint::load(&path, FINGERPRINT));
+        fs::write(&path, &contents[..HEADER_SIZE as usize - 4]).unwrap();
+        let error = Checkpoint::load(&path, FINGERPRINT).err().unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(error.kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn wrong_version_is_rejected() {
+        let (_, path) = saved_checkpoint("version");
+        let mut contents = fs::read(&path).unwrap();
+        contents[MAGIC.len()..MAGIC.len() + 4].copy_from_slice(&(VERSION - 1).to_le_bytes());
+        fs::write(&path, &contents).unwrap();
+        let result = Checkpoint::load(&path, FINGERPRINT);
+        fs::remove_file(&path).unwrap();
+        assert_invalid_data(result);
+    }
+
+    #[test]
+    fn wrong_fingerprint_is_rejected() {
+        let (_, path) = saved_checkpoint("fingerprint");
+        let result = Checkpoint::load(&path, FINGERPRINT + 1);
+        fs::remove_file(&path).unwrap();
+        assert_invalid_data(result);
+    }
+}
@@ -1,6 +1,6 @@
 //! Color utilities.
 
-use crate::{Float, Vec3};
+use crate::{Float, RandomGenerator, Vec3};
 use rand::prelude::*;
 use serde::{Deserialize, Serialize};
 use std::ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign};
@@ -29,7 +29,7 @@ impl Color {
         Color { r, g, b }
     }
 
-    pub fn random(mut rng: ThreadRng) -> Color {
+    pub fn random(rng: &mut RandomGenerator) -> Color {
         Color {
             r: rng.gen::<Float>(),
             g: rng.gen::<Float>(),
@@ -45,6 +45,11 @@ impl Color {
         }
     }
 
+    /// Returns the relative luminance of the color, using the Rec. 709 coefficients
+    pub fn luminance(&self) -> Float {
+        0.2126 * self.r + 0.7152 * self.g + 0.0722 * self.b
+    }
+
     // TODO: why did this misbehave when attempted as a mutable self?
     pub fn gamma_correction(&self, gamma: Float) -> Color {
         // Raise to the power of inverse of gamma number given
@@ -56,6 +61,38 @@ impl Color {
         }
     }
 
+    /// Encodes a linear color with the [sRGB transfer function](https://en.wikipedia.org/wiki/SRGB#Transfer_function_(%22gamma%22))
+    pub fn srgb_encode(&self) -> Color {
+        fn encode(value: Float) -> Float {
+            if value <= 0.0031308 {
+                12.92 * value
+            } else {
+                1.055 * value.powf(1.0 / 2.4) - 0.055
+            }
+        }
+        Color {
+            r: encode(self.r),
+            g: encode(self.g),
+            b: encode(self.b),
+        }
+    }
+
+    /// Decodes an sRGB encoded color into linear values, the inverse of [srgb_encode()](Color::srgb_encode)
+    pub fn srgb_decode(&self) -> Color {
+        fn decode(value: Float) -> Float {
+            if value <= 0.04045 {
+                value / 12.92
+            } else {
+                ((value + 0.055) / 1.055).powf(2.4)
+            }
+        }
+        Color {
+            r: decode(self.r),
+            g: decode(self.g),
+            b: decode(self.b),
+        }
+    }
+
     pub fn to_rgb_u8(&self) -> [u8; 3] {
         // TODO: might be possible to optimize
         let mut r = self.r;
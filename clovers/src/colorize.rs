@@ -1,85 +1,721 @@
 //! An opinionated colorize method. Given a [Ray](crate::ray::Ray) and a [Scene](crate::scenes::Scene), evaluates the ray's path and returns a color.
+//!
+//! At each diffuse bounce, the direct lighting is estimated with [next event estimation](https://www.pbr-book.org/3ed-2018/Light_Transport_I_Surface_Reflection/Direct_Lighting): a direction is sampled towards the light sources of the scene, and a shadow ray finds the light arriving from it. The path then continues in a direction sampled from the material. Both strategies can find the same light, so their contributions are weighted with the power heuristic of [multiple importance sampling](https://graphics.stanford.edu/courses/cs348b-03/papers/veach-chapter9.pdf): small bright lights are found with the light samples, and glossy reflections of large lights with the material samples.
+//!
+//! After a few bounces, paths are terminated with [Russian roulette](https://www.pbr-book.org/3ed-2018/Monte_Carlo_Integration/Russian_Roulette_and_Splitting), with a probability based on how much the path can still contribute to the image.
 
 use crate::{
     color::Color,
-    pdf::{HitablePDF, MixturePDF},
+    environment::Environment,
+    hitable::{HitRecord, Hitable},
+    materials::MaterialType,
+    pdf::{EnvironmentPDF, HitablePDF, MixturePDF, PDF},
     ray::Ray,
     scenes::Scene,
-    Float, EPSILON_SHADOW_ACNE,
+    Float, RandomGenerator, Vec3, EPSILON_SHADOW_ACNE,
 };
 use rand::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Highest survival probability of Russian roulette. Even bright paths get terminated occasionally, cutting off paths trapped between mirrors.
+const ROULETTE_MAX_SURVIVAL: Float = 0.95;
+
+/// Settings for terminating paths early with Russian roulette. Paths that can only contribute little to the image are terminated with a high probability, and the surviving paths are weighted up to keep the result unbiased.
+#[derive(Copy, Clone, Debug, Deserialize, Serialize)]
+pub struct RussianRoulette {
+    /// Whether paths are terminated with Russian roulette. Default value: true
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    /// Number of bounces before paths can be terminated. Default value: 3
+    #[serde(default = "default_min_depth")]
+    pub min_depth: u32,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+fn default_min_depth() -> u32 {
+    3
+}
+
+impl Default for RussianRoulette {
+    fn default() -> Self {
+        RussianRoulette {
+            enabled: default_enabled(),
+            min_depth: default_min_depth(),
+        }
+    }
+}
+
+/// Statistics about the lengths of the traced paths
+#[derive(Copy, Clone, Debug, Default)]
+pub struct PathStats {
+    /// Number of paths traced
+    pub paths: u64,
+    /// Total number of bounces of the paths: hits that scattered or emitted light, not counting shadow rays
+    pub bounces: u64,
+}
+
+impl PathStats {
+    /// Adds the counts of the other statistics to these
+    pub fn merge(&mut self, other: PathStats) {
+        self.paths += other.paths;
+        self.bounces += other.bounces;
+    }
+
+    /// Returns the average number of bounces per path
+    pub fn average_length(&self) -> Float {
+        if self.paths == 0 {
+            return 0.0;
+        }
+        self.bounces as Float / self.paths as Float
+    }
+}
 
 /// The main coloring function
-pub fn colorize(ray: &Ray, scene: &Scene, depth: u32, max_depth: u32, rng: ThreadRng) -> Color {
-    if depth > max_depth {
-        // Ray bounce limit reached, return background_color
-        return scene.background_color;
+pub fn colorize(
+    ray: &Ray,
+    scene: &Scene,
+    depth: u32,
+    max_depth: u32,
+    rng: &mut RandomGenerator,
+) -> Color {
+    trace_path(ray, scene, depth, max_depth, &mut |_| {}, rng)
+}
+
+/// Like [colorize()], but also records the length of the path in the statistics
+pub fn colorize_with_stats(
+    ray: &Ray,
+    scene: &Scene,
+    max_depth: u32,
+    stats: &mut PathStats,
+    rng: &mut RandomGenerator,
+) -> Color {
+    stats.paths += 1;
+    trace_path(ray, scene, 0, max_depth, &mut |_| stats.bounces += 1, rng)
+}
+
+/// A hit along a path, passed to the hook of [trace_path()]
+pub struct Bounce<'a> {
+    /// Number of bounces before this one
+    pub depth: u32,
+    /// The ray that found the hit
+    pub ray: &'a Ray,
+    pub hit_record: &'a HitRecord<'a>,
+    /// How much the light leaving the hit towards the camera contributes to the image, including the weights of Russian roulette
+    pub weight: Color,
+}
+
+/// The state of a path at a vertex
+#[derive(Copy, Clone, Debug)]
+struct PathState {
+    depth: u32,
+    /// Multiple importance sampling weight of the material sample that created the ray, for the light emitted at the next hit or arriving from the environment
+    emission_weight: Float,
+    /// Product of the scattering colors divided by the sampling densities along the path so far. Decides the survival of the path in Russian roulette.
+    throughput: Color,
+    /// The throughput divided by the survival probabilities of Russian roulette along the path so far: how much the light arriving at this vertex contributes to the image
+    weight: Color,
+}
+
+impl Default for PathState {
+    fn default() -> Self {
+        PathState {
+            depth: 0,
+            emission_weight: 1.0,
+            throughput: Color::new(1.0, 1.0, 1.0),
+            weight: Color::new(1.0, 1.0, 1.0),
+        }
+    }
+}
+
+impl PathState {
+    /// Returns the state for the next vertex of the path, before Russian roulette
+    fn next(self, emission_weight: Float, scattering: Color) -> PathState {
+        PathState {
+            depth: self.depth + 1,
+            emission_weight,
+            throughput: self.throughput * scattering,
+            weight: self.weight * scattering,
+        }
     }
 
-    // Here, smoothing is used to avoid "shadow acne"
-    match scene
+    /// Returns the state of a path that survived Russian roulette with the given probability
+    fn survived(self, survival: Float) -> PathState {
+        PathState {
+            weight: self.weight / survival,
+            ..self
+        }
+    }
+}
+
+/// Light found at a vertex of a path, and how the light arriving from the rest of the path is added to it
+struct Vertex {
+    /// Light emitted at the vertex and direct lighting from the light sources, weighted for multiple importance sampling
+    light: Color,
+    /// Scattering color of the material, divided by the sampling density of the continued path
+    scattering: Color,
+    /// Probability of the path surviving Russian roulette after the vertex
+    survival: Float,
+}
+
+/// Evaluates the path of the ray, starting at the given depth. The path is traced in a loop, one bounce at a time, drawing the random numbers in the same order as a recursive evaluation of the path would. The light found at the vertices is then combined from the end of the path back to the camera, in the same order of floating point operations as the recursion, so the result is bit-for-bit the same. `on_bounce` is called for every hit, e.g. for collecting statistics.
+pub fn trace_path(
+    ray: &Ray,
+    scene: &Scene,
+    depth: u32,
+    max_depth: u32,
+    on_bounce: &mut impl FnMut(&Bounce),
+    rng: &mut RandomGenerator,
+) -> Color {
+    let mut vertices: Vec<Vertex> = Vec::new();
+    let mut ray = *ray;
+    let mut path = PathState {
+        depth,
+        ..PathState::default()
+    };
+
+    let end = loop {
+        if path.depth > max_depth {
+            // Ray bounce limit reached, the path ends with the environment color
+            break scene.environment.color(ray.direction) * path.emission_weight;
+        }
+
+        // Here, smoothing is used to avoid "shadow acne"
+        let hit_record = match scene
+            .objects
+            .hit(&ray, EPSILON_SHADOW_ACNE, Float::MAX, rng)
+        {
+            // If the ray hits nothing, the path ends with the environment color.
+            None => break scene.environment.color(ray.direction) * path.emission_weight,
+            Some(hit_record) => hit_record,
+        };
+        on_bounce(&Bounce {
+            depth: path.depth,
+            ray: &ray,
+            hit_record: &hit_record,
+            weight: path.weight,
+        });
+
+        let emitted: Color = hit_record.material.emit(
+            &ray,
+            &hit_record,
+            hit_record.u,
+            hit_record.v,
+            hit_record.position,
+        ) * path.emission_weight;
+
+        // Do we scatter?
+        let scatter_record = match hit_record.material.scatter(&ray, &hit_record, rng) {
+            // No scatter, emit only
+            None => break emitted,
+            Some(scatter_record) => scatter_record,
+        };
+
+        let (next_ray, next, light, scattering) = match scatter_record.material_type {
+            // If we hit a specular, continue with the specular ray. Light sampling cannot find this direction, so the full emission is counted at the next hit.
+            MaterialType::Specular => (
+                scatter_record.specular_ray.unwrap(), // should always have a ray at this point
+                path.next(1.0, scatter_record.attenuation),
+                emitted,
+                scatter_record.attenuation,
+            ),
+            MaterialType::Diffuse => {
+                let material_pdf = scatter_record.pdf_ptr;
+                let light_pdf = light_pdf(scene, hit_record.position);
+
+                // Light sample: direct lighting through a shadow ray
+                let direct = match &light_pdf {
+                    Some(light_pdf) => {
+                        let shadow_ray =
+                            Ray::new(hit_record.position, light_pdf.generate(rng), ray.time)
+                                .with_wavelength(ray.wavelength);
+                        let light_value = light_pdf.value(shadow_ray.direction, ray.time, rng);
+                        let material_value =
+                            material_pdf.value(shadow_ray.direction, ray.time, rng);
+                        if light_value > 0.0 {
+                            let scattering = hit_record.material.scattering_color(
+                                &ray,
+                                &hit_record,
+                                scatter_record.attenuation,
+                                &shadow_ray,
+                                rng,
+                            );
+                            scattering
+                                * emitted_light(&shadow_ray, scene, rng)
+                                * power_heuristic(light_value, material_value)
+                                / light_value
+                        } else {
+                            Color::new(0.0, 0.0, 0.0)
+                        }
+                    }
+                    None => Color::new(0.0, 0.0, 0.0),
+                };
+
+                // Material sample: continue the path
+                let scattered = Ray::new(hit_record.position, material_pdf.generate(rng), ray.time)
+                    .with_wavelength(ray.wavelength);
+                let material_value = material_pdf.value(scattered.direction, ray.time, rng);
+                if material_value <= 0.0 {
+                    break emitted + direct;
+                }
+                let weight = match &light_pdf {
+                    Some(light_pdf) => power_heuristic(
+                        material_value,
+                        light_pdf.value(scattered.direction, ray.time, rng),
+                    ),
+                    None => 1.0,
+                };
+                let scattering = hit_record.material.scattering_color(
+                    &ray,
+                    &hit_record,
+                    scatter_record.attenuation,
+                    &scattered,
+                    rng,
+                ) / material_value;
+                (
+                    scattered,
+                    path.next(weight, scattering),
+                    emitted + direct,
+                    scattering,
+                )
+            }
+        };
+
+        let survival = match survival_probability(scene, next.depth, next.throughput) {
+            Some(survival) if rng.gen::<Float>() >= survival => break light,
+            Some(survival) => survival,
+            None => 1.0,
+        };
+        vertices.push(Vertex {
+            light,
+            scattering,
+            survival,
+        });
+        path = next.survived(survival);
+        ray = next_ray;
+    };
+
+    vertices.iter().rev().fold(end, |arriving, vertex| {
+        vertex.light + vertex.scattering * arriving / vertex.survival
+    })
+}
+
+/// Returns the probability of a path surviving Russian roulette before continuing to the vertex at the given depth with the given throughput, or None if the path cannot be terminated yet
+pub(crate) fn survival_probability(scene: &Scene, depth: u32, throughput: Color) -> Option<Float> {
+    let roulette = scene.russian_roulette;
+    if !roulette.enabled || depth < roulette.min_depth {
+        return None;
+    }
+    let survival = throughput.r.max(throughput.g).max(throughput.b);
+    // NaN throughput fails the comparison and terminates the path
+    Some(survival.min(ROULETTE_MAX_SURVIVAL))
+}
+
+/// Returns the light emitted towards the origin of the shadow ray by the light source it finds: the nearest of the `priority_objects`, or the environment if it misses them. The priority objects only locate the light source; its emission comes from the objects of the scene at the same point, as they may differ in material or orientation. The light is attenuated by the [transmittance](Hitable::transmittance) of the scene up to the light source, so it passes through grid media but is blocked by surfaces.
+fn emitted_light(ray: &Ray, scene: &Scene, rng: &mut RandomGenerator) -> Color {
+    let light = scene
+        .priority_objects
+        .hit(ray, EPSILON_SHADOW_ACNE, Float::MAX, rng);
+    let (emitted, distance) = match light {
+        None => (scene.environment.color(ray.direction), Float::MAX),
+        Some(light) => {
+            let distance = light.distance;
+            let surface = scene.objects.hit(
+                ray,
+                distance - EPSILON_SHADOW_ACNE,
+                distance + EPSILON_SHADOW_ACNE,
+                rng,
+            );
+            let emitted = match surface {
+                Some(hit_record) => hit_record.material.emit(
+                    ray,
+                    &hit_record,
+                    hit_record.u,
+                    hit_record.v,
+                    hit_record.position,
+                ),
+                None => Color::new(0.0, 0.0, 0.0),
+            };
+            (emitted, distance - EPSILON_SHADOW_ACNE)
+        }
+    };
+    if emitted.luminance() <= 0.0 {
+        return emitted;
+    }
+    let transmittance = scene
         .objects
-        .hit(&ray, EPSILON_SHADOW_ACNE, Float::MAX, rng)
-    {
-        // If the ray hits nothing, return the background color.
-        None => scene.background_color,
+        .transmittance(ray, EPSILON_SHADOW_ACNE, distance, rng);
+    emitted * transmittance
+}
+
+/// The power heuristic of multiple importance sampling, with an exponent of two: the weight of a sample from the strategy with the density `pdf`, when the other strategy has the density `other_pdf` for the same direction
+fn power_heuristic(pdf: Float, other_pdf: Float) -> Float {
+    let pdf = pdf * pdf;
+    let other_pdf = other_pdf * other_pdf;
+    if pdf + other_pdf <= 0.0 {
+        return 0.0;
+    }
+    pdf / (pdf + other_pdf)
+}
+
+/// Returns a [PDF] for sampling the light sources of the scene: the `priority_objects` and the environment map, if the scene has them. When it has both, the environment map is sampled with the probability of its [sampling_weight()](crate::environment::EnvironmentMap::sampling_weight).
+fn light_pdf(scene: &Scene, origin: Vec3) -> Option<PDF> {
+    let objects = match &scene.priority_objects {
+        Hitable::HitableList(list) if list.0.is_empty() => None,
+        priority_objects => Some(HitablePDF::new(priority_objects, origin)),
+    };
+    let environment = match &scene.environment {
+        Environment::Map(map) => Some((EnvironmentPDF::new(map), map.sampling_weight())),
+        Environment::Color(_) => None,
+    };
+
+    match (objects, environment) {
+        (Some(objects), Some((environment, weight))) => {
+            Some(MixturePDF::new(environment, objects, weight))
+        }
+        (Some(pdf), None) | (None, Some((pdf, _))) => Some(pdf),
+        (None, None) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{random::sample_rng, scenes};
+
+    const WIDTH: u32 = 16;
+    const HEIGHT: u32 = 16;
+    const SAMPLES: u32 = 4;
+    const MAX_DEPTH: u32 = 100;
+
+    /// The recursive evaluation of the paths, exactly as [colorize()] was before it was replaced by the loop of [trace_path()], as a reference for it. Light sampling and shadow rays have changed since, so it only matches on scenes without an environment map or media.
+    mod recursive {
+        use crate::{
+            color::Color,
+            colorize::PathStats,
+            environment::Environment,
+            hitable::Hitable,
+            materials::MaterialType,
+            pdf::{EnvironmentPDF, HitablePDF, MixturePDF, PDF},
+            ray::Ray,
+            scenes::Scene,
+            Float, RandomGenerator, Vec3, EPSILON_SHADOW_ACNE,
+        };
+        use rand::prelude::*;
+
+        const ROULETTE_MAX_SURVIVAL: Float = 0.95;
+
+        /// The main coloring function
+        pub fn colorize(
+            ray: &Ray,
+            scene: &Scene,
+            depth: u32,
+            max_depth: u32,
+            rng: &mut RandomGenerator,
+        ) -> Color {
+            let path = PathState {
+                depth,
+                ..PathState::default()
+            };
+            trace(ray, scene, max_depth, path, &mut PathStats::default(), rng)
+        }
+
+        /// The state of a path at a vertex
+        #[derive(Copy, Clone, Debug)]
+        struct PathState {
+            depth: u32,
+            /// Multiple importance sampling weight of the material sample that created the ray, for the light emitted at the next hit or arriving from the environment
+            emission_weight: Float,
+            /// Product of the scattering colors divided by the sampling densities along the path so far: how much the light arriving at this vertex contributes to the image
+            throughput: Color,
+        }
+
+        impl Default for PathState {
+            fn default() -> Self {
+                PathState {
+                    depth: 0,
+                    emission_weight: 1.0,
+                    throughput: Color::new(1.0, 1.0, 1.0),
+                }
+            }
+        }
+
+        impl PathState {
+            /// Returns the state for the next vertex of the path
+            fn next(self, emission_weight: Float, scattering: Color) -> PathState {
+                PathState {
+                    depth: self.depth + 1,
+                    emission_weight,
+                    throughput: self.throughput * scattering,
+                }
+            }
+        }
+
+        /// Evaluates the path of the ray
+        fn trace(
+            ray: &Ray,
+            scene: &Scene,
+            max_depth: u32,
+            path: PathState,
+            stats: &mut PathStats,
+            rng: &mut RandomGenerator,
+        ) -> Color {
+            if path.depth > max_depth {
+                // Ray bounce limit reached, return the environment color
+                return scene.environment.color(ray.direction) * path.emission_weight;
+            }
+
+            // Here, smoothing is used to avoid "shadow acne"
+            let hit_record = match scene.objects.hit(ray, EPSILON_SHADOW_ACNE, Float::MAX, rng) {
+                // If the ray hits nothing, return the environment color.
+                None => return scene.environment.color(ray.direction) * path.emission_weight,
+                Some(hit_record) => hit_record,
+            };
+            stats.bounces += 1;
 
-        // Hit something
-        Some(hit_record) => {
             let emitted: Color = hit_record.material.emit(
                 ray,
                 &hit_record,
                 hit_record.u,
                 hit_record.v,
                 hit_record.position,
-            );
+            ) * path.emission_weight;
 
             // Do we scatter?
-            match hit_record.material.scatter(&ray, &hit_record, rng) {
+            let scatter_record = match hit_record.material.scatter(ray, &hit_record, rng) {
                 // No scatter, emit only
-                None => emitted,
-                // Got a scatter
-                Some(scatter_record) => {
-                    match scatter_record.material_type {
-                        // If we hit a specular, return a specular ray
-                        crate::materials::MaterialType::Specular => {
-                            scatter_record.attenuation
-                                * colorize(
-                                    &scatter_record.specular_ray.unwrap(), // should always have a ray at this point
-                                    scene,
-                                    depth + 1,
-                                    max_depth,
+                None => return emitted,
+                Some(scatter_record) => scatter_record,
+            };
+
+            match scatter_record.material_type {
+                // If we hit a specular, return a specular ray. Light sampling cannot find this direction, so the full emission is counted at the next hit.
+                MaterialType::Specular => {
+                    let next = path.next(1.0, scatter_record.attenuation);
+                    let survival = match survival_probability(scene, next) {
+                        Some(survival) if rng.gen::<Float>() >= survival => return emitted,
+                        Some(survival) => survival,
+                        None => 1.0,
+                    };
+                    emitted
+                        + scatter_record.attenuation
+                            * trace(
+                                &scatter_record.specular_ray.unwrap(), // should always have a ray at this point
+                                scene,
+                                max_depth,
+                                next,
+                                stats,
+                                rng,
+                            )
+                            / survival
+                }
+                MaterialType::Diffuse => {
+                    let material_pdf = scatter_record.pdf_ptr;
+                    let light_pdf = light_pdf(scene, hit_record.position);
+
+                    // Light sample: direct lighting through a shadow ray
+                    let direct = match &light_pdf {
+                        Some(light_pdf) => {
+                            let shadow_ray =
+                                Ray::new(hit_record.position, light_pdf.generate(rng), ray.time)
+                                    .with_wavelength(ray.wavelength);
+                            let light_value = light_pdf.value(shadow_ray.direction, ray.time, rng);
+                            let material_value =
+                                material_pdf.value(shadow_ray.direction, ray.time, rng);
+                            if light_value > 0.0 {
+                                let scattering = hit_record.material.scattering_color(
+                                    ray,
+                                    &hit_record,
+                                    scatter_record.attenuation,
+                                    &shadow_ray,
                                     rng,
-                                )
-                        }
-                        crate::materials::MaterialType::Diffuse => {
-                            // Use a probability density function to figure out where to scatter a new ray
-                            let light_ptr =
-                                HitablePDF::new(&scene.priority_objects, hit_record.position);
-                            let mixture_pdf = MixturePDF::new(light_ptr, scatter_record.pdf_ptr);
-
-                            let scattered =
-                                Ray::new(hit_record.position, mixture_pdf.generate(rng), ray.time);
-                            let pdf_val = mixture_pdf.value(scattered.direction, ray.time, rng);
-
-                            // recurse
-                            let recurse = colorize(&scattered, scene, depth + 1, max_depth, rng);
-
-                            // Blend it all together
-                            emitted
-                                + scatter_record.attenuation
-                                    * hit_record.material.scattering_pdf(
-                                        ray,
-                                        &hit_record,
-                                        &scattered,
-                                        rng,
-                                    )
-                                    * recurse
-                                    / pdf_val
+                                );
+                                scattering
+                                    * emitted_light(&shadow_ray, scene, rng)
+                                    * power_heuristic(light_value, material_value)
+                                    / light_value
+                            } else {
+                                Color::new(0.0, 0.0, 0.0)
+                            }
                         }
+                        None => Color::new(0.0, 0.0, 0.0),
+                    };
+
+                    // Material sample: continue the path
+                    let scattered =
+                        Ray::new(hit_record.position, material_pdf.generate(rng), ray.time)
+                            .with_wavelength(ray.wavelength);
+                    let material_value = material_pdf.value(scattered.direction, ray.time, rng);
+                    if material_value <= 0.0 {
+                        return emitted + direct;
                     }
+                    let weight = match &light_pdf {
+                        Some(light_pdf) => power_heuristic(
+                            material_value,
+                            light_pdf.value(scattered.direction, ray.time, rng),
+                        ),
+                        None => 1.0,
+                    };
+                    let scattering = hit_record.material.scattering_color(
+                        ray,
+                        &hit_record,
+                        scatter_record.attenuation,
+                        &scattered,
+                        rng,
+                    ) / material_value;
+
+                    let next = path.next(weight, scattering);
+                    let survival = match survival_probability(scene, next) {
+                        Some(survival) if rng.gen::<Float>() >= survival => {
+                            return emitted + direct
+                        }
+                        Some(survival) => survival,
+                        None => 1.0,
+                    };
+
+                    // recurse
+                    let recurse = trace(&scattered, scene, max_depth, next, stats, rng);
+
+                    // Blend it all together
+                    emitted + direct + scattering * recurse / survival
+                }
+            }
+        }
+
+        /// Returns the probability of the path surviving Russian roulette before continuing to the given vertex, or None if the path cannot be terminated yet
+        fn survival_probability(scene: &Scene, next: PathState) -> Option<Float> {
+            let roulette = scene.russian_roulette;
+            if !roulette.enabled || next.depth < roulette.min_depth {
+                return None;
+            }
+            let throughput = next.throughput;
+            let survival = throughput.r.max(throughput.g).max(throughput.b);
+            // NaN throughput fails the comparison and terminates the path
+            Some(survival.min(ROULETTE_MAX_SURVIVAL))
+        }
+
+        /// Returns the light emitted towards the origin of the ray from its first hit, or from the environment if it hits nothing
+        fn emitted_light(ray: &Ray, scene: &Scene, rng: &mut RandomGenerator) -> Color {
+            match scene.objects.hit(ray, EPSILON_SHADOW_ACNE, Float::MAX, rng) {
+                None => scene.environment.color(ray.direction),
+                Some(hit_record) => hit_record.material.emit(
+                    ray,
+                    &hit_record,
+                    hit_record.u,
+                    hit_record.v,
+                    hit_record.position,
+                ),
+            }
+        }
+
+        /// The power heuristic of multiple importance sampling, with an exponent of two: the weight of a sample from the strategy with the density `pdf`, when the other strategy has the density `other_pdf` for the same direction
+        fn power_heuristic(pdf: Float, other_pdf: Float) -> Float {
+            let pdf = pdf * pdf;
+            let other_pdf = other_pdf * other_pdf;
+            if pdf + other_pdf <= 0.0 {
+                return 0.0;
+            }
+            pdf / (pdf + other_pdf)
+        }
+
+        /// Returns a [PDF] for sampling the light sources of the scene: the `priority_objects` and the environment map, if the scene has them.
+        fn light_pdf(scene: &Scene, origin: Vec3) -> Option<PDF> {
+            let objects = match &scene.priority_objects {
+                Hitable::HitableList(list) if list.0.is_empty() => None,
+                priority_objects => Some(HitablePDF::new(priority_objects, origin)),
+            };
+            let environment = match &scene.environment {
+                Environment::Map(map) => Some(EnvironmentPDF::new(map)),
+                Environment::Color(_) => None,
+            };
+
+            match (objects, environment) {
+                (Some(objects), Some(environment)) => {
+                    Some(MixturePDF::new(objects, environment, 0.5))
+                }
+                (Some(pdf), None) | (None, Some(pdf)) => Some(pdf),
+                (None, None) => None,
+            }
+        }
+    }
+
+    /// Renders a few samples for each pixel of a small image with both tracers from the same seeds, and checks that every sample has bit-for-bit the same color
+    fn assert_matches_recursive(path: &str, russian_roulette: bool) {
+        let path = format!("{}/scenes/{}", env!("CARGO_MANIFEST_DIR"), path);
+        let mut scene = scenes::initialize(&path, WIDTH, HEIGHT).unwrap();
+        scene.russian_roulette.enabled = russian_roulette;
+
+        for y in 0..HEIGHT {
+            for x in 0..WIDTH {
+                let index = (y * WIDTH + x) as u64;
+                for sample in 0..SAMPLES as u64 {
+                    let mut rng = sample_rng(7, index, sample);
+                    let u = (x as Float + rng.gen::<Float>()) / WIDTH as Float;
+                    let v = (y as Float + rng.gen::<Float>()) / HEIGHT as Float;
+                    let ray = scene.camera.get_ray(u, v, &mut rng);
+                    let mut recursive_rng = rng.clone();
+                    let looped = colorize(&ray, &scene, 0, MAX_DEPTH, &mut rng);
+                    let recursive =
+                        recursive::colorize(&ray, &scene, 0, MAX_DEPTH, &mut recursive_rng);
+                    assert!(
+                        [looped.r, looped.g, looped.b]
+                            .iter()
+                            .zip([recursive.r, recursive.g, recursive.b].iter())
+                            .all(|(a, b)| a.to_bits() == b.to_bits()),
+                        "{}: sample {} of pixel ({}, {}) differs: {:?} vs {:?}",
+                        path,
+                        sample,
+                        x,
+                        y,
+                        looped,
+                        recursive
+                    );
                 }
             }
         }
     }
+
+    /// Runs the check on a thread with a large stack: the recursion of the reference, and of building the scenes in debug builds, is too deep for the default stack of the test threads
+    fn run_with_large_stack(check: fn()) {
+        std::thread::Builder::new()
+            .stack_size(256 * 1024 * 1024)
+            .spawn(check)
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+
+    #[test]
+    fn trace_path_matches_recursive() {
+        run_with_large_stack(|| {
+            assert_matches_recursive("scene.json", true);
+            assert_matches_recursive("cornell_with_principled_spheres.json", true);
+        });
+    }
+
+    #[test]
+    fn trace_path_matches_recursive_without_roulette() {
+        run_with_large_stack(|| {
+            assert_matches_recursive("scene.json", false);
+            assert_matches_recursive("cornell_with_principled_spheres.json", false);
+        });
+    }
+
+    #[test]
+    fn emitted_light_comes_from_the_scene_objects() {
+        run_with_large_stack(|| {
+            // The light of the scene is flipped to face down, the priority object marking it is not
+            let path = format!("{}/scenes/scene.json", env!("CARGO_MANIFEST_DIR"));
+            let scene = scenes::initialize(&path, WIDTH, HEIGHT).unwrap();
+            let mut rng = sample_rng(7, 0, 0);
+            let ray = Ray::new(
+                Vec3::new(278.0, 400.0, 279.5),
+                Vec3::new(0.0, 1.0, 0.0),
+                0.0,
+            );
+            assert!(emitted_light(&ray, &scene, &mut rng).luminance() > 0.0);
+        });
+    }
 }
@@ -1,73 +1,233 @@
-use crate::{color::Color, colorize::colorize, ray::Ray, scenes, Float};
+use crate::{
+    bidirectional::Splat,
+    checkpoint::Checkpoint,
+    color::Color,
+    colorize::PathStats,
+    integrator::Integrator,
+    random::sample_rng,
+    ray::Ray,
+    scenes,
+    spectrum::{rgb_to_spectral, sample_wavelength, SpectralConverter},
+    Float, RandomGenerator,
+};
 use indicatif::{ProgressBar, ProgressStyle};
 use rand::prelude::*;
 use rayon::prelude::*;
 use scenes::Scene;
+use std::{
+    io::Error,
+    time::{Duration, Instant},
+};
 
-/// The main drawing function, returns a Vec<Color> as a pixelbuffer.
-pub fn draw(
+/// Width and height of the square tiles the image is split into for rendering
+const TILE_SIZE: u32 = 32;
+
+/// Where and how often to save checkpoints during rendering
+pub struct Checkpointing<'a> {
+    pub path: &'a str,
+    pub interval: Duration,
+}
+
+/// Settings for adaptive sampling: pixels stop receiving samples once their estimated relative error falls below the threshold
+pub struct Adaptive {
+    /// Maximum estimated relative error of a finished pixel, see [Checkpoint::relative_error()]
+    pub threshold: Float,
+    /// Minimum number of samples for each pixel before the error estimate is trusted
+    pub min_samples: u32,
+}
+
+/// How each sample turns a camera ray into a color
+pub struct Integration {
+    pub integrator: Integrator,
+    /// Maximum number of bounces of the paths
+    pub max_depth: u32,
+    /// Trace a single wavelength for each sample, see [spectrum](clovers::spectrum)
+    pub spectral: bool,
+}
+
+/// Settings shared by all the samples of a render
+struct SampleSettings {
     width: u32,
     height: u32,
-    samples: u32,
     max_depth: u32,
-    gamma: Float,
-    scene: Scene,
-) -> Vec<Color> {
+    integrator: Integrator,
+    /// Converter for the results of spectral samples, None in RGB mode
+    spectral: Option<SpectralConverter>,
+}
+
+/// A rectangular region of the image, rendered as a single unit of work
+struct Tile {
+    x_min: u32,
+    y_min: u32,
+    x_max: u32,
+    y_max: u32,
+}
+
+/// The main drawing function. Renders in passes, taking one more sample for each pixel that has less than `samples` samples in the [Checkpoint]. With `adaptive` sampling, pixels that are already converged are skipped. Each pass is split into tiles that are rendered in parallel. The checkpoint is saved periodically and after the last pass, if `checkpointing` is given. Returns statistics about the paths traced in this run.
+pub fn draw(
+    state: &mut Checkpoint,
+    samples: u32,
+    scene: &Scene,
+    integration: Integration,
+    adaptive: Option<Adaptive>,
+    checkpointing: Option<Checkpointing>,
+) -> Result<PathStats, Error> {
+    let width = state.width;
+    let height = state.height;
+    let seed = state.seed;
+    let tiles = tiles(width, height);
+    let settings = SampleSettings {
+        width,
+        height,
+        max_depth: integration.max_depth,
+        integrator: integration.integrator,
+        spectral: if integration.spectral {
+            Some(SpectralConverter::new())
+        } else {
+            None
+        },
+    };
+
     // Progress bar
-    let pixels = (width * height) as u64;
-    let bar = ProgressBar::new(pixels);
-    bar.set_draw_delta(pixels / 1000);
+    let total = width as u64 * height as u64 * samples as u64;
+    let done = state.total_samples().min(total);
+    let bar = ProgressBar::new(total);
+    bar.set_draw_delta(total / 1000);
     bar.set_style(ProgressStyle::default_bar().template(
-        "Elapsed: {elapsed_precise}\nPixels:  {bar} {pos}/{len}\nETA:     {eta_precise}",
+        "Elapsed: {elapsed_precise}\nSamples: {bar} {pos}/{len}\nETA:     {eta_precise}",
     ));
+    bar.set_position(done);
 
-    let black = Color::new(0.0, 0.0, 0.0);
-    let mut pixelbuffer = vec![black; pixels as usize];
-
-    pixelbuffer
-        .par_iter_mut()
-        .enumerate()
-        .for_each(|(index, pixel)| {
-            let x = index % width as usize;
-            let y = index / width as usize;
-            let rng = rand::thread_rng();
-            let mut color: Color = Color::new(0.0, 0.0, 0.0);
-
-            // Multisampling for antialiasing
-            for _sample in 0..samples {
-                match sample(&scene, x, y, width, height, rng, max_depth) {
-                    Some(s) => color += s,
-                    None => {}
+    let mut stats = PathStats::default();
+    let mut last_save = Instant::now();
+    loop {
+        let current: &Checkpoint = state;
+        let counts = &current.counts;
+        let pending = |index: usize| {
+            let count = counts[index];
+            match &adaptive {
+                None => count < samples,
+                Some(adaptive) => {
+                    count < adaptive.min_samples.min(samples)
+                        || (count < samples && current.relative_error(index) > adaptive.threshold)
                 }
             }
-            color /= samples as Float;
+        };
 
-            color = color.gamma_correction(gamma);
-            *pixel = color;
+        // Render one sample for each pending pixel. Each tile returns the samples for its own pixels, and the splats for any pixels.
+        let results: Vec<_> = tiles
+            .par_iter()
+            .map(|tile| {
+                let mut results = Vec::new();
+                let mut splats = Vec::new();
+                let mut stats = PathStats::default();
+                for y in tile.y_min..tile.y_max {
+                    for x in tile.x_min..tile.x_max {
+                        let index = (y * width + x) as usize;
+                        if !pending(index) {
+                            continue;
+                        }
+                        let sample_index = counts[index];
+                        let mut rng = sample_rng(seed, index as u64, sample_index as u64);
+                        let color =
+                            sample(scene, x, y, &settings, &mut stats, &mut splats, &mut rng);
+                        results.push((index, color));
+                    }
+                }
+                bar.inc(results.len() as u64);
+                (results, splats, stats)
+            })
+            .collect();
 
-            bar.inc(1);
-        });
+        if results.iter().all(|(results, _, _)| results.is_empty()) {
+            break;
+        }
+        for (results, splats, tile_stats) in results {
+            stats.merge(tile_stats);
+            for (index, color) in results {
+                state.add_sample(index, color);
+            }
+            for (index, color) in splats {
+                state.add_splat(index, color);
+            }
+        }
+
+        if let Some(checkpointing) = &checkpointing {
+            if last_save.elapsed() >= checkpointing.interval {
+                state.save(checkpointing.path)?;
+                last_save = Instant::now();
+            }
+        }
+    }
 
-    pixelbuffer
+    if let Some(checkpointing) = &checkpointing {
+        state.save(checkpointing.path)?;
+    }
+    bar.finish();
+
+    Ok(stats)
 }
 
-/// Get a single sample for a single pixel in the scene. Has slight jitter for antialiasing when multisampling.
+/// Splits the image into tiles of [TILE_SIZE] pixels. The tiles at the right and bottom edges may be smaller.
+fn tiles(width: u32, height: u32) -> Vec<Tile> {
+    let mut tiles = Vec::new();
+    for y_min in (0..height).step_by(TILE_SIZE as usize) {
+        for x_min in (0..width).step_by(TILE_SIZE as usize) {
+            tiles.push(Tile {
+                x_min,
+                y_min,
+                x_max: (x_min + TILE_SIZE).min(width),
+                y_max: (y_min + TILE_SIZE).min(height),
+            });
+        }
+    }
+    tiles
+}
+
+/// Get a single sample for a single pixel in the scene, recording the length of its path in `stats` and pushing the pixel indices and colors of its [Splats](Splat) into `splats`. Has slight jitter for antialiasing when multisampling. With a [SpectralConverter], the ray gets a random wavelength and the result is the RGB contribution of that wavelength.
 fn sample(
     scene: &Scene,
-    x: usize,
-    y: usize,
-    width: u32,
-    height: u32,
-    mut rng: ThreadRng,
-    max_depth: u32,
+    x: u32,
+    y: u32,
+    settings: &SampleSettings,
+    stats: &mut PathStats,
+    splats: &mut Vec<(usize, Color)>,
+    rng: &mut RandomGenerator,
 ) -> Option<Color> {
-    let u = (x as Float + rng.gen::<Float>()) / width as Float;
-    let v = (y as Float + rng.gen::<Float>()) / height as Float;
+    let max_depth = settings.max_depth;
+    let u = (x as Float + rng.gen::<Float>()) / settings.width as Float;
+    let v = (y as Float + rng.gen::<Float>()) / settings.height as Float;
     let ray: Ray = scene.camera.get_ray(u, v, rng);
-    let new_color = colorize(&ray, &scene, 0, max_depth, rng);
+    let wavelength = settings.spectral.as_ref().map(|_| sample_wavelength(rng));
+    let ray = ray.with_wavelength(wavelength);
+    let mut sample_splats: Vec<Splat> = Vec::new();
+    let color = settings
+        .integrator
+        .sample(&ray, scene, max_depth, stats, &mut sample_splats, rng);
+    let to_rgb = |color: Color| match (&settings.spectral, wavelength) {
+        (Some(converter), Some(wavelength)) => {
+            converter.to_rgb(rgb_to_spectral(color, wavelength), wavelength)
+        }
+        _ => color,
+    };
+
+    for splat in sample_splats {
+        let color = to_rgb(splat.color);
+        if is_finite(color) {
+            let x = ((splat.u * settings.width as Float) as u32).min(settings.width - 1);
+            let y = ((splat.v * settings.height as Float) as u32).min(settings.height - 1);
+            splats.push(((y * settings.width + x) as usize, color));
+        }
+    }
+    let new_color = to_rgb(color);
     // skip NaN and Infinity
-    if new_color.r.is_finite() && new_color.g.is_finite() && new_color.b.is_finite() {
+    if is_finite(new_color) {
         return Some(new_color);
     }
     None
 }
+
+/// Whether all the channels of the color are finite, i.e. neither NaN nor infinity
+fn is_finite(color: Color) -> bool {
+    color.r.is_finite() && color.g.is_finite() && color.b.is_finite()
+}
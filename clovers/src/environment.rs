@@ -0,0 +1,344 @@
+//! The environment surrounding the scene. Rays that do not hit any object get their color from the environment: either a uniform background color, or an equirectangular high dynamic range environment map.
+
+use crate::{color::Color, Float, RandomGenerator, Vec3, PI};
+use image::codecs::hdr::HdrDecoder;
+use rand::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::File,
+    io::{BufReader, Error, ErrorKind},
+    path::Path,
+};
+
+/// Used for the scene files etc
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EnvironmentInit {
+    /// Path to an equirectangular `.hdr` or `.exr` image, relative to the directory of the scene file
+    pub path: String,
+    /// Multiplier for the brightness of the environment map. Default value: 1.0
+    #[serde(default = "default_intensity")]
+    pub intensity: Float,
+    /// Probability of sampling the environment map instead of the `priority_objects` for the direct lighting, in scenes that have both. Between 0.0 and 1.0. Default value: 0.5
+    #[serde(default = "default_sampling_weight")]
+    pub sampling_weight: Float,
+}
+
+fn default_intensity() -> Float {
+    1.0
+}
+
+fn default_sampling_weight() -> Float {
+    0.5
+}
+
+/// The environment surrounding the scene. Returned for all rays that do not hit any objects.
+pub enum Environment {
+    /// A uniform background color in every direction
+    Color(Color),
+    /// An equirectangular environment map
+    Map(EnvironmentMap),
+}
+
+impl Environment {
+    /// Returns the color of the environment in the given direction
+    pub fn color(&self, direction: Vec3) -> Color {
+        match self {
+            Environment::Color(color) => *color,
+            Environment::Map(map) => map.color(direction),
+        }
+    }
+}
+
+impl From<Color> for Environment {
+    fn from(color: Color) -> Self {
+        Environment::Color(color)
+    }
+}
+
+impl From<EnvironmentMap> for Environment {
+    fn from(map: EnvironmentMap) -> Self {
+        Environment::Map(map)
+    }
+}
+
+/// An equirectangular high dynamic range environment map. The top row of the image is straight up along the Y axis. Can be importance sampled based on the luminance of the pixels, allowing the environment to act as a light source.
+pub struct EnvironmentMap {
+    width: usize,
+    height: usize,
+    pixels: Vec<Color>,
+    /// Cumulative distribution for picking a row
+    marginal_cdf: Vec<Float>,
+    /// Cumulative distributions for picking a column within each row, `width` entries per row
+    conditional_cdfs: Vec<Float>,
+    /// Sampling weight of each pixel, normalized to be a probability density over the unit square
+    densities: Vec<Float>,
+    /// Probability of sampling the environment map instead of the priority objects for the direct lighting
+    sampling_weight: Float,
+}
+
+impl EnvironmentMap {
+    /// Creates a new environment map from a row-major buffer of pixels. Returns an error if the map has no pixels, or if the buffer does not match the size of the map.
+    pub fn new(width: usize, height: usize, pixels: Vec<Color>) -> Result<EnvironmentMap, Error> {
+        if width == 0 || height == 0 {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("Environment map has no pixels: {}x{}", width, height),
+            ));
+        }
+        if pixels.len() != width * height {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "Environment map has {} pixels instead of {}x{}",
+                    pixels.len(),
+                    width,
+                    height
+                ),
+            ));
+        }
+        // Weight the pixels by their luminance. Rows near the poles cover less solid angle, so weight by sin(theta) too.
+        let weights: Vec<Float> = pixels
+            .iter()
+            .enumerate()
+            .map(|(index, pixel)| {
+                let row = index / width;
+                let theta = PI * (row as Float + 0.5) / height as Float;
+                pixel.luminance().max(0.0) * theta.sin()
+            })
+            .collect();
+
+        let mut conditional_cdfs: Vec<Float> = Vec::with_capacity(width * height);
+        let mut row_sums: Vec<Float> = Vec::with_capacity(height);
+        for row in weights.chunks_exact(width) {
+            let mut sum = 0.0;
+            for weight in row {
+                sum += weight;
+                conditional_cdfs.push(sum);
+            }
+            row_sums.push(sum);
+        }
+        let mut marginal_cdf: Vec<Float> = Vec::with_capacity(height);
+        let mut total = 0.0;
+        for sum in row_sums.iter() {
+            total += sum;
+            marginal_cdf.push(total);
+        }
+
+        // A fully black map cannot be importance sampled; fall back to uniform sampling
+        let densities: Vec<Float> = if total > 0.0 {
+            let area = (width * height) as Float;
+            weights.iter().map(|weight| weight * area / total).collect()
+        } else {
+            conditional_cdfs = (0..height)
+                .flat_map(|_| (1..=width).map(|column| column as Float))
+                .collect();
+            marginal_cdf = (1..=height).map(|row| row as Float).collect();
+            vec![1.0; width * height]
+        };
+
+        Ok(EnvironmentMap {
+            width,
+            height,
+            pixels,
+            marginal_cdf,
+            conditional_cdfs,
+            densities,
+            sampling_weight: default_sampling_weight(),
+        })
+    }
+
+    /// Loads an environment map from an equirectangular `.hdr` or `.exr` file. The colors are multiplied by the given intensity.
+    pub fn load(path: &str, intensity: Float) -> Result<EnvironmentMap, Error> {
+        EnvironmentMap::read(path, intensity).map_err(|err| {
+            Error::new(
+                err.kind(),
+                format!("failed to load environment map {}: {}", path, err),
+            )
+        })
+    }
+
+    fn read(path: &str, intensity: Float) -> Result<EnvironmentMap, Error> {
+        let extension = Path::new(path)
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.to_lowercase());
+
+        let (width, height, pixels) = match extension.as_deref() {
+            Some("hdr") => {
+                let reader = BufReader::new(File::open(path)?);
+                let decoder = HdrDecoder::new(reader).map_err(Error::other)?;
+                let metadata = decoder.metadata();
+                let pixels: Vec<Color> = decoder
+                    .read_image_hdr()
+                    .map_err(Error::other)?
+                    .iter()
+                    .map(|pixel| Color::new(pixel[0], pixel[1], pixel[2]))
+                    .collect();
+                (metadata.width as usize, metadata.height as usize, pixels)
+            }
+            Some("exr") => {
+                let image = exr::prelude::read_first_rgba_layer_from_file(
+                    path,
+                    |resolution, _channels| {
+                        let black = Color::new(0.0, 0.0, 0.0);
+                        (resolution.width(), vec![black; resolution.area()])
+                    },
+                    |(width, pixels), position, (r, g, b, _a): (Float, Float, Float, Float)| {
+                        pixels[position.y() * *width + position.x()] = Color::new(r, g, b);
+                    },
+                )
+                .map_err(Error::other)?;
+                let size = image.layer_data.size;
+                let (_width, pixels) = image.layer_data.channel_data.pixels;
+                (size.width(), size.height(), pixels)
+            }
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    "unsupported format, expected .hdr or .exr",
+                ))
+            }
+        };
+
+        let pixels = pixels.into_iter().map(|pixel| pixel * intensity).collect();
+        EnvironmentMap::new(width, height, pixels)
+    }
+
+    /// Sets the probability of sampling the environment map instead of the priority objects for the direct lighting. Returns an error if the weight is not between 0.0 and 1.0.
+    pub fn with_sampling_weight(mut self, weight: Float) -> Result<EnvironmentMap, Error> {
+        if !(0.0..=1.0).contains(&weight) {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "Environment map sampling weight must be between 0.0 and 1.0: {}",
+                    weight
+                ),
+            ));
+        }
+        self.sampling_weight = weight;
+        Ok(self)
+    }
+
+    /// Returns the probability of sampling the environment map instead of the priority objects for the direct lighting
+    pub fn sampling_weight(&self) -> Float {
+        self.sampling_weight
+    }
+
+    /// Returns the color of the environment map in the given direction
+    pub fn color(&self, direction: Vec3) -> Color {
+        let (u, v) = direction_to_uv(direction);
+        self.pixels[self.index(u, v)]
+    }
+
+    /// Returns the probability density of sampling the given direction with [random()](EnvironmentMap::random), with respect to solid angle
+    pub fn pdf_value(&self, direction: Vec3) -> Float {
+        let (u, v) = direction_to_uv(direction);
+        // Near the poles, computing the angle from the Y axis with acos() rounds it to zero, so take its sine from the other axes
+        let direction = direction.normalize();
+        let sin_theta = (direction.x * direction.x + direction.z * direction.z).sqrt();
+        if sin_theta <= 0.0 {
+            return 0.0;
+        }
+        // Convert from the density over the unit square to a density over the sphere
+        self.densities[self.index(u, v)] / (2.0 * PI * PI * sin_theta)
+    }
+
+    /// Returns a random direction, sampled proportionally to the luminance of the environment map
+    pub fn random(&self, rng: &mut RandomGenerator) -> Vec3 {
+        let row = sample_cdf(&self.marginal_cdf, rng.gen::<Float>());
+        let row_cdf = &self.conditional_cdfs[row * self.width..(row + 1) * self.width];
+        let column = sample_cdf(row_cdf, rng.gen::<Float>());
+        // Jitter uniformly within the chosen pixel
+        let u = (column as Float + rng.gen::<Float>()) / self.width as Float;
+        let v = (row as Float + rng.gen::<Float>()) / self.height as Float;
+        uv_to_direction(u, v)
+    }
+
+    fn index(&self, u: Float, v: Float) -> usize {
+        let column = ((u * self.width as Float) as usize).min(self.width - 1);
+        let row = ((v * self.height as Float) as usize).min(self.height - 1);
+        row * self.width + column
+    }
+}
+
+/// Picks an index from a non-normalized cumulative distribution, given a uniform random number between 0 and 1
+fn sample_cdf(cdf: &[Float], random: Float) -> usize {
+    let target = random * cdf[cdf.len() - 1];
+    cdf.partition_point(|&cumulative| cumulative <= target)
+        .min(cdf.len() - 1)
+}
+
+/// Converts a direction to equirectangular U,V coordinates. V is zero straight up along the Y axis.
+fn direction_to_uv(direction: Vec3) -> (Float, Float) {
+    let direction = direction.normalize();
+    let phi: Float = direction.z.atan2(direction.x);
+    let theta: Float = direction.y.clamp(-1.0, 1.0).acos();
+    let u: Float = (phi + PI) / (2.0 * PI);
+    let v: Float = theta / PI;
+    (u, v)
+}
+
+/// Converts equirectangular U,V coordinates to a unit direction
+fn uv_to_direction(u: Float, v: Float) -> Vec3 {
+    let phi: Float = u * 2.0 * PI - PI;
+    let theta: Float = v * PI;
+    Vec3::new(
+        theta.sin() * phi.cos(),
+        theta.cos(),
+        theta.sin() * phi.sin(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLES: usize = 200_000;
+
+    /// A small map with a bright pixel, a dim row and a range of colors, but no black pixels
+    fn non_uniform_map() -> EnvironmentMap {
+        let pixels = [
+            0.1, 0.1, 0.2, 0.1, //
+            0.5, 20.0, 1.0, 0.3, //
+            2.0, 0.4, 0.05, 4.0,
+        ]
+        .iter()
+        .enumerate()
+        .map(|(index, &value)| Color::new(value, value * (index % 3) as Float, 0.5 * value))
+        .collect();
+        EnvironmentMap::new(4, 3, pixels).unwrap()
+    }
+
+    #[test]
+    fn pdf_integrates_to_one() {
+        let map = non_uniform_map();
+        let mut rng = RandomGenerator::seed_from_u64(0);
+        // Uniform directions over the sphere, with the density 1 / 4π
+        let mut sum = 0.0;
+        for _ in 0..SAMPLES {
+            let z: Float = 1.0 - 2.0 * rng.gen::<Float>();
+            let phi = 2.0 * PI * rng.gen::<Float>();
+            let r = (1.0 - z * z).max(0.0).sqrt();
+            let direction = Vec3::new(r * phi.cos(), r * phi.sin(), z);
+            sum += map.pdf_value(direction) * 4.0 * PI;
+        }
+        let integral = sum / SAMPLES as Float;
+        assert!((integral - 1.0).abs() < 0.02, "{}", integral);
+    }
+
+    #[test]
+    fn pdf_matches_random() {
+        let map = non_uniform_map();
+        let mut rng = RandomGenerator::seed_from_u64(0);
+        // Estimates the area of the sphere, 4π, from the sampled directions weighted by their density
+        let mut sum = 0.0;
+        for _ in 0..SAMPLES {
+            let direction = map.random(&mut rng);
+            let pdf = map.pdf_value(direction);
+            assert!(pdf > 0.0);
+            sum += 1.0 / pdf;
+        }
+        let area = sum / SAMPLES as Float;
+        assert!((area / (4.0 * PI) - 1.0).abs() < 0.02, "{}", area);
+    }
+}
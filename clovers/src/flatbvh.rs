@@ -0,0 +1,427 @@
+//! Flattened Bounding Volume Hierarchy.
+
+use smallvec::SmallVec;
+use std::{collections::HashMap, sync::Arc};
+
+use crate::{
+    aabb::AABB,
+    bvhnode::{BVHNode, BVHStats, SAH_INTERSECTION_COST, SAH_TRAVERSAL_COST},
+    hitable::{HitRecord, Hitable},
+    ray::Ray,
+    Float, RandomGenerator,
+};
+
+/// Number of nodes the traversal stack holds without allocating. Deeper trees are still supported, the stack grows onto the heap.
+const STACK_SIZE: usize = 64;
+
+/// A [BVHNode] tree flattened into a linear array of nodes in depth-first order. The traversal uses an explicit stack instead of recursing through [Hitable], and visits the child nearer to the ray origin first, so that farther nodes can often be skipped.
+pub struct FlatBVH {
+    nodes: Vec<LinearNode>,
+    objects: Vec<Arc<Hitable>>,
+    /// Identifier of each object, see [with_object_ids()](FlatBVH::with_object_ids)
+    object_ids: Vec<usize>,
+    depth: usize,
+}
+
+/// The work done by a traversal of a [FlatBVH]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct TraversalCost {
+    /// Number of nodes whose bounding box was tested
+    pub nodes: u32,
+    /// Number of objects tested for a hit. Objects with their own internal trees, like a [Mesh](crate::objects::Mesh), count as a single object.
+    pub objects: u32,
+}
+
+impl TraversalCost {
+    /// Returns the cost in the units of the surface area heuristic, see [BVHStats]
+    pub fn sah_cost(&self) -> Float {
+        SAH_TRAVERSAL_COST * self.nodes as Float + SAH_INTERSECTION_COST * self.objects as Float
+    }
+}
+
+/// A node of a [FlatBVH]. Interior nodes have their first child directly after them in the array.
+struct LinearNode {
+    bounding_box: AABB,
+    /// For leaves: index of the first object. For interior nodes: index of the second child.
+    offset: u32,
+    /// Number of objects in a leaf, zero for interior nodes
+    count: u16,
+    /// Axis along which the children of an interior node are ordered: the first child is on the lower side
+    axis: u8,
+}
+
+impl FlatBVH {
+    /// Flattens a [BVHNode] tree. The objects in the tree are shared, not copied.
+    pub fn new(bvhnode: &BVHNode, time_0: Float, time_1: Float) -> FlatBVH {
+        let mut flat = FlatBVH {
+            nodes: Vec::new(),
+            objects: Vec::new(),
+            object_ids: Vec::new(),
+            depth: 0,
+        };
+        flat.flatten_node(bvhnode, 1, time_0, time_1);
+        flat.object_ids = (0..flat.objects.len()).collect();
+        flat
+    }
+
+    /// Sets the identifiers of the objects to their indices in the given list, e.g. the order of the objects in the scene file. By default, the objects are numbered in the order of the tree. Objects not in the list keep their identifiers.
+    pub fn with_object_ids(mut self, objects: &[Arc<Hitable>]) -> FlatBVH {
+        let ids: HashMap<*const Hitable, usize> = objects
+            .iter()
+            .enumerate()
+            .map(|(id, object)| (Arc::as_ptr(object), id))
+            .collect();
+        for (object, object_id) in self.objects.iter().zip(self.object_ids.iter_mut()) {
+            if let Some(&id) = ids.get(&Arc::as_ptr(object)) {
+                *object_id = id;
+            }
+        }
+        self
+    }
+
+    fn flatten_node(&mut self, bvhnode: &BVHNode, depth: usize, time_0: Float, time_1: Float) {
+        self.depth = self.depth.max(depth);
+        if Arc::ptr_eq(&bvhnode.left, &bvhnode.right) {
+            // Single object node, see BVHNode::from_list
+            self.flatten_child(&bvhnode.left, depth, time_0, time_1);
+            return;
+        }
+
+        let index = self.nodes.len();
+        self.nodes.push(LinearNode {
+            bounding_box: bvhnode.bounding_box,
+            offset: 0,
+            count: 0,
+            axis: 0,
+        });
+
+        // Order the children along the axis where their centers are the farthest apart
+        let box_left = child_box(&bvhnode.left, time_0, time_1);
+        let box_right = child_box(&bvhnode.right, time_0, time_1);
+        let difference = box_right.centroid() - box_left.centroid();
+        let axis: usize = (0..3)
+            .max_by(|&a, &b| difference[a].abs().total_cmp(&difference[b].abs()))
+            .unwrap_or(0);
+        let (first, second) = if difference[axis] >= 0.0 {
+            (&bvhnode.left, &bvhnode.right)
+        } else {
+            (&bvhnode.right, &bvhnode.left)
+        };
+
+        self.flatten_child(first, depth + 1, time_0, time_1);
+        let second_index = self.nodes.len();
+        self.flatten_child(second, depth + 1, time_0, time_1);
+
+        let node = &mut self.nodes[index];
+        node.offset = second_index as u32;
+        node.axis = axis as u8;
+    }
+
+    fn flatten_child(&mut self, child: &Arc<Hitable>, depth: usize, time_0: Float, time_1: Float) {
+        match &**child {
+            Hitable::BVHNode(bvhnode) => self.flatten_node(bvhnode, depth, time_0, time_1),
+            // Leaves of multiple objects, see BVHNode::from_list_sah
+            Hitable::HitableList(list) => {
+                self.depth = self.depth.max(depth);
+                self.push_leaf(child_box(child, time_0, time_1), &list.0);
+            }
+            _ => {
+                self.depth = self.depth.max(depth);
+                self.push_leaf(
+                    child_box(child, time_0, time_1),
+                    std::slice::from_ref(child),
+                );
+            }
+        }
+    }
+
+    fn push_leaf(&mut self, bounding_box: AABB, objects: &[Arc<Hitable>]) {
+        self.nodes.push(LinearNode {
+            bounding_box,
+            offset: self.objects.len() as u32,
+            count: objects.len() as u16,
+            axis: 0,
+        });
+        self.objects.extend(objects.iter().cloned());
+    }
+
+    pub fn hit(
+        &self,
+        ray: &Ray,
+        distance_min: Float,
+        distance_max: Float,
+        rng: &mut RandomGenerator,
+    ) -> Option<HitRecord<'_>> {
+        self.hit_object(ray, distance_min, distance_max, rng)
+            .map(|(_id, hit_record)| hit_record)
+    }
+
+    /// Like [hit()](FlatBVH::hit), but also returns the identifier of the object that was hit
+    pub fn hit_object(
+        &self,
+        ray: &Ray,
+        distance_min: Float,
+        distance_max: Float,
+        rng: &mut RandomGenerator,
+    ) -> Option<(usize, HitRecord<'_>)> {
+        self.traverse(
+            ray,
+            distance_min,
+            distance_max,
+            &mut TraversalCost::default(),
+            rng,
+        )
+    }
+
+    /// Like [hit()](FlatBVH::hit), but also returns the work done for finding the hit
+    pub fn hit_with_cost(
+        &self,
+        ray: &Ray,
+        distance_min: Float,
+        distance_max: Float,
+        rng: &mut RandomGenerator,
+    ) -> (Option<HitRecord<'_>>, TraversalCost) {
+        let mut cost = TraversalCost::default();
+        let hit = self.traverse(ray, distance_min, distance_max, &mut cost, rng);
+        (hit.map(|(_id, hit_record)| hit_record), cost)
+    }
+
+    /// Finds the closest hit, counting the visited nodes and tested objects in `cost`
+    fn traverse(
+        &self,
+        ray: &Ray,
+        distance_min: Float,
+        mut distance_max: Float,
+        cost: &mut TraversalCost,
+        rng: &mut RandomGenerator,
+    ) -> Option<(usize, HitRecord<'_>)> {
+        let mut closest: Option<(usize, HitRecord)> = None;
+        let mut stack: SmallVec<[u32; STACK_SIZE]> = SmallVec::new();
+        let mut index: usize = 0;
+
+        loop {
+            let node = &self.nodes[index];
+            cost.nodes += 1;
+            if node.bounding_box.hit(ray, distance_min, distance_max) {
+                if node.count > 0 {
+                    // Leaf: test all the objects, shrinking the search distance on every hit
+                    let first = node.offset as usize;
+                    let last = first + node.count as usize;
+                    cost.objects += node.count as u32;
+                    for index in first..last {
+                        let object = &self.objects[index];
+                        if let Some(hit_record) = object.hit(ray, distance_min, distance_max, rng) {
+                            distance_max = hit_record.distance;
+                            closest = Some((self.object_ids[index], hit_record));
+                        }
+                    }
+                } else {
+                    // Interior node: visit the nearer child first, push the farther child on the stack
+                    let first = index as u32 + 1;
+                    let second = node.offset;
+                    let (near, far) = if ray.direction[node.axis as usize] < 0.0 {
+                        (second, first)
+                    } else {
+                        (first, second)
+                    };
+                    stack.push(far);
+                    index = near as usize;
+                    continue;
+                }
+            }
+
+            match stack.pop() {
+                Some(next) => index = next as usize,
+                None => break,
+            }
+        }
+
+        closest
+    }
+
+    /// Returns the product of the transmittances of the objects along the ray, see [Hitable::transmittance()]. Unlike the search for the closest hit, every object along the ray is visited, until one blocks the light.
+    pub fn transmittance(
+        &self,
+        ray: &Ray,
+        distance_min: Float,
+        distance_max: Float,
+        rng: &mut RandomGenerator,
+    ) -> Float {
+        let mut transmittance = 1.0;
+        let mut stack: SmallVec<[u32; STACK_SIZE]> = SmallVec::new();
+        let mut index: usize = 0;
+
+        loop {
+            let node = &self.nodes[index];
+            if node.bounding_box.hit(ray, distance_min, distance_max) {
+                if node.count > 0 {
+                    let first = node.offset as usize;
+                    let last = first + node.count as usize;
+                    for object in self.objects[first..last].iter() {
+                        transmittance *= object.transmittance(ray, distance_min, distance_max, rng);
+                        if transmittance <= 0.0 {
+                            return 0.0;
+                        }
+                    }
+                } else {
+                    stack.push(node.offset);
+                    index += 1;
+                    continue;
+                }
+            }
+
+            match stack.pop() {
+                Some(next) => index = next as usize,
+                None => break,
+            }
+        }
+
+        transmittance
+    }
+
+    pub fn bounding_box(&self, _t0: Float, _t1: Float) -> Option<AABB> {
+        Some(self.nodes[0].bounding_box)
+    }
+
+    /// Returns statistics about the quality of the tree. Objects with their own internal trees, like a [Mesh](crate::objects::Mesh), count as a single object.
+    pub fn stats(&self) -> BVHStats {
+        let root_area = self.nodes[0].bounding_box.surface_area();
+        let mut stats = BVHStats {
+            nodes: 0,
+            leaves: 0,
+            objects: self.objects.len(),
+            depth: self.depth,
+            min_leaf_size: usize::MAX,
+            max_leaf_size: 0,
+            sah_cost: 0.0,
+        };
+        for node in self.nodes.iter() {
+            let area = node.bounding_box.surface_area() / root_area;
+            if node.count > 0 {
+                let size = node.count as usize;
+                stats.leaves += 1;
+                stats.min_leaf_size = stats.min_leaf_size.min(size);
+                stats.max_leaf_size = stats.max_leaf_size.max(size);
+                stats.sah_cost += SAH_INTERSECTION_COST * size as Float * area;
+            } else {
+                stats.nodes += 1;
+                stats.sah_cost += SAH_TRAVERSAL_COST * area;
+            }
+        }
+        stats
+    }
+}
+
+fn child_box(child: &Hitable, time_0: Float, time_1: Float) -> AABB {
+    match child.bounding_box(time_0, time_1) {
+        Some(bounding_box) => bounding_box,
+        None => panic!("No bounding box in FlatBVH constructor."),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{bvhnode::SplitMethod, materials::Material, objects::Sphere, Vec3};
+    use rand::prelude::*;
+
+    fn random_spheres(count: usize, rng: &mut RandomGenerator) -> Vec<Arc<Hitable>> {
+        (0..count)
+            .map(|_| {
+                let center = Vec3::new(
+                    rng.gen_range(-10.0, 10.0),
+                    rng.gen_range(-10.0, 10.0),
+                    rng.gen_range(-10.0, 10.0),
+                );
+                let radius = rng.gen_range(0.1, 1.0);
+                Arc::new(Sphere::new(center, radius, Material::default()))
+            })
+            .collect()
+    }
+
+    fn random_ray(rng: &mut RandomGenerator) -> Ray {
+        let origin = Vec3::new(
+            rng.gen_range(-15.0, 15.0),
+            rng.gen_range(-15.0, 15.0),
+            rng.gen_range(-15.0, 15.0),
+        );
+        let target = Vec3::new(
+            rng.gen_range(-10.0, 10.0),
+            rng.gen_range(-10.0, 10.0),
+            rng.gen_range(-10.0, 10.0),
+        );
+        Ray::new(origin, target - origin, 0.0)
+    }
+
+    #[test]
+    fn hit_agrees_with_bvhnode() {
+        let mut rng = RandomGenerator::seed_from_u64(0);
+        for split_method in [SplitMethod::Median, SplitMethod::Sah].iter() {
+            let objects = random_spheres(200, &mut rng);
+            let bvhnode = BVHNode::new(objects, 0.0, 1.0, *split_method, &mut rng);
+            let flat = FlatBVH::new(&bvhnode, 0.0, 1.0);
+            let mut hits = 0;
+            for _ in 0..1000 {
+                let ray = random_ray(&mut rng);
+                let expected = bvhnode.hit(&ray, 0.001, Float::INFINITY, &mut rng);
+                let actual = flat.hit(&ray, 0.001, Float::INFINITY, &mut rng);
+                match (expected, actual) {
+                    (Some(expected), Some(actual)) => {
+                        hits += 1;
+                        assert!((expected.distance - actual.distance).abs() < 1e-5);
+                        assert!((expected.position - actual.position).norm() < 1e-4);
+                    }
+                    (None, None) => {}
+                    (expected, actual) => panic!(
+                        "BVHNode hit {:?}, FlatBVH hit {:?}",
+                        expected.map(|hit| hit.distance),
+                        actual.map(|hit| hit.distance)
+                    ),
+                }
+            }
+            assert!(hits > 0);
+        }
+    }
+
+    #[test]
+    fn deeper_than_inline_stack() {
+        // A degenerate chain of nodes, each with one sphere on the left and the rest of the chain on the right
+        let mut rng = RandomGenerator::seed_from_u64(0);
+        let count = 2 * STACK_SIZE;
+        let spheres: Vec<Arc<Hitable>> = (0..count)
+            .map(|i| {
+                let center = Vec3::new(i as Float * 3.0, 0.0, 0.0);
+                Arc::new(Sphere::new(center, 1.0, Material::default()))
+            })
+            .collect();
+        let mut node = Arc::clone(&spheres[count - 1]);
+        for sphere in spheres.iter().rev().skip(1) {
+            let bounding_box = AABB::surrounding_box(
+                sphere.bounding_box(0.0, 1.0).unwrap(),
+                node.bounding_box(0.0, 1.0).unwrap(),
+            );
+            node = Arc::new(Hitable::BVHNode(BVHNode {
+                left: Arc::clone(sphere),
+                right: node,
+                bounding_box,
+            }));
+        }
+        let bvhnode = match &*node {
+            Hitable::BVHNode(bvhnode) => bvhnode,
+            _ => unreachable!(),
+        };
+
+        let flat = FlatBVH::new(bvhnode, 0.0, 1.0);
+        assert!(flat.stats().depth > STACK_SIZE);
+        // Looking down the chain from the far end, the nearest sphere is the last one
+        let ray = Ray::new(
+            Vec3::new(count as Float * 3.0 + 10.0, 0.0, 0.0),
+            Vec3::new(-1.0, 0.0, 0.0),
+            0.0,
+        );
+        let hit_record = flat
+            .hit(&ray, 0.001, Float::INFINITY, &mut rng)
+            .expect("ray along the chain should hit");
+        assert!((hit_record.position.x - ((count - 1) as Float * 3.0 + 1.0)).abs() < 1e-3);
+    }
+}
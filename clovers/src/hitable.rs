@@ -2,14 +2,15 @@
 
 use crate::{
     aabb::AABB,
-    bvhnode::BVHNode,
+    bvhnode::{BVHNode, SplitMethod},
+    flatbvh::FlatBVH,
     materials::Material,
     objects::{
-        Boxy, ConstantMedium, FlipFace, MovingSphere, RotateY, Sphere, Translate, XYRect, XZRect,
-        YZRect,
+        Boxy, ConstantMedium, FlipFace, GridMedium, Instance, KeyframedTransform, Mesh,
+        MovingSphere, RotateY, Sphere, Transform, Translate, Triangle, XYRect, XZRect, YZRect,
     },
     ray::Ray,
-    Float, Vec3,
+    Float, RandomGenerator, Vec3,
 };
 use rand::prelude::*;
 
@@ -45,12 +46,26 @@ impl<'a> HitRecord<'a> {
     }
 }
 
+/// A point sampled uniformly on the surface of an object, see [Hitable::sample_surface()]
+#[derive(Copy, Clone, Debug)]
+pub struct SurfaceSample {
+    /// 3D coordinate of the point
+    pub position: Vec3,
+    /// Outward unit normal of the surface at the point
+    pub normal: Vec3,
+    /// Total surface area of the object. The density of the sample is `1.0 / area` per unit area.
+    pub area: Float,
+}
+
 /// An abstraction for things that can be hit by [Rays](crate::ray::Ray).
 ///
 /// TODO: ideally, for cleaner abstraction, this could be a Trait. However, the performance implications might need deeper investigation and consideration...
 pub enum Hitable {
     Boxy(Boxy),
     ConstantMedium(ConstantMedium),
+    GridMedium(GridMedium),
+    Instance(Instance),
+    KeyframedTransform(KeyframedTransform),
     MovingSphere(MovingSphere),
     XZRect(XZRect),
     XYRect(XYRect),
@@ -58,9 +73,13 @@ pub enum Hitable {
     RotateY(RotateY),
     Sphere(Sphere),
     Translate(Translate),
+    Transform(Transform),
     BVHNode(BVHNode),
+    FlatBVH(FlatBVH),
     HitableList(HitableList),
     FlipFace(FlipFace),
+    Triangle(Triangle),
+    Mesh(Mesh),
 }
 
 impl Hitable {
@@ -69,11 +88,14 @@ impl Hitable {
         ray: &Ray,
         distance_min: Float,
         distance_max: Float,
-        rng: ThreadRng,
+        rng: &mut RandomGenerator,
     ) -> Option<HitRecord> {
         match self {
             Hitable::Boxy(h) => h.hit(ray, distance_min, distance_max, rng),
             Hitable::ConstantMedium(h) => h.hit(ray, distance_min, distance_max, rng),
+            Hitable::GridMedium(h) => h.hit(ray, distance_min, distance_max, rng),
+            Hitable::Instance(h) => h.hit(ray, distance_min, distance_max, rng),
+            Hitable::KeyframedTransform(h) => h.hit(ray, distance_min, distance_max, rng),
             Hitable::MovingSphere(h) => h.hit(ray, distance_min, distance_max, rng),
             Hitable::XZRect(h) => h.hit(ray, distance_min, distance_max, rng),
             Hitable::XYRect(h) => h.hit(ray, distance_min, distance_max, rng),
@@ -81,9 +103,13 @@ impl Hitable {
             Hitable::RotateY(h) => h.hit(ray, distance_min, distance_max, rng),
             Hitable::Sphere(h) => h.hit(ray, distance_min, distance_max, rng),
             Hitable::Translate(h) => h.hit(ray, distance_min, distance_max, rng),
+            Hitable::Transform(h) => h.hit(ray, distance_min, distance_max, rng),
             Hitable::BVHNode(h) => h.hit(ray, distance_min, distance_max, rng),
+            Hitable::FlatBVH(h) => h.hit(ray, distance_min, distance_max, rng),
             Hitable::HitableList(h) => h.hit(ray, distance_min, distance_max, rng),
             Hitable::FlipFace(h) => h.hit(ray, distance_min, distance_max, rng),
+            Hitable::Triangle(h) => h.hit(ray, distance_min, distance_max, rng),
+            Hitable::Mesh(h) => h.hit(ray, distance_min, distance_max, rng),
         }
     }
 
@@ -91,6 +117,9 @@ impl Hitable {
         match self {
             Hitable::Boxy(h) => h.bounding_box(t0, t1),
             Hitable::ConstantMedium(h) => h.bounding_box(t0, t1),
+            Hitable::GridMedium(h) => h.bounding_box(t0, t1),
+            Hitable::Instance(h) => h.bounding_box(t0, t1),
+            Hitable::KeyframedTransform(h) => h.bounding_box(t0, t1),
             Hitable::MovingSphere(h) => h.bounding_box(t0, t1),
             Hitable::XZRect(h) => h.bounding_box(t0, t1),
             Hitable::XYRect(h) => h.bounding_box(t0, t1),
@@ -98,34 +127,81 @@ impl Hitable {
             Hitable::RotateY(h) => h.bounding_box(t0, t1),
             Hitable::Sphere(h) => h.bounding_box(t0, t1),
             Hitable::Translate(h) => h.bounding_box(t0, t1),
+            Hitable::Transform(h) => h.bounding_box(t0, t1),
             Hitable::BVHNode(h) => h.bounding_box(t0, t1),
+            Hitable::FlatBVH(h) => h.bounding_box(t0, t1),
             Hitable::HitableList(h) => h.bounding_box(t0, t1),
             Hitable::FlipFace(h) => h.bounding_box(t0, t1),
+            Hitable::Triangle(h) => h.bounding_box(t0, t1),
+            Hitable::Mesh(h) => h.bounding_box(t0, t1),
+        }
+    }
+
+    /// Estimates the fraction of light passing along the ray within the given range, for shadow rays. Surfaces block the light completely, while [GridMedium] volumes are passed through with ratio tracking. Other objects are tested for a hit, so e.g. a medium inside a [Transform] either blocks the light or lets it through.
+    pub fn transmittance(
+        &self,
+        ray: &Ray,
+        distance_min: Float,
+        distance_max: Float,
+        rng: &mut RandomGenerator,
+    ) -> Float {
+        match self {
+            Hitable::GridMedium(h) => h.transmittance(ray, distance_min, distance_max, rng),
+            Hitable::BVHNode(h) => h.transmittance(ray, distance_min, distance_max, rng),
+            Hitable::FlatBVH(h) => h.transmittance(ray, distance_min, distance_max, rng),
+            Hitable::HitableList(h) => h.transmittance(ray, distance_min, distance_max, rng),
+            _ => match self.hit(ray, distance_min, distance_max, rng) {
+                Some(_) => 0.0,
+                None => 1.0,
+            },
         }
     }
 
-    pub fn pdf_value(&self, origin: Vec3, vector: Vec3, time: Float, rng: ThreadRng) -> Float {
+    pub fn pdf_value(
+        &self,
+        origin: Vec3,
+        vector: Vec3,
+        time: Float,
+        rng: &mut RandomGenerator,
+    ) -> Float {
         match self {
             Hitable::XZRect(h) => h.pdf_value(origin, vector, time, rng),
             Hitable::XYRect(h) => h.pdf_value(origin, vector, time, rng),
             Hitable::YZRect(h) => h.pdf_value(origin, vector, time, rng),
             Hitable::HitableList(h) => h.pdf_value(origin, vector, time, rng),
             Hitable::Sphere(h) => h.pdf_value(origin, vector, time, rng),
+            Hitable::Triangle(h) => h.pdf_value(origin, vector, time, rng),
+            Hitable::Mesh(h) => h.pdf_value(origin, vector, time, rng),
             _ => 0.0,
         }
     }
 
-    pub fn random(&self, origin: Vec3, rng: ThreadRng) -> Vec3 {
+    pub fn random(&self, origin: Vec3, rng: &mut RandomGenerator) -> Vec3 {
         match self {
             Hitable::XZRect(h) => h.random(origin, rng),
             Hitable::XYRect(h) => h.random(origin, rng),
             Hitable::YZRect(h) => h.random(origin, rng),
             Hitable::HitableList(h) => h.random(origin, rng),
             Hitable::Sphere(h) => h.random(origin, rng),
+            Hitable::Triangle(h) => h.random(origin, rng),
+            Hitable::Mesh(h) => h.random(origin, rng),
             _ => Vec3::new(1.0, 0.0, 0.0),
         }
     }
 
+    /// Samples a point uniformly on the surface of the object. Used for starting light paths from emissive objects. Returns None for objects that don't support area sampling.
+    pub fn sample_surface(&self, rng: &mut RandomGenerator) -> Option<SurfaceSample> {
+        match self {
+            Hitable::XZRect(h) => Some(h.sample_surface(rng)),
+            Hitable::XYRect(h) => Some(h.sample_surface(rng)),
+            Hitable::YZRect(h) => Some(h.sample_surface(rng)),
+            Hitable::Sphere(h) => Some(h.sample_surface(rng)),
+            Hitable::Triangle(h) => Some(h.sample_surface(rng)),
+            Hitable::FlipFace(h) => h.sample_surface(rng),
+            _ => None,
+        }
+    }
+
     pub fn add(&mut self, object: Hitable) {
         match self {
             Hitable::HitableList(h) => h.add(object),
@@ -149,7 +225,7 @@ impl HitableList {
         ray: &Ray,
         distance_min: Float,
         distance_max: Float,
-        rng: ThreadRng,
+        rng: &mut RandomGenerator,
     ) -> Option<HitRecord> {
         let mut hit_record: Option<HitRecord> = None;
         let mut closest = distance_max;
@@ -163,6 +239,24 @@ impl HitableList {
         hit_record
     }
 
+    /// Returns the product of the transmittances of the objects, see [Hitable::transmittance()]
+    pub fn transmittance(
+        &self,
+        ray: &Ray,
+        distance_min: Float,
+        distance_max: Float,
+        rng: &mut RandomGenerator,
+    ) -> Float {
+        let mut transmittance = 1.0;
+        for hitable in self.0.iter() {
+            transmittance *= hitable.transmittance(ray, distance_min, distance_max, rng);
+            if transmittance <= 0.0 {
+                return 0.0;
+            }
+        }
+        transmittance
+    }
+
     pub fn bounding_box(&self, t0: Float, t1: Float) -> Option<AABB> {
         if self.0.is_empty() {
             return None;
@@ -196,7 +290,13 @@ impl HitableList {
 
         output_box
     }
-    pub fn pdf_value(&self, origin: Vec3, vector: Vec3, time: Float, rng: ThreadRng) -> Float {
+    pub fn pdf_value(
+        &self,
+        origin: Vec3,
+        vector: Vec3,
+        time: Float,
+        rng: &mut RandomGenerator,
+    ) -> Float {
         let weight = 1.0 / self.0.len() as Float;
         let mut sum = 0.0;
 
@@ -207,7 +307,7 @@ impl HitableList {
         sum
     }
 
-    pub fn random(&self, origin: Vec3, mut rng: ThreadRng) -> Vec3 {
+    pub fn random(&self, origin: Vec3, rng: &mut RandomGenerator) -> Vec3 {
         let int_size = self.0.len();
         self.0[rng.gen_range(0, int_size)].random(origin, rng)
     }
@@ -220,9 +320,16 @@ impl HitableList {
         self.0.push(Arc::new(object));
     }
 
-    pub fn into_bvh(self, time_0: Float, time_1: Float, rng: ThreadRng) -> Hitable {
-        let bvh_node = BVHNode::from_list(self.0, time_0, time_1, rng);
-        Hitable::BVHNode(bvh_node)
+    pub fn into_bvh(
+        self,
+        time_0: Float,
+        time_1: Float,
+        split_method: SplitMethod,
+        rng: &mut RandomGenerator,
+    ) -> Hitable {
+        let bvh_node = BVHNode::new(self.0.clone(), time_0, time_1, split_method, rng);
+        let flat = FlatBVH::new(&bvh_node, time_0, time_1).with_object_ids(&self.0);
+        Hitable::FlatBVH(flat)
     }
 
     // TODO: fixme, silly
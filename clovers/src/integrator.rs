@@ -0,0 +1,183 @@
+//! Integrators: the ways of turning a camera [Ray] into a color. Besides the [path tracer](crate::colorize) and the [bidirectional path tracer](crate::bidirectional), there are debug integrators that show a single property of the scene at the first hit. They are quick to converge, for diagnosing broken scenes without waiting for a full render.
+
+use crate::{
+    aov::first_hit,
+    bidirectional::{bidirectional, Splat},
+    color::Color,
+    colorize::{colorize_with_stats, PathStats},
+    hitable::Hitable,
+    onb::ONB,
+    random::random_cosine_direction,
+    ray::Ray,
+    scenes::Scene,
+    Float, RandomGenerator, Vec3, EPSILON_SHADOW_ACNE,
+};
+use std::{fmt, str::FromStr};
+
+/// Default maximum distance of the occluders found by the [AmbientOcclusion](Integrator::AmbientOcclusion) integrator
+pub const DEFAULT_AO_DISTANCE: Float = 100.0;
+
+/// Traversal cost at which the [BvhCost](Integrator::BvhCost) heatmap reaches the middle of its color ramp, in the units of the surface area heuristic
+const BVH_COST_SCALE: Float = 16.0;
+
+/// The available integrators
+#[derive(Copy, Clone, Debug, PartialEq, Default)]
+pub enum Integrator {
+    /// Physically based rendering with the path tracer of [colorize](crate::colorize)
+    #[default]
+    PathTracer,
+    /// Physically based rendering with the bidirectional path tracer of [bidirectional](crate::bidirectional). Converges faster for scenes lit by small or hidden lights, and for caustics.
+    Bidirectional,
+    /// Shading normal at the first hit, remapped from `-1.0..=1.0` to `0.0..=1.0`. Black for rays that miss.
+    Normals,
+    /// Texture coordinates at the first hit, in the red and green channels. Black for rays that miss.
+    Uv,
+    /// Color of the material at the first hit, ignoring lighting. The environment color for rays that miss.
+    Albedo,
+    /// Fraction of the hemisphere above the first hit that is not occluded by other objects within the given distance. Black for rays that miss.
+    AmbientOcclusion { distance: Float },
+    /// Heatmap of the work done for finding the first hit in the bounding volume hierarchy of the scene: from black for cheap rays through blue, red and yellow to white for expensive ones.
+    BvhCost,
+}
+
+impl Integrator {
+    /// Name of the integrator, as accepted on the command line
+    pub fn name(&self) -> &'static str {
+        match self {
+            Integrator::PathTracer => "path",
+            Integrator::Bidirectional => "bdpt",
+            Integrator::Normals => "normals",
+            Integrator::Uv => "uv",
+            Integrator::Albedo => "albedo",
+            Integrator::AmbientOcclusion { .. } => "ao",
+            Integrator::BvhCost => "bvh-cost",
+        }
+    }
+
+    /// Whether the integrator renders a debug view of the scene instead of its lighting. The colors of debug views are data: they should be saved without tone mapping or gamma correction.
+    pub fn is_debug(&self) -> bool {
+        !matches!(self, Integrator::PathTracer | Integrator::Bidirectional)
+    }
+
+    /// Returns the color for the camera ray. The path tracers record the length of their camera paths in the statistics. The bidirectional path tracer traces one light path for each sample, and pushes the light it finds for other points of the image into `splats`.
+    pub fn sample(
+        &self,
+        ray: &Ray,
+        scene: &Scene,
+        max_depth: u32,
+        stats: &mut PathStats,
+        splats: &mut Vec<Splat>,
+        rng: &mut RandomGenerator,
+    ) -> Color {
+        match *self {
+            Integrator::PathTracer => colorize_with_stats(ray, scene, max_depth, stats, rng),
+            Integrator::Bidirectional => bidirectional(ray, scene, max_depth, stats, splats, rng),
+            Integrator::Normals => {
+                let hit = first_hit(ray, scene, rng);
+                if hit.depth.is_infinite() {
+                    return Color::new(0.0, 0.0, 0.0);
+                }
+                let normal = 0.5 * (hit.normal.normalize() + Vec3::new(1.0, 1.0, 1.0));
+                Color::new(normal.x, normal.y, normal.z)
+            }
+            Integrator::Uv => {
+                let hit = first_hit(ray, scene, rng);
+                Color::new(hit.uv.0, hit.uv.1, 0.0)
+            }
+            Integrator::Albedo => first_hit(ray, scene, rng).albedo,
+            Integrator::AmbientOcclusion { distance } => {
+                ambient_occlusion(ray, scene, distance, rng)
+            }
+            Integrator::BvhCost => bvh_cost(ray, scene, rng),
+        }
+    }
+}
+
+impl FromStr for Integrator {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "path" | "pt" => Ok(Integrator::PathTracer),
+            "bdpt" | "bidirectional" => Ok(Integrator::Bidirectional),
+            "normals" | "normal" => Ok(Integrator::Normals),
+            "uv" => Ok(Integrator::Uv),
+            "albedo" => Ok(Integrator::Albedo),
+            "ao" => Ok(Integrator::AmbientOcclusion {
+                distance: DEFAULT_AO_DISTANCE,
+            }),
+            "bvh-cost" | "bvh" => Ok(Integrator::BvhCost),
+            _ => Err(format!(
+                "unknown integrator: {}. Valid values: path, bdpt, normals, uv, albedo, ao, bvh-cost",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Integrator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Integrator::AmbientOcclusion { distance } => {
+                write!(f, "{} (distance {})", self.name(), distance)
+            }
+            _ => write!(f, "{}", self.name()),
+        }
+    }
+}
+
+/// Casts a single occlusion ray from the first hit, in a cosine weighted direction around the normal: white if it escapes to the given distance, black if it is blocked
+fn ambient_occlusion(
+    ray: &Ray,
+    scene: &Scene,
+    distance: Float,
+    rng: &mut RandomGenerator,
+) -> Color {
+    let hit_record = match scene.objects.hit(ray, EPSILON_SHADOW_ACNE, Float::MAX, rng) {
+        None => return Color::new(0.0, 0.0, 0.0),
+        Some(hit_record) => hit_record,
+    };
+    let uvw = ONB::build_from_w(hit_record.normal);
+    let direction = uvw.local(random_cosine_direction(rng));
+    let occlusion_ray =
+        Ray::new(hit_record.position, direction, ray.time).with_wavelength(ray.wavelength);
+    match scene
+        .objects
+        .hit(&occlusion_ray, EPSILON_SHADOW_ACNE, distance, rng)
+    {
+        None => Color::new(1.0, 1.0, 1.0),
+        Some(_) => Color::new(0.0, 0.0, 0.0),
+    }
+}
+
+/// Colors the traversal cost of the ray through the top level [FlatBVH](crate::flatbvh::FlatBVH) of the scene. Objects with their own internal trees, like meshes and instances, count as a single object.
+fn bvh_cost(ray: &Ray, scene: &Scene, rng: &mut RandomGenerator) -> Color {
+    let cost = match &scene.objects {
+        Hitable::FlatBVH(bvh) => {
+            let (_hit, cost) = bvh.hit_with_cost(ray, EPSILON_SHADOW_ACNE, Float::MAX, rng);
+            cost.sah_cost()
+        }
+        _ => 0.0,
+    };
+    heat(cost / (cost + BVH_COST_SCALE))
+}
+
+/// A color ramp for the `0.0..=1.0` range: black, blue, red, yellow, white
+fn heat(t: Float) -> Color {
+    const RAMP: [[Float; 3]; 5] = [
+        [0.0, 0.0, 0.0],
+        [0.0, 0.0, 1.0],
+        [1.0, 0.0, 0.0],
+        [1.0, 1.0, 0.0],
+        [1.0, 1.0, 1.0],
+    ];
+    let position = t.clamp(0.0, 1.0) * (RAMP.len() - 1) as Float;
+    let index = (position as usize).min(RAMP.len() - 2);
+    let t = position - index as Float;
+    let (a, b) = (RAMP[index], RAMP[index + 1]);
+    Color::new(
+        a[0] + t * (b[0] - a[0]),
+        a[1] + t * (b[1] - a[1]),
+        a[2] + t * (b[2] - a[2]),
+    )
+}
@@ -39,10 +39,14 @@
 //!
 //! The library provides an opinionated [colorize()](colorize::colorize) function that does the steps mentioned above. Using it is optional - feel free to implement your own methods that utilize the lower-level building blocks for more creative power!
 //!
+//! The [Integrator](integrator::Integrator) enum wraps it together with a [bidirectional path tracer](bidirectional) and debug views of the scene, like shading normals and ambient occlusion.
+//!
 //! ## Post processing
 //!
-//! **TODO:** maybe add some post processing utilities?
-//! - denoise support?
+//! The [postprocess] module has utilities for improving the rendered pixel buffer:
+//! - [denoise()](postprocess::denoise) removes noise from low sample count renders, guided by the auxiliary render passes of the [aov] module
+//!
+//! **TODO:** maybe add more post processing utilities?
 //! - 3D & rendering aware effects?
 //! - etc
 //!
@@ -67,20 +71,30 @@ use nalgebra::Vector3;
 
 // Internals
 pub mod aabb;
+pub mod aov;
+pub mod bidirectional;
 pub mod bvhnode;
 pub mod camera;
+pub mod checkpoint;
 pub mod color;
 pub mod colorize;
+pub mod environment;
+pub mod flatbvh;
 pub mod hitable;
+pub mod integrator;
 pub mod materials;
 pub mod objects;
 pub mod onb;
+pub mod output;
 pub mod pdf;
 pub mod perlin;
+pub mod postprocess;
 pub mod random;
 pub mod ray;
 pub mod scenes;
+pub mod spectrum;
 pub mod textures;
+pub mod tonemap;
 
 // Handy aliases for internal use
 
@@ -88,6 +102,8 @@ pub mod textures;
 pub type Float = f32;
 /// Internal helper: re-exports the pi constant as our internal [Float] type. TODO: selectable at run time instead of build time?
 pub const PI: Float = std::f32::consts::PI;
+/// Internal type alias: the random number generator used everywhere in the renderer. Seedable, so that a render can be reproduced exactly. See [sample_rng](random::sample_rng).
+pub type RandomGenerator = rand_xoshiro::Xoshiro256PlusPlus;
 /// Internal type alias: a nalgebra [Vector3](nalgebra::Vector3) which is a vector with three dimensions, containing three of our internal [Float] types
 pub type Vec3 = Vector3<Float>;
 /// Internal const: epsilon used for avoiding "shadow acne". See e.g. [Raytracing In One Weekend](https://raytracing.github.io/)
@@ -96,3 +112,5 @@ pub const EPSILON_SHADOW_ACNE: Float = 0.001;
 pub const EPSILON_RECT_THICKNESS: Float = 0.0001;
 /// Internal const: epsilon used in the hit calculation of a [ConstantMedium](objects::constant_medium::ConstantMedium)
 pub const EPSILON_CONSTANT_MEDIUM: Float = 0.0001;
+/// Internal const: epsilon used for discarding rays that are parallel to a [Triangle](objects::triangle::Triangle)
+pub const EPSILON_TRIANGLE_DETERMINANT: Float = 1e-8;
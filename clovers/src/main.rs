@@ -4,15 +4,18 @@
 use chrono::Utc;
 use clap::Clap;
 use humantime::format_duration;
-use image::{ImageBuffer, Rgb, RgbImage};
-use std::fs::File;
-use std::{error::Error, fs, time::Instant};
+use std::{error::Error, fs, str::FromStr, time::Instant};
 
 // Internal imports
 use clovers::*;
 mod draw;
-use draw::draw;
+use aov::{Aov, AovBuffers};
+use checkpoint::Checkpoint;
+use draw::{draw, Adaptive, Checkpointing, Integration};
+use integrator::Integrator;
+use postprocess::{denoise, DenoiseSettings, Guides};
 use scenes::Scene;
+use tonemap::{ToneMapOperator, ToneMapping, Transfer};
 
 // Configure CLI parameters
 #[derive(Clap)]
@@ -21,7 +24,7 @@ struct Opts {
     /// Input filename / location
     #[clap(short, long)]
     input: String,
-    /// Output filename / location. [default: renders/timestamp.png]
+    /// Output filename / location. The format is chosen by the file extension: `.exr` and `.hdr` save the linear high dynamic range colors, other formats are saved as 8-bit images. [default: renders/timestamp.png]
     #[clap(short, long)]
     output: Option<String>,
     /// Width of the image in pixels
@@ -30,80 +33,409 @@ struct Opts {
     /// Height of the image in pixels
     #[clap(short, long, default_value = "1024")]
     height: u32,
-    /// Number of samples to generate per each pixel
+    /// Number of samples to generate per each pixel. With adaptive sampling, the maximum number of samples
     #[clap(short, long, default_value = "100")]
     samples: u32,
     /// Maximum evaluated bounce depth for each ray
     #[clap(short, long, default_value = "100")]
     max_depth: u32,
-    /// Gamma correction value
-    #[clap(short, long, default_value = "2.0")]
-    gamma: Float,
+    /// Integrator: path for the physically based path tracer, bdpt for the bidirectional path tracer, or a debug view of the first hits: normals, uv, albedo, ao (ambient occlusion) or bvh-cost (heatmap of the traversal cost of the bounding volume hierarchy). Debug views are saved without tone mapping, unless overridden with the tone mapping options
+    #[clap(long, default_value = "path")]
+    integrator: Integrator,
+    /// Maximum distance of the occluders for the ao integrator [default: 100.0]
+    #[clap(long)]
+    ao_distance: Option<Float>,
+    /// Number of bounces before paths can be terminated with Russian roulette [default: from the scene file, or 3]
+    #[clap(long)]
+    roulette_depth: Option<u32>,
+    /// Disable Russian roulette: trace every path until it escapes, hits a light, or reaches the maximum depth
+    #[clap(long, conflicts_with = "roulette-depth")]
+    no_roulette: bool,
+    /// Gamma correction value. Not used for high dynamic range output formats [default: from the scene file, or 2.0]
+    #[clap(short, long)]
+    gamma: Option<Float>,
+    /// Use the sRGB transfer function instead of a gamma power curve
+    #[clap(long, conflicts_with = "gamma")]
+    srgb: bool,
+    /// Tone mapping operator: clamp, reinhard, extended-reinhard or aces. Not used for high dynamic range output formats [default: from the scene file, or clamp]
+    #[clap(long)]
+    tone_map: Option<ToneMapOperator>,
+    /// Exposure adjustment in stops (EV), applied before tone mapping. High dynamic range output formats are scaled by it too [default: from the scene file, or 0.0]
+    #[clap(long)]
+    exposure: Option<Float>,
+    /// Luminance mapped to pure white by the extended-reinhard operator [default: from the scene file, or 4.0]
+    #[clap(long)]
+    white_point: Option<Float>,
+    /// Spectral rendering: each sample traces a single wavelength, for wavelength-dependent effects like the dispersion of glass. Needs more samples than RGB rendering for the same noise level
+    #[clap(long)]
+    spectral: bool,
+    /// Seed for the random number generator. Renders with the same scene and seed are identical
+    #[clap(long, default_value = "0")]
+    seed: u64,
+    /// Enable adaptive sampling: stop sampling pixels once their estimated relative error is below this threshold, e.g. 0.05
+    #[clap(long)]
+    adaptive: Option<Float>,
+    /// Minimum number of samples per pixel with adaptive sampling
+    #[clap(long, default_value = "16")]
+    min_samples: u32,
+    /// Save a heatmap of the number of samples taken for each pixel to this file
+    #[clap(long)]
+    sample_heatmap: Option<String>,
+    /// Render auxiliary passes from the first hit of the camera rays, separated by commas: albedo, normal, depth, object_id, uv. Saved as extra channels of `.exr` output, otherwise as separate images named `<output>_<aov>.<extension>`
+    #[clap(long, use_delimiter = true)]
+    aov: Vec<Aov>,
+    /// Number of samples per pixel for the auxiliary passes
+    #[clap(long, default_value = "16")]
+    aov_samples: u32,
+    /// Denoise the render with an edge-avoiding filter guided by the albedo, normal and depth of the first hits. Useful for low sample count previews
+    #[clap(long)]
+    denoise: bool,
+    /// Number of passes of the denoising filter. More passes remove lower frequency noise
+    #[clap(long, default_value = "5")]
+    denoise_iterations: u32,
+    /// Print statistics about the bounding volume hierarchy of the scene
+    #[clap(long)]
+    bvh_stats: bool,
+    /// Periodically save the progress of the render to this checkpoint file. When resuming, defaults to the resumed checkpoint file
+    #[clap(long)]
+    checkpoint: Option<String>,
+    /// How often to save the checkpoint file, e.g. `30s` or `5m`
+    #[clap(long, default_value = "5m")]
+    checkpoint_interval: humantime::Duration,
+    /// Render an animation: the frames in the given range, e.g. `0..120` with the end excluded. Saved as a numbered sequence of files, `<output>_<frame>.<extension>`. The shutter interval of each frame comes from the animation settings of the scene file, and each frame uses a different seed
+    #[clap(long, conflicts_with_all = &["checkpoint", "resume"])]
+    frames: Option<FrameRange>,
+    /// Resume the render from a checkpoint file, taking more samples until each pixel has the requested number of samples. The width, height and seed are taken from the checkpoint
+    #[clap(long)]
+    resume: Option<String>,
+}
+
+impl Opts {
+    /// Returns the selected integrator, with the settings given on the command line
+    fn integrator(&self) -> Integrator {
+        match (self.integrator, self.ao_distance) {
+            (Integrator::AmbientOcclusion { .. }, Some(distance)) => {
+                Integrator::AmbientOcclusion { distance }
+            }
+            (integrator, _) => integrator,
+        }
+    }
+
+    /// Returns the [fingerprint](checkpoint::fingerprint) of the scene file and the settings that change the samples of the render, for matching checkpoints to the render they were made for
+    fn fingerprint(&self, scene_file: &[u8]) -> u64 {
+        let settings = format!(
+            "integrator={:?} max_depth={} spectral={} roulette_depth={:?} no_roulette={}",
+            self.integrator(),
+            self.max_depth,
+            self.spectral,
+            self.roulette_depth,
+            self.no_roulette
+        );
+        checkpoint::fingerprint(scene_file, &settings)
+    }
+}
+
+/// A range of animation frames, parsed from `start..end` with the end excluded
+#[derive(Copy, Clone, Debug)]
+struct FrameRange {
+    start: u32,
+    end: u32,
+}
+
+impl FrameRange {
+    /// Number of digits in the frame numbers of the file names: enough for the last frame, and at least four
+    fn digits(&self) -> usize {
+        self.end.saturating_sub(1).to_string().len().max(4)
+    }
+}
+
+impl FromStr for FrameRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || format!("invalid frame range {}, expected e.g. 0..120", s);
+        let (start, end) = s.split_once("..").ok_or_else(error)?;
+        let start: u32 = start.trim().parse().map_err(|_| error())?;
+        let end: u32 = end.trim().parse().map_err(|_| error())?;
+        if start >= end {
+            return Err(format!("empty frame range {}", s));
+        }
+        Ok(FrameRange { start, end })
+    }
 }
 
 fn main() -> Result<(), Box<dyn Error>> {
     let opts: Opts = Opts::parse();
+    let integrator = opts.integrator();
+    if opts.spectral && integrator.is_debug() {
+        return Err(format!(
+            "spectral rendering requires the path or bdpt integrator, not {}",
+            integrator
+        )
+        .into());
+    }
+
+    // Start a new render, or continue from where a checkpoint left off. Only continue checkpoints of the same scene file and settings
+    let fingerprint = opts.fingerprint(&fs::read(&opts.input)?);
+    let mut state: Checkpoint = match &opts.resume {
+        Some(path) => Checkpoint::load(path, fingerprint)?,
+        None => Checkpoint::new(opts.width, opts.height, opts.seed, fingerprint),
+    };
+    let width = state.width;
+    let height = state.height;
 
     println!("clovers 🍀    ray tracing in rust 🦀");
-    println!("width:        {}", opts.width);
-    println!("height:       {}", opts.height);
+    if let Some(path) = &opts.resume {
+        println!("resuming:     {}", path);
+    }
+    println!("width:        {}", width);
+    println!("height:       {}", height);
     println!("samples:      {}", opts.samples);
+    if let Some(threshold) = opts.adaptive {
+        println!("adaptive:     {}", threshold);
+        println!("min samples:  {}", opts.min_samples);
+    }
+    println!("integrator:   {}", integrator);
     println!("max depth:    {}", opts.max_depth);
-    let rays: u64 =
-        opts.width as u64 * opts.height as u64 * opts.samples as u64 * opts.max_depth as u64;
+    if opts.denoise {
+        println!("denoise:      {} iterations", opts.denoise_iterations);
+    }
+    if !opts.aov.is_empty() {
+        let names: Vec<&str> = opts.aov.iter().map(|aov| aov.name()).collect();
+        println!("aovs:         {}", names.join(", "));
+    }
+    println!("seed:         {}", state.seed);
+    if let Some(frames) = opts.frames {
+        println!("frames:       {}..{}", frames.start, frames.end);
+    }
+    if opts.spectral {
+        println!("spectral:     {}", opts.spectral);
+    }
+    let rays: u64 = width as u64 * height as u64 * opts.samples as u64 * opts.max_depth as u64;
     println!("approx. rays: {}", rays);
-    println!(); // Empty line before progress bar
 
-    // Read the given scene file
-    let file = File::open(opts.input)?;
-    let scene: Scene = scenes::initialize(file, opts.width, opts.height)?;
+    // Write to the given file, or default to using a timestamp & `renders/` directory
+    let target: String = match &opts.output {
+        Some(filename) => filename.clone(),
+        None => {
+            let timestamp = Utc::now().timestamp();
+            fs::create_dir_all("renders")?;
+            format!("renders/{}.png", timestamp)
+        }
+    };
+
+    match opts.frames {
+        None => {
+            // Read the given scene file
+            let mut scene: Scene = scenes::initialize(&opts.input, width, height)?;
+            render(
+                &opts,
+                &mut scene,
+                &mut state,
+                &target,
+                opts.sample_heatmap.as_deref(),
+            )
+        }
+        Some(frames) => {
+            let digits = frames.digits();
+            for frame in frames.start..frames.end {
+                println!("frame:        {}", frame);
+                let mut scene: Scene = scenes::initialize_frame(&opts.input, width, height, frame)?;
+                // A different seed for each frame, avoiding a fixed noise pattern over the animation
+                let mut state = Checkpoint::new(
+                    width,
+                    height,
+                    state.seed.wrapping_add(frame as u64),
+                    fingerprint,
+                );
+                let target = output::frame_path(&target, frame, digits);
+                let heatmap = opts
+                    .sample_heatmap
+                    .as_deref()
+                    .map(|path| output::frame_path(path, frame, digits));
+                render(&opts, &mut scene, &mut state, &target, heatmap.as_deref())?;
+                println!();
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Renders a single image of the scene, continuing from the given state, and saves it with its auxiliary passes to the target path
+fn render(
+    opts: &Opts,
+    scene: &mut Scene,
+    state: &mut Checkpoint,
+    target: &str,
+    sample_heatmap: Option<&str>,
+) -> Result<(), Box<dyn Error>> {
+    let width = state.width;
+    let height = state.height;
+
+    // Command line options override the tone mapping settings of the scene file. Debug views are saved as they are.
+    let integrator = opts.integrator();
+    let mut tone_mapping: ToneMapping = if integrator.is_debug() {
+        ToneMapping {
+            transfer: Transfer::Gamma(1.0),
+            ..ToneMapping::default()
+        }
+    } else {
+        scene.tone_mapping
+    };
+    if let Some(operator) = opts.tone_map {
+        tone_mapping.operator = operator;
+    }
+    if let Some(exposure) = opts.exposure {
+        tone_mapping.exposure = exposure;
+    }
+    if let Some(white_point) = opts.white_point {
+        tone_mapping.white_point = white_point;
+    }
+    if let Some(gamma) = opts.gamma {
+        tone_mapping.transfer = Transfer::Gamma(gamma);
+    }
+    if opts.srgb {
+        tone_mapping.transfer = Transfer::Srgb;
+    }
+    println!("tone mapping: {}", tone_mapping.operator);
+    println!("exposure:     {}", tone_mapping.exposure);
+    println!("transfer:     {}", tone_mapping.transfer);
+
+    // Command line options override the Russian roulette settings of the scene file
+    if let Some(depth) = opts.roulette_depth {
+        scene.russian_roulette.min_depth = depth;
+    }
+    if opts.no_roulette {
+        scene.russian_roulette.enabled = false;
+    }
+    if scene.russian_roulette.enabled {
+        println!(
+            "roulette:     after {} bounces",
+            scene.russian_roulette.min_depth
+        );
+    } else {
+        println!("roulette:     disabled");
+    }
+    if opts.bvh_stats {
+        if let hitable::Hitable::FlatBVH(bvh) = &scene.objects {
+            println!("{}", bvh.stats());
+        }
+    }
+    println!(); // Empty line before progress bar
 
     // Note: live progress bar printed within draw
     let start = Instant::now();
-    let pixelbuffer = draw(
-        opts.width,
-        opts.height,
+    let adaptive = opts.adaptive.map(|threshold| Adaptive {
+        threshold,
+        min_samples: opts.min_samples,
+    });
+    let checkpointing = opts
+        .checkpoint
+        .as_deref()
+        .or(opts.resume.as_deref())
+        .map(|path| Checkpointing {
+            path,
+            interval: *opts.checkpoint_interval,
+        });
+    let path_stats = draw(
+        state,
         opts.samples,
-        opts.max_depth,
-        opts.gamma,
         scene,
-    );
-
-    // Translate our internal pixelbuffer into an Image buffer
-    let width = opts.width;
-    let height = opts.height;
-    let mut img: RgbImage = ImageBuffer::new(width, height);
-    img.enumerate_pixels_mut().for_each(|(x, y, pixel)| {
-        let index = y * width + x;
-        *pixel = Rgb(pixelbuffer[index as usize].to_rgb_u8());
-    });
+        Integration {
+            integrator,
+            max_depth: opts.max_depth,
+            spectral: opts.spectral,
+        },
+        adaptive,
+        checkpointing,
+    )?;
+    let mut pixelbuffer = state.pixels();
+
+    // The auxiliary passes share the camera rays of the first samples of the render. The denoiser uses them as guides.
+    let mut aov_buffers = if opts.aov.is_empty() && !opts.denoise {
+        None
+    } else {
+        Some(AovBuffers::render(
+            width,
+            height,
+            opts.aov_samples,
+            state.seed,
+            scene,
+        ))
+    };
+
+    if let (true, Some(aov_buffers)) = (opts.denoise, &aov_buffers) {
+        let variance = state.variances();
+        let guides = Guides {
+            albedo: &aov_buffers.albedo,
+            normal: &aov_buffers.normal,
+            depth: &aov_buffers.depth,
+            variance: &variance,
+        };
+        let settings = DenoiseSettings {
+            iterations: opts.denoise_iterations,
+            ..DenoiseSettings::default()
+        };
+        pixelbuffer = denoise(width, height, &pixelbuffer, &guides, settings);
+    }
 
     // Graphics assume origin at bottom left corner of the screen
     // Our buffer writes pixels from top left corner. Simple fix, just flip it!
-    image::imageops::flip_vertical_in_place(&mut img);
-    // Our coordinate system is weird in general, try flipping this way too.
-    image::imageops::flip_horizontal_in_place(&mut img);
+    // Our coordinate system is weird in general, try flipping horizontally too.
+    // Flipping both vertically and horizontally is the same as reversing the buffer.
     // TODO: fix the coordinate system
+    pixelbuffer.reverse();
+    if let Some(aov_buffers) = &mut aov_buffers {
+        aov_buffers.reverse();
+    }
 
     let duration = Instant::now() - start;
     println!(); // Empty line after progress bar
     println!("finished render in {}", format_duration(duration));
+    let average_samples = state.total_samples() as Float / (width as Float * height as Float);
+    println!("average samples per pixel: {:.2}", average_samples);
+    if !integrator.is_debug() {
+        println!("average path length: {:.2}", path_stats.average_length());
+    }
 
     // Write
-    let target: String;
-    match opts.output {
-        Some(filename) => {
-            target = filename;
+    match &aov_buffers {
+        Some(_) if opts.aov.is_empty() => {
+            output::save(target, width, height, &pixelbuffer, tone_mapping)?;
+            println!("output saved: {}", target);
+        }
+        Some(aov_buffers) if output::Format::from_path(target) == output::Format::Exr => {
+            output::save_exr_with_aovs(
+                target,
+                width,
+                height,
+                &pixelbuffer,
+                tone_mapping,
+                aov_buffers,
+                &opts.aov,
+            )?;
+            println!("output saved: {}", target);
+        }
+        Some(aov_buffers) => {
+            output::save(target, width, height, &pixelbuffer, tone_mapping)?;
+            println!("output saved: {}", target);
+            for path in output::save_aovs(target, width, height, aov_buffers, &opts.aov)? {
+                println!("aov saved:    {}", path);
+            }
         }
         None => {
-            // Default to using a timestamp & `renders/` directory
-            let timestamp = Utc::now().timestamp();
-            fs::create_dir_all("renders")?;
-            target = format!("renders/{}.png", timestamp);
+            output::save(target, width, height, &pixelbuffer, tone_mapping)?;
+            println!("output saved: {}", target);
         }
-    };
-    img.save(format!("{}", target))?;
-    println!("output saved: {}", target);
+    }
+
+    if let Some(heatmap) = sample_heatmap {
+        // Same orientation fix as for the render
+        let mut counts = state.counts.clone();
+        counts.reverse();
+        output::save_sample_heatmap(heatmap, width, height, &counts)?;
+        println!("heatmap saved: {}", heatmap);
+    }
 
     Ok(())
 }
@@ -1,26 +1,35 @@
 //! Materials enable different behaviors of light on objects.
 
-use crate::{color::Color, hitable::HitRecord, pdf::PDF, ray::Ray, Float, Vec3};
+use crate::{
+    color::Color, hitable::HitRecord, pdf::PDF, ray::Ray, textures::ImageCache, Float,
+    RandomGenerator, Vec3,
+};
+use std::io::Error;
 pub mod dielectric;
 pub mod diffuse_light;
+pub mod henyey_greenstein;
 pub mod isotropic;
 pub mod lambertian;
 pub mod metal;
+pub mod principled;
 
 pub use dielectric::*;
 pub use diffuse_light::*;
+pub use henyey_greenstein::HenyeyGreenstein;
 pub use isotropic::*;
 pub use lambertian::*;
 pub use metal::*;
-use rand::prelude::ThreadRng;
+pub use principled::*;
 use serde::{Deserialize, Serialize};
-#[derive(Deserialize, Serialize, Debug, Copy, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub enum Material {
     Dielectric(Dielectric),
     Lambertian(Lambertian),
     DiffuseLight(DiffuseLight),
     Metal(Metal),
     Isotropic(Isotropic),
+    HenyeyGreenstein(HenyeyGreenstein),
+    Principled(Principled),
 }
 
 impl Default for Material {
@@ -35,14 +44,16 @@ impl Material {
         &self,
         ray: &Ray,
         hit_record: &HitRecord,
-        rng: ThreadRng,
+        rng: &mut RandomGenerator,
     ) -> Option<ScatterRecord> {
-        match *self {
-            Material::Lambertian(l) => Lambertian::scatter(l, ray, hit_record, rng),
-            Material::DiffuseLight(d) => DiffuseLight::scatter(d, ray, hit_record, rng),
-            Material::Metal(m) => Metal::scatter(m, ray, hit_record, rng),
-            Material::Dielectric(d) => Dielectric::scatter(d, ray, hit_record, rng),
-            Material::Isotropic(i) => Isotropic::scatter(i, ray, hit_record, rng),
+        match self {
+            Material::Lambertian(l) => l.scatter(ray, hit_record, rng),
+            Material::DiffuseLight(d) => d.scatter(ray, hit_record, rng),
+            Material::Metal(m) => m.scatter(ray, hit_record, rng),
+            Material::Dielectric(d) => d.scatter(ray, hit_record, rng),
+            Material::Isotropic(i) => i.scatter(ray, hit_record, rng),
+            Material::HenyeyGreenstein(h) => h.scatter(ray, hit_record, rng),
+            Material::Principled(p) => p.scatter(ray, hit_record, rng),
         }
     }
 
@@ -52,14 +63,44 @@ impl Material {
         ray: &Ray,
         hit_record: &HitRecord,
         scattered: &Ray,
-        rng: ThreadRng,
+        rng: &mut RandomGenerator,
     ) -> Float {
-        match *self {
+        match self {
             Material::Dielectric(m) => m.scattering_pdf(ray, hit_record, scattered, rng),
             Material::Lambertian(m) => m.scattering_pdf(ray, hit_record, scattered, rng),
             Material::DiffuseLight(m) => m.scattering_pdf(ray, hit_record, scattered, rng),
             Material::Metal(m) => m.scattering_pdf(ray, hit_record, scattered, rng),
             Material::Isotropic(m) => m.scattering_pdf(ray, hit_record, scattered, rng),
+            Material::HenyeyGreenstein(m) => m.scattering_pdf(ray, hit_record, scattered, rng),
+            Material::Principled(m) => m.scattering_pdf(ray, hit_record, scattered, rng),
+        }
+    }
+
+    /// Returns the color of the light scattered towards the origin of `ray`, for light arriving from the direction of `scattered`. This is the BSDF of the material multiplied by the cosine term. By default, this is the `attenuation` of the [ScatterRecord] multiplied by the `scattering_pdf()`.
+    pub fn scattering_color(
+        &self,
+        ray: &Ray,
+        hit_record: &HitRecord,
+        attenuation: Color,
+        scattered: &Ray,
+        rng: &mut RandomGenerator,
+    ) -> Color {
+        match self {
+            Material::Principled(m) => m.scattering_color(ray, hit_record, scattered, rng),
+            _ => attenuation * self.scattering_pdf(ray, hit_record, scattered, rng),
+        }
+    }
+
+    /// Returns the albedo of the material at the hitpoint: the color of its texture, ignoring lighting. Used for the [albedo AOV](crate::aov::Aov::Albedo).
+    pub fn albedo(&self, hit_record: &HitRecord) -> Color {
+        match self {
+            Material::Dielectric(m) => m.albedo(hit_record),
+            Material::Lambertian(m) => m.albedo(hit_record),
+            Material::DiffuseLight(m) => m.albedo(hit_record),
+            Material::Metal(m) => m.albedo(hit_record),
+            Material::Isotropic(m) => m.albedo(hit_record),
+            Material::HenyeyGreenstein(m) => m.albedo(hit_record),
+            Material::Principled(m) => m.albedo(hit_record),
         }
     }
 
@@ -72,11 +113,24 @@ impl Material {
         v: Float,
         position: Vec3,
     ) -> Color {
-        match *self {
+        match self {
             Material::DiffuseLight(d) => d.emit(ray, hit_record, u, v, position),
             _ => Color::new(0.0, 0.0, 0.0),
         }
     }
+
+    /// Loads the images of the textures of the material through the cache, see [Texture::load_images()](crate::textures::Texture::load_images)
+    pub fn load_images(&mut self, images: &mut ImageCache) -> Result<(), Error> {
+        match self {
+            Material::Dielectric(_) => Ok(()),
+            Material::Lambertian(m) => m.albedo.load_images(images),
+            Material::DiffuseLight(m) => m.emit.load_images(images),
+            Material::Metal(m) => m.albedo.load_images(images),
+            Material::Isotropic(m) => m.albedo.load_images(images),
+            Material::HenyeyGreenstein(m) => m.albedo.load_images(images),
+            Material::Principled(m) => m.base_color.load_images(images),
+        }
+    }
 }
 
 pub enum MaterialType {
@@ -1,5 +1,7 @@
 use super::{reflect, refract, schlick, Material, MaterialType, ScatterRecord};
-use crate::{color::Color, hitable::HitRecord, pdf::ZeroPDF, ray::Ray, Float, Vec3};
+use crate::{
+    color::Color, hitable::HitRecord, pdf::ZeroPDF, ray::Ray, Float, RandomGenerator, Vec3,
+};
 use rand::prelude::*;
 
 use serde::{Deserialize, Serialize};
@@ -13,6 +15,40 @@ pub struct Dielectric {
     /// Color of the material. Used for colorizing the rays. Default value: [`Color::new(1.0, 1.0, 1.0)`](crate::color::Color), producing a fully transparent, clear glass.
     #[serde(default = "default_color")]
     pub color: Color,
+    /// Optional wavelength dependency of the refractive index. Only has a visible effect in [spectral](crate::spectrum) mode. When given, replaces the `refractive_index`: rays without a wavelength use the index at the Fraunhofer d line, 587.6 nm. Default value: None
+    #[serde(default)]
+    pub dispersion: Option<Dispersion>,
+}
+
+/// Models for the refractive index of a material as a function of wavelength. Wavelengths are in micrometers, following the usual conventions of the published coefficients.
+#[derive(Copy, Clone, Deserialize, Serialize, Debug)]
+pub enum Dispersion {
+    /// [Cauchy's equation](https://en.wikipedia.org/wiki/Cauchy%27s_equation): `n = a + b / λ²`. E.g. `a: 1.5046, b: 0.0042` for BK7 glass.
+    Cauchy { a: Float, b: Float },
+    /// The [Sellmeier equation](https://en.wikipedia.org/wiki/Sellmeier_equation): `n² = 1 + Σ b_i λ² / (λ² - c_i)`, with the `c` coefficients in square micrometers. E.g. `b: [1.03961212, 0.231792344, 1.01046945], c: [0.00600069867, 0.0200179144, 103.560653]` for BK7 glass.
+    Sellmeier { b: [Float; 3], c: [Float; 3] },
+}
+
+/// Wavelength of the Fraunhofer d line in nanometers, the usual reference for refractive indices
+const WAVELENGTH_D_LINE: Float = 587.6;
+
+impl Dispersion {
+    /// Returns the refractive index at the given wavelength in nanometers
+    pub fn refractive_index(&self, wavelength: Float) -> Float {
+        let micrometers = wavelength / 1000.0;
+        let squared = micrometers * micrometers;
+        match self {
+            Dispersion::Cauchy { a, b } => a + b / squared,
+            Dispersion::Sellmeier { b, c } => {
+                let sum: Float = b
+                    .iter()
+                    .zip(c.iter())
+                    .map(|(b, c)| b * squared / (squared - c))
+                    .sum();
+                (1.0 + sum).sqrt()
+            }
+        }
+    }
 }
 
 fn default_index() -> Float {
@@ -24,18 +60,29 @@ fn default_color() -> Color {
 }
 
 impl<'a> Dielectric {
+    /// Returns the refractive index of the material for the given ray, depending on its wavelength if the material has [Dispersion]
+    pub fn refractive_index(&self, ray: &Ray) -> Float {
+        match self.dispersion {
+            Some(dispersion) => {
+                dispersion.refractive_index(ray.wavelength.unwrap_or(WAVELENGTH_D_LINE))
+            }
+            None => self.refractive_index,
+        }
+    }
+
     pub fn scatter(
         self,
         ray: &Ray,
         hit_record: &HitRecord,
-        mut rng: ThreadRng,
+        rng: &mut RandomGenerator,
     ) -> Option<ScatterRecord<'a>> {
         let albedo = self.color;
         let specular_ray: Ray;
 
+        let refractive_index = self.refractive_index(ray);
         let etai_over_etat: Float = match hit_record.front_face {
-            true => 1.0 / self.refractive_index,
-            false => self.refractive_index,
+            true => 1.0 / refractive_index,
+            false => refractive_index,
         };
 
         let unit_direction: Vec3 = ray.direction.normalize();
@@ -43,7 +90,8 @@ impl<'a> Dielectric {
         let sin_theta: Float = (1.0 - cos_theta * cos_theta).sqrt();
         if etai_over_etat * sin_theta > 1.0 {
             let reflected: Vec3 = reflect(unit_direction, hit_record.normal);
-            specular_ray = Ray::new(hit_record.position, reflected, ray.time);
+            specular_ray =
+                Ray::new(hit_record.position, reflected, ray.time).with_wavelength(ray.wavelength);
             Some(ScatterRecord {
                 material_type: MaterialType::Specular,
                 specular_ray: Some(specular_ray),
@@ -54,7 +102,8 @@ impl<'a> Dielectric {
             let reflect_probability: Float = schlick(cos_theta, etai_over_etat);
             if rng.gen::<Float>() < reflect_probability {
                 let reflected: Vec3 = reflect(unit_direction, hit_record.normal);
-                specular_ray = Ray::new(hit_record.position, reflected, ray.time);
+                specular_ray = Ray::new(hit_record.position, reflected, ray.time)
+                    .with_wavelength(ray.wavelength);
                 Some(ScatterRecord {
                     material_type: MaterialType::Specular,
                     specular_ray: Some(specular_ray),
@@ -63,7 +112,8 @@ impl<'a> Dielectric {
                 })
             } else {
                 let refracted: Vec3 = refract(unit_direction, hit_record.normal, etai_over_etat);
-                specular_ray = Ray::new(hit_record.position, refracted, ray.time);
+                specular_ray = Ray::new(hit_record.position, refracted, ray.time)
+                    .with_wavelength(ray.wavelength);
                 Some(ScatterRecord {
                     material_type: MaterialType::Specular,
                     specular_ray: Some(specular_ray),
@@ -79,15 +129,21 @@ impl<'a> Dielectric {
         _ray: &Ray,
         _hit_record: &HitRecord,
         _scattered: &Ray,
-        _rng: ThreadRng,
+        _rng: &mut RandomGenerator,
     ) -> Float {
         todo!()
     }
 
+    /// Returns the color of the material. Clear glass is white.
+    pub fn albedo(self, _hit_record: &HitRecord) -> Color {
+        self.color
+    }
+
     pub fn new(refractive_index: Float, color: Color) -> Material {
         Material::Dielectric(Dielectric {
             refractive_index,
             color,
+            dispersion: None,
         })
     }
 }
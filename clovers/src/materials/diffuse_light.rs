@@ -4,16 +4,15 @@ use crate::{
     hitable::HitRecord,
     ray::Ray,
     textures::{SolidColor, Texture},
-    Float, Vec3,
+    Float, RandomGenerator, Vec3,
 };
-use rand::prelude::ThreadRng;
 
 use serde::{Deserialize, Serialize};
 
 /// A diffuse light material. On this material, rays never scatter - the material always emits a color based on its texture.
-#[derive(Copy, Clone, Deserialize, Serialize, Debug)]
+#[derive(Clone, Deserialize, Serialize, Debug)]
 pub struct DiffuseLight {
-    emit: Texture,
+    pub(crate) emit: Texture,
 }
 
 impl Default for DiffuseLight {
@@ -27,26 +26,26 @@ impl Default for DiffuseLight {
 
 impl<'a> DiffuseLight {
     pub fn scatter(
-        self,
+        &self,
         _ray: &Ray,
         _hit_record: &HitRecord,
-        _rng: ThreadRng,
+        _rng: &mut RandomGenerator,
     ) -> Option<ScatterRecord<'a>> {
         None
     }
 
     pub fn scattering_pdf(
-        self,
+        &self,
         _ray: &Ray,
         _hit_record: &HitRecord,
         _scattered: &Ray,
-        _rng: ThreadRng,
+        _rng: &mut RandomGenerator,
     ) -> Float {
         0.0 // TODO: cleanup
     }
 
     pub fn emit(
-        self,
+        &self,
         _ray: &Ray,
         hit_record: &HitRecord,
         u: Float,
@@ -60,6 +59,18 @@ impl<'a> DiffuseLight {
         }
     }
 
+    /// Returns the color of the emitted light, clamped to the `0.0..=1.0` range of a reflectance
+    pub fn albedo(&self, hit_record: &HitRecord) -> Color {
+        let color = self
+            .emit
+            .color(hit_record.u, hit_record.v, hit_record.position);
+        Color::new(
+            color.r.clamp(0.0, 1.0),
+            color.g.clamp(0.0, 1.0),
+            color.b.clamp(0.0, 1.0),
+        )
+    }
+
     pub fn new(emission: Texture) -> Material {
         Material::DiffuseLight(DiffuseLight { emit: emission })
     }
@@ -0,0 +1,142 @@
+use super::{Material, MaterialType, ScatterRecord};
+use crate::{
+    color::Color, hitable::HitRecord, onb::ONB, pdf::HenyeyGreensteinPDF, ray::Ray,
+    textures::Texture, Float, RandomGenerator, Vec3, PI,
+};
+use rand::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Anisotropy values closer to zero than this are treated as isotropic, avoiding a division by zero when sampling
+const ISOTROPIC_THRESHOLD: Float = 1e-3;
+
+/// Largest magnitude of the anisotropy. At ±1.0 all the light scatters in a single direction, and the [phase()] function divides by zero.
+const MAX_ANISOTROPY: Float = 0.999;
+
+/// The scene file representation of a [HenyeyGreenstein] material
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct HenyeyGreensteinInit {
+    #[serde(default)]
+    pub albedo: Texture,
+    /// Anisotropy of the scattering, clamped to the range `-0.999..=0.999`. NaN is treated as isotropic. Default value: 0.0
+    #[serde(default)]
+    pub anisotropy: Float,
+}
+
+/// A phase function material for participating media, using the [Henyey-Greenstein](https://www.astro.umd.edu/~jph/HG_note.pdf) phase function. The anisotropy parameter sets the average cosine of the scattering angle: positive values scatter forwards, like fog and clouds, and negative values scatter backwards. Zero is isotropic.
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+#[serde(from = "HenyeyGreensteinInit", into = "HenyeyGreensteinInit")]
+pub struct HenyeyGreenstein {
+    pub(crate) albedo: Texture,
+    /// Anisotropy of the scattering, in the range `-0.999..=0.999`
+    anisotropy: Float,
+}
+
+impl<'a> HenyeyGreenstein {
+    /// Creates a new phase function material. The anisotropy is clamped to the range `-0.999..=0.999`, and NaN is treated as isotropic.
+    pub fn new(albedo: Texture, anisotropy: Float) -> Material {
+        Material::HenyeyGreenstein(HenyeyGreensteinInit { albedo, anisotropy }.into())
+    }
+
+    /// Returns the scattering albedo of the medium at the hitpoint
+    pub fn albedo(&self, hit_record: &HitRecord) -> Color {
+        self.albedo
+            .color(hit_record.u, hit_record.v, hit_record.position)
+    }
+
+    pub fn scatter(
+        &self,
+        ray: &Ray,
+        hit_record: &HitRecord,
+        _rng: &mut RandomGenerator,
+    ) -> Option<ScatterRecord<'a>> {
+        Some(ScatterRecord {
+            material_type: MaterialType::Diffuse,
+            specular_ray: None,
+            attenuation: self.albedo(hit_record),
+            pdf_ptr: HenyeyGreensteinPDF::new(ray.direction, self.anisotropy),
+        })
+    }
+
+    pub fn scattering_pdf(
+        &self,
+        ray: &Ray,
+        _hit_record: &HitRecord,
+        scattered: &Ray,
+        _rng: &mut RandomGenerator,
+    ) -> Float {
+        let cos_theta = ray
+            .direction
+            .normalize()
+            .dot(&scattered.direction.normalize());
+        phase(cos_theta, self.anisotropy)
+    }
+}
+
+impl From<HenyeyGreensteinInit> for HenyeyGreenstein {
+    fn from(init: HenyeyGreensteinInit) -> Self {
+        HenyeyGreenstein {
+            albedo: init.albedo,
+            // Clamping keeps NaN, which would make every density NaN
+            anisotropy: if init.anisotropy.is_nan() {
+                0.0
+            } else {
+                init.anisotropy.clamp(-MAX_ANISOTROPY, MAX_ANISOTROPY)
+            },
+        }
+    }
+}
+
+impl From<HenyeyGreenstein> for HenyeyGreensteinInit {
+    fn from(material: HenyeyGreenstein) -> Self {
+        HenyeyGreensteinInit {
+            albedo: material.albedo,
+            anisotropy: material.anisotropy,
+        }
+    }
+}
+
+/// The Henyey-Greenstein phase function: the probability density of scattering by an angle with the given cosine, per unit solid angle
+pub fn phase(cos_theta: Float, anisotropy: Float) -> Float {
+    let g = anisotropy;
+    let denominator = 1.0 + g * g - 2.0 * g * cos_theta;
+    (1.0 - g * g) / (4.0 * PI * denominator * denominator.sqrt())
+}
+
+/// Samples a new direction for a ray traveling in the given direction, with the density of the Henyey-Greenstein [phase()] function
+pub fn sample_direction(direction: Vec3, anisotropy: Float, rng: &mut RandomGenerator) -> Vec3 {
+    let g = anisotropy;
+    let xi: Float = rng.gen();
+    let cos_theta = if g.abs() < ISOTROPIC_THRESHOLD {
+        1.0 - 2.0 * xi
+    } else {
+        let term = (1.0 - g * g) / (1.0 - g + 2.0 * g * xi);
+        (1.0 + g * g - term * term) / (2.0 * g)
+    };
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let phi = 2.0 * PI * rng.gen::<Float>();
+    let uvw = ONB::build_from_w(direction);
+    uvw.local(Vec3::new(
+        sin_theta * phi.cos(),
+        sin_theta * phi.sin(),
+        cos_theta,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deserialized_anisotropy_is_clamped() {
+        for (json, anisotropy) in [
+            (r#"{"anisotropy": 1.0}"#, MAX_ANISOTROPY),
+            (r#"{"anisotropy": -2.0}"#, -MAX_ANISOTROPY),
+            (r#"{"anisotropy": 0.5}"#, 0.5),
+            ("{}", 0.0),
+        ] {
+            let material: HenyeyGreenstein = serde_json::from_str(json).unwrap();
+            assert_eq!(material.anisotropy, anisotropy);
+            assert!(phase(1.0, material.anisotropy).is_finite());
+        }
+    }
+}
@@ -1,13 +1,15 @@
 use super::{Material, MaterialType, ScatterRecord};
 use crate::{
-    color::Color, hitable::HitRecord, pdf::CosinePDF, ray::Ray, textures::Texture, Float, PI,
+    color::Color, hitable::HitRecord, pdf::SpherePDF, ray::Ray, textures::Texture, Float,
+    RandomGenerator, PI,
 };
-use rand::prelude::ThreadRng;
 use serde::{Deserialize, Serialize};
-#[derive(Deserialize, Serialize, Debug, Copy, Clone, Default)]
+
+/// A phase function material for participating media, scattering light uniformly in all directions
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
 pub struct Isotropic {
     #[serde(default)]
-    albedo: Texture,
+    pub(crate) albedo: Texture,
 }
 
 impl<'a> Isotropic {
@@ -15,40 +17,35 @@ impl<'a> Isotropic {
         Material::Isotropic(Isotropic { albedo: emission })
     }
 
+    /// Returns the scattering albedo of the medium at the hitpoint
+    pub fn albedo(&self, hit_record: &HitRecord) -> Color {
+        self.albedo
+            .color(hit_record.u, hit_record.v, hit_record.position)
+    }
+
     pub fn scatter(
-        self,
+        &self,
         _ray: &Ray,
         hit_record: &HitRecord,
-        _rng: ThreadRng,
+        _rng: &mut RandomGenerator,
     ) -> Option<ScatterRecord<'a>> {
-        // TODO: fix / verify correctness!
-        // this is just copied from lambertian as an experiment
-        let albedo: Color = self
-            .albedo
-            .color(hit_record.u, hit_record.v, hit_record.position);
-
+        // The normal of a volume hit is arbitrary, so the scattering cannot depend on it
         Some(ScatterRecord {
             material_type: MaterialType::Diffuse,
             specular_ray: None,
-            attenuation: albedo,
-            pdf_ptr: CosinePDF::new(hit_record.normal),
+            attenuation: self.albedo(hit_record),
+            pdf_ptr: SpherePDF::new(),
         })
     }
 
+    /// The isotropic phase function: a constant over the full sphere of directions
     pub fn scattering_pdf(
-        self,
+        &self,
         _ray: &Ray,
-        hit_record: &HitRecord,
-        scattered: &Ray,
-        _rng: ThreadRng,
+        _hit_record: &HitRecord,
+        _scattered: &Ray,
+        _rng: &mut RandomGenerator,
     ) -> Float {
-        // TODO: fix / verify correctness!
-        // this is just copied from lambertian as an experiment
-        let cosine = hit_record.normal.dot(&scattered.direction.normalize());
-        if cosine < 0.0 {
-            0.0
-        } else {
-            cosine / PI
-        }
+        1.0 / (4.0 * PI)
     }
 }
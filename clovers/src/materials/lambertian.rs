@@ -1,21 +1,23 @@
 use super::{MaterialType, ScatterRecord};
-use crate::{hitable::HitRecord, pdf::CosinePDF, ray::Ray, textures::Texture, Float, PI};
-use rand::prelude::ThreadRng;
+use crate::{
+    color::Color, hitable::HitRecord, pdf::CosinePDF, ray::Ray, textures::Texture, Float,
+    RandomGenerator, PI,
+};
 use serde::{Deserialize, Serialize};
 
-#[derive(Copy, Clone, Deserialize, Serialize, Debug, Default)]
+#[derive(Clone, Deserialize, Serialize, Debug, Default)]
 pub struct Lambertian {
     #[serde(default)]
-    albedo: Texture,
+    pub(crate) albedo: Texture,
 }
 
 impl<'a> Lambertian {
     /// Returns None, if ray is absorbed. Otherwise, returns a ray, albedo of what was hit, and (?) a value used for probability density function based sampling
     pub fn scatter(
-        self,
+        &self,
         _ray: &Ray,
         hit_record: &HitRecord,
-        _rng: ThreadRng,
+        _rng: &mut RandomGenerator,
     ) -> Option<ScatterRecord<'a>> {
         Some(ScatterRecord {
             material_type: MaterialType::Diffuse,
@@ -28,11 +30,11 @@ impl<'a> Lambertian {
     }
 
     pub fn scattering_pdf(
-        self,
+        &self,
         _ray: &Ray,
         hit_record: &HitRecord,
         scattered: &Ray,
-        _rng: ThreadRng,
+        _rng: &mut RandomGenerator,
     ) -> Float {
         let cosine = hit_record.normal.dot(&scattered.direction.normalize());
         if cosine < 0.0 {
@@ -42,6 +44,12 @@ impl<'a> Lambertian {
         }
     }
 
+    /// Returns the diffuse reflectance of the material at the hitpoint
+    pub fn albedo(&self, hit_record: &HitRecord) -> Color {
+        self.albedo
+            .color(hit_record.u, hit_record.v, hit_record.position)
+    }
+
     pub fn new(albedo: impl Into<Texture>) -> Self {
         Lambertian {
             albedo: albedo.into(),
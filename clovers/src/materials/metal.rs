@@ -1,32 +1,34 @@
 use super::{reflect, Material, MaterialType, ScatterRecord};
 use crate::{
-    hitable::HitRecord, pdf::ZeroPDF, random::random_in_unit_sphere, ray::Ray, textures::Texture,
-    Float, Vec3,
+    color::Color, hitable::HitRecord, pdf::ZeroPDF, random::random_in_unit_sphere, ray::Ray,
+    textures::Texture, Float, RandomGenerator, Vec3,
 };
-use rand::prelude::ThreadRng;
 use serde::{Deserialize, Serialize};
-#[derive(Deserialize, Serialize, Debug, Copy, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct Metal {
     #[serde(default)]
-    albedo: Texture,
+    pub(crate) albedo: Texture,
     #[serde(default)]
     fuzz: Float,
 }
 
 impl<'a> Metal {
     pub fn scatter(
-        self,
+        &self,
         ray: &Ray,
         hit_record: &HitRecord,
-        rng: ThreadRng,
+        rng: &mut RandomGenerator,
     ) -> Option<ScatterRecord<'a>> {
         let reflected: Vec3 = reflect(ray.direction.normalize(), hit_record.normal);
         Some(ScatterRecord {
-            specular_ray: Some(Ray::new(
-                hit_record.position,
-                reflected + self.fuzz * random_in_unit_sphere(rng),
-                ray.time,
-            )),
+            specular_ray: Some(
+                Ray::new(
+                    hit_record.position,
+                    reflected + self.fuzz * random_in_unit_sphere(rng),
+                    ray.time,
+                )
+                .with_wavelength(ray.wavelength),
+            ),
             attenuation: self.albedo.color(0.0, 0.0, hit_record.position), // NOTE: random coords...
             material_type: MaterialType::Specular,
             pdf_ptr: ZeroPDF::new(),
@@ -34,15 +36,21 @@ impl<'a> Metal {
     }
 
     pub fn scattering_pdf(
-        self,
+        &self,
         _ray: &Ray,
         _hit_record: &HitRecord,
         _scattered: &Ray,
-        _rng: ThreadRng,
+        _rng: &mut RandomGenerator,
     ) -> Float {
         0.0 // TODO: why does metal scatter 0? No mention in tutorial afaiu
     }
 
+    /// Returns the specular reflectance of the material at the hitpoint
+    pub fn albedo(&self, hit_record: &HitRecord) -> Color {
+        self.albedo
+            .color(hit_record.u, hit_record.v, hit_record.position)
+    }
+
     pub fn new(albedo: Texture, fuzz: Float) -> Material {
         Material::Metal(Metal {
             albedo,
@@ -0,0 +1,223 @@
+use super::{Material, MaterialType, ScatterRecord};
+use crate::{
+    color::Color, hitable::HitRecord, pdf::MicrofacetPDF, ray::Ray, textures::Texture, Float,
+    RandomGenerator, Vec3, PI,
+};
+use serde::{Deserialize, Serialize};
+
+/// The scene file representation of a [Principled] material
+#[derive(Clone, Deserialize, Serialize, Debug)]
+pub struct PrincipledInit {
+    /// Color of the material. For dielectrics this is the diffuse color, for metals this is the color of the specular reflection.
+    #[serde(default)]
+    pub base_color: Texture,
+    /// Microfacet roughness of the surface, between 0.0 (mirror-like) and 1.0 (fully rough). Values outside the range are clamped to it. Default value: 0.5
+    #[serde(default = "default_roughness")]
+    pub roughness: Float,
+    /// Metalness of the surface, between 0.0 (dielectric) and 1.0 (metal). Values outside the range are clamped to it. Default value: 0.0
+    #[serde(default)]
+    pub metallic: Float,
+}
+
+/// A physically based material using the metallic-roughness model: a [GGX / Trowbridge-Reitz](https://www.graphics.cornell.edu/~bjw/microfacetbsdf.pdf) microfacet specular lobe on top of a Lambertian diffuse base. Unlike [Metal](crate::materials::Metal), this material can be importance sampled, and takes part in the light sampling of [colorize()](crate::colorize::colorize).
+#[derive(Clone, Deserialize, Serialize, Debug)]
+#[serde(from = "PrincipledInit", into = "PrincipledInit")]
+pub struct Principled {
+    pub(crate) base_color: Texture,
+    /// Microfacet roughness of the surface, between 0.0 and 1.0
+    roughness: Float,
+    /// Metalness of the surface, between 0.0 and 1.0
+    metallic: Float,
+}
+
+fn default_roughness() -> Float {
+    0.5
+}
+
+/// Specular reflectance at normal incidence for dielectrics, based on a typical refractive index of 1.5
+const DIELECTRIC_F0: Float = 0.04;
+/// The GGX distribution gets numerically unstable for perfectly smooth surfaces, so clamp the alpha parameter
+const MIN_ALPHA: Float = 1e-3;
+
+impl<'a> Principled {
+    /// Creates a new principled material. The roughness and metallic values are clamped to the range `0.0..=1.0`; NaN gets the default value.
+    pub fn new(base_color: Texture, roughness: Float, metallic: Float) -> Material {
+        Material::Principled(
+            PrincipledInit {
+                base_color,
+                roughness,
+                metallic,
+            }
+            .into(),
+        )
+    }
+
+    /// Returns the base color of the material at the hitpoint
+    pub fn albedo(&self, hit_record: &HitRecord) -> Color {
+        self.base_color
+            .color(hit_record.u, hit_record.v, hit_record.position)
+    }
+
+    pub fn scatter(
+        &self,
+        ray: &Ray,
+        hit_record: &HitRecord,
+        _rng: &mut RandomGenerator,
+    ) -> Option<ScatterRecord<'a>> {
+        Some(ScatterRecord {
+            material_type: MaterialType::Diffuse,
+            specular_ray: None,
+            attenuation: self
+                .base_color
+                .color(hit_record.u, hit_record.v, hit_record.position),
+            pdf_ptr: MicrofacetPDF::new(
+                hit_record.normal,
+                -ray.direction.normalize(),
+                self.alpha(),
+                self.specular_probability(),
+            ),
+        })
+    }
+
+    /// Returns the probability density of scattering towards the `scattered` direction, matching the sampling of [MicrofacetPDF]
+    pub fn scattering_pdf(
+        &self,
+        ray: &Ray,
+        hit_record: &HitRecord,
+        scattered: &Ray,
+        _rng: &mut RandomGenerator,
+    ) -> Float {
+        MicrofacetPDF::density(
+            hit_record.normal,
+            -ray.direction.normalize(),
+            scattered.direction.normalize(),
+            self.alpha(),
+            self.specular_probability(),
+        )
+    }
+
+    /// Evaluates the BRDF multiplied by the cosine term, for light arriving from the `scattered` direction and leaving towards the origin of `ray`
+    pub fn scattering_color(
+        &self,
+        ray: &Ray,
+        hit_record: &HitRecord,
+        scattered: &Ray,
+        _rng: &mut RandomGenerator,
+    ) -> Color {
+        let black = Color::new(0.0, 0.0, 0.0);
+        let normal: Vec3 = hit_record.normal;
+        let outgoing: Vec3 = -ray.direction.normalize();
+        let incoming: Vec3 = scattered.direction.normalize();
+        let n_dot_o: Float = normal.dot(&outgoing);
+        let n_dot_i: Float = normal.dot(&incoming);
+        if n_dot_o <= 0.0 || n_dot_i <= 0.0 {
+            return black;
+        }
+
+        let halfway: Vec3 = (outgoing + incoming).normalize();
+        let n_dot_h: Float = normal.dot(&halfway).max(0.0);
+        let o_dot_h: Float = outgoing.dot(&halfway).max(0.0);
+        let alpha = self.alpha();
+
+        let base_color: Color =
+            self.base_color
+                .color(hit_record.u, hit_record.v, hit_record.position);
+        let white = Color::new(1.0, 1.0, 1.0);
+        let dielectric_f0 = Color::new(DIELECTRIC_F0, DIELECTRIC_F0, DIELECTRIC_F0);
+        let f0: Color = dielectric_f0 * (1.0 - self.metallic) + base_color * self.metallic;
+        // Schlick's approximation for the Fresnel term
+        let weight: Float = (1.0 - o_dot_h).powi(5);
+        let fresnel: Color = f0 * (1.0 - weight) + white * weight;
+
+        let distribution: Float = ggx_distribution(n_dot_h, alpha);
+        let geometry: Float = smith_g1(n_dot_o, alpha) * smith_g1(n_dot_i, alpha);
+        let specular: Color = fresnel * (distribution * geometry / (4.0 * n_dot_o * n_dot_i));
+
+        // Energy not reflected specularly is available for the diffuse base. Metals have no diffuse base.
+        let not_reflected = Color::new(1.0 - fresnel.r, 1.0 - fresnel.g, 1.0 - fresnel.b);
+        let diffuse: Color =
+            not_reflected.component_mul(&base_color) * ((1.0 - self.metallic) / PI);
+
+        (diffuse + specular) * n_dot_i
+    }
+
+    fn alpha(&self) -> Float {
+        (self.roughness * self.roughness).max(MIN_ALPHA)
+    }
+
+    /// Probability of sampling the specular lobe instead of the diffuse lobe
+    fn specular_probability(&self) -> Float {
+        0.5 * (1.0 + self.metallic)
+    }
+}
+
+impl From<PrincipledInit> for Principled {
+    fn from(init: PrincipledInit) -> Self {
+        Principled {
+            base_color: init.base_color,
+            roughness: clamp_unit(init.roughness, default_roughness()),
+            metallic: clamp_unit(init.metallic, 0.0),
+        }
+    }
+}
+
+impl From<Principled> for PrincipledInit {
+    fn from(material: Principled) -> Self {
+        PrincipledInit {
+            base_color: material.base_color,
+            roughness: material.roughness,
+            metallic: material.metallic,
+        }
+    }
+}
+
+/// Clamps the value to the range `0.0..=1.0`, replacing NaN with the default
+fn clamp_unit(value: Float, default: Float) -> Float {
+    if value.is_nan() {
+        return default;
+    }
+    value.clamp(0.0, 1.0)
+}
+
+/// The GGX / Trowbridge-Reitz normal distribution function
+pub fn ggx_distribution(n_dot_h: Float, alpha: Float) -> Float {
+    let alpha2 = alpha * alpha;
+    let denominator = n_dot_h * n_dot_h * (alpha2 - 1.0) + 1.0;
+    alpha2 / (PI * denominator * denominator)
+}
+
+/// The Smith masking-shadowing function for a single direction, for the GGX distribution
+fn smith_g1(n_dot_v: Float, alpha: Float) -> Float {
+    let alpha2 = alpha * alpha;
+    2.0 * n_dot_v / (n_dot_v + (alpha2 + (1.0 - alpha2) * n_dot_v * n_dot_v).sqrt())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deserialized_parameters_are_clamped() {
+        for (json, roughness, metallic) in [
+            (r#"{"roughness": -1.0, "metallic": 2.0}"#, 0.0, 1.0),
+            (r#"{"roughness": 1.5, "metallic": -0.5}"#, 1.0, 0.0),
+            (r#"{"roughness": 0.25, "metallic": 0.75}"#, 0.25, 0.75),
+            ("{}", 0.5, 0.0),
+        ] {
+            let material: Principled = serde_json::from_str(json).unwrap();
+            assert_eq!(material.roughness, roughness);
+            assert_eq!(material.metallic, metallic);
+        }
+    }
+
+    #[test]
+    fn new_clamps_parameters() {
+        match Principled::new(Texture::default(), Float::NAN, 3.0) {
+            Material::Principled(material) => {
+                assert_eq!(material.roughness, 0.5);
+                assert_eq!(material.metallic, 1.0);
+            }
+            _ => unreachable!(),
+        }
+    }
+}
@@ -1,26 +1,42 @@
 //! Various literal objects and meta-object utilities for creating content in [Scenes](crate::scenes::Scene).
 
-use crate::hitable::Hitable;
+use crate::{hitable::Hitable, scenes::resolve_path, textures::ImageCache, Float};
 use serde::{Deserialize, Serialize};
-use std::sync::Arc;
+use std::{
+    io::{Error, ErrorKind},
+    path::Path,
+    sync::Arc,
+};
 
 pub mod boxy; // avoid keyword
 pub mod constant_medium;
 pub mod flip_face;
+pub mod grid_medium;
+pub mod instance;
+pub mod keyframed_transform;
+pub mod mesh;
 pub mod moving_sphere;
 pub mod rect;
 pub mod rotate;
 pub mod sphere;
+pub mod transform;
 pub mod translate;
+pub mod triangle;
 
 pub use boxy::*; // avoid keyword
 pub use constant_medium::*;
 pub use flip_face::*;
+pub use grid_medium::*;
+pub use instance::*;
+pub use keyframed_transform::*;
+pub use mesh::*;
 pub use moving_sphere::*;
 pub use rect::*;
 pub use rotate::*;
 pub use sphere::*;
+pub use transform::*;
 pub use translate::*;
+pub use triangle::*;
 
 // TODO: This is kind of an ugly hack, having to double-implement various structures to have an external representation vs internal representation. How could this be made cleaner?
 
@@ -30,41 +46,145 @@ pub enum Object {
     XYRect(XYRectInit),
     YZRect(YZRectInit),
     Sphere(SphereInit),
+    MovingSphere(MovingSphereInit),
     Boxy(BoxyInit),
     RotateY(RotateInit),
     Translate(TranslateInit),
+    Transform(TransformInit),
+    Instance(InstanceInit),
+    KeyframedTransform(KeyframedTransformInit),
     FlipFace(FlipFaceInit),
     ConstantMedium(ConstantMediumInit),
+    GridMedium(GridMediumInit),
+    Triangle(TriangleInit),
+    Mesh(MeshInit),
 }
 
-impl From<Object> for Hitable {
-    fn from(obj: Object) -> Hitable {
-        match obj {
+impl Object {
+    /// Resolves the paths of the external files of the object and its children, relative to the directory of the scene file
+    pub fn resolve_paths(&mut self, directory: &Path) {
+        match self {
+            Object::RotateY(x) => x.object.resolve_paths(directory),
+            Object::Translate(x) => x.object.resolve_paths(directory),
+            Object::Transform(x) => x.object.resolve_paths(directory),
+            Object::KeyframedTransform(x) => x.object.resolve_paths(directory),
+            Object::FlipFace(x) => x.object.resolve_paths(directory),
+            Object::ConstantMedium(x) => x.boundary.resolve_paths(directory),
+            Object::GridMedium(x) => {
+                if let DensityGridInit::Voxels { path } = &mut x.grid {
+                    *path = resolve_path(directory, path);
+                }
+                x.boundary.resolve_paths(directory)
+            }
+            Object::Mesh(x) => x.path = resolve_path(directory, &x.path),
+            Object::XZRect(_)
+            | Object::XYRect(_)
+            | Object::YZRect(_)
+            | Object::Sphere(_)
+            | Object::MovingSphere(_)
+            | Object::Boxy(_)
+            | Object::Instance(_)
+            | Object::Triangle(_) => (),
+        }
+    }
+
+    /// Loads the images of the textures of the object and its children through the cache. Returns an error if an image can not be loaded.
+    pub fn load_images(&mut self, images: &mut ImageCache) -> Result<(), Error> {
+        match self {
+            Object::XZRect(x) => x.material.load_images(images),
+            Object::XYRect(x) => x.material.load_images(images),
+            Object::YZRect(x) => x.material.load_images(images),
+            Object::Sphere(x) => x.material.load_images(images),
+            Object::MovingSphere(x) => x.material.load_images(images),
+            Object::Boxy(x) => x.material.load_images(images),
+            Object::RotateY(x) => x.object.load_images(images),
+            Object::Translate(x) => x.object.load_images(images),
+            Object::Transform(x) => x.object.load_images(images),
+            Object::Instance(x) => match &mut x.material {
+                Some(material) => material.load_images(images),
+                None => Ok(()),
+            },
+            Object::KeyframedTransform(x) => x.object.load_images(images),
+            Object::FlipFace(x) => x.object.load_images(images),
+            Object::ConstantMedium(x) => {
+                x.texture.load_images(images)?;
+                x.boundary.load_images(images)
+            }
+            Object::GridMedium(x) => {
+                x.texture.load_images(images)?;
+                x.boundary.load_images(images)
+            }
+            Object::Triangle(x) => x.material.load_images(images),
+            Object::Mesh(x) => x.material.load_images(images),
+        }
+    }
+
+    /// Builds the object for the shutter interval from `time_0` to `time_1`, resolving the [Instance] objects from the given library of shared geometries. Returns an error if an external file of the object can not be loaded, or if the object is not valid.
+    pub fn build(
+        self,
+        library: &GeometryLibrary,
+        time_0: Float,
+        time_1: Float,
+    ) -> Result<Hitable, Error> {
+        let hitable = match self {
             Object::XZRect(x) => XZRect::new(x.x0, x.x1, x.z0, x.z1, x.k, x.material),
             Object::XYRect(x) => XYRect::new(x.x0, x.x1, x.y0, x.y1, x.k, x.material),
             Object::YZRect(x) => YZRect::new(x.y0, x.y1, x.z0, x.z1, x.k, x.material),
             Object::Sphere(x) => Sphere::new(x.center, x.radius, x.material),
+            Object::MovingSphere(x) => MovingSphere::new(
+                x.center_0, x.center_1, x.time_0, x.time_1, x.radius, x.material,
+            ),
             Object::Boxy(x) => Boxy::new(x.corner_0, x.corner_1, x.material),
             Object::RotateY(x) => {
-                let obj = *x.object;
-                let obj: Hitable = obj.into();
+                let obj = x.object.build(library, time_0, time_1)?;
                 RotateY::new(Arc::new(obj), x.angle)
             }
             Object::Translate(x) => {
-                let obj = *x.object;
-                let obj: Hitable = obj.into();
+                let obj = x.object.build(library, time_0, time_1)?;
                 Translate::new(Arc::new(obj), x.offset)
             }
+            Object::Transform(x) => {
+                let obj = x.object.build(library, time_0, time_1)?;
+                Transform::new(Arc::new(obj), transform_matrix(&x.transforms)?)?
+            }
+            Object::Instance(x) => Instance::from_init(x, library)?,
+            Object::KeyframedTransform(x) => {
+                let obj = x.object.build(library, time_0, time_1)?;
+                KeyframedTransform::new(Arc::new(obj), x.keyframes)?
+            }
             Object::FlipFace(x) => {
-                let obj = *x.object;
-                let obj: Hitable = obj.into();
+                let obj = x.object.build(library, time_0, time_1)?;
                 FlipFace::new(obj)
             }
             Object::ConstantMedium(x) => {
-                let obj = *x.boundary;
-                let obj: Hitable = obj.into();
+                let obj = x.boundary.build(library, time_0, time_1)?;
                 ConstantMedium::new(Arc::new(obj), x.density, x.texture)
             }
-        }
+            Object::GridMedium(x) => {
+                let grid = x.grid.build()?;
+                let obj = x.boundary.build(library, time_0, time_1)?;
+                GridMedium::new(
+                    Arc::new(obj),
+                    grid,
+                    x.density,
+                    x.texture,
+                    x.anisotropy,
+                    time_0,
+                    time_1,
+                )
+            }
+            Object::Triangle(x) => Triangle::new(x.vertex_0, x.vertex_1, x.vertex_2, x.material),
+            Object::Mesh(x) => {
+                let path = x.path;
+                let triangles = load_obj(&path, x.material).map_err(|err| {
+                    Error::new(
+                        ErrorKind::InvalidData,
+                        format!("failed to load mesh {}: {}", path, err),
+                    )
+                })?;
+                Mesh::new(triangles, time_0, time_1)?
+            }
+        };
+        Ok(hitable)
     }
 }
@@ -4,14 +4,13 @@ use crate::{
     hitable::{HitRecord, Hitable, HitableList},
     materials::Material,
     ray::Ray,
-    Float, Vec3,
+    Float, RandomGenerator, Vec3,
 };
-use rand::prelude::*;
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 
 /// Used for the scene files etc
-#[derive(Serialize, Deserialize, Debug, Copy, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct BoxyInit {
     pub corner_0: Vec3,
     pub corner_1: Vec3,
@@ -31,24 +30,54 @@ impl Boxy {
     pub fn new(corner_0: Vec3, corner_1: Vec3, material: Material) -> Hitable {
         let mut sides = HitableList::new();
         sides.add(XYRect::new(
-            corner_0.x, corner_1.x, corner_0.y, corner_1.y, corner_1.z, material,
+            corner_0.x,
+            corner_1.x,
+            corner_0.y,
+            corner_1.y,
+            corner_1.z,
+            material.clone(),
         ));
         sides.add(XYRect::new(
-            corner_0.x, corner_1.x, corner_0.y, corner_1.y, corner_0.z, material,
+            corner_0.x,
+            corner_1.x,
+            corner_0.y,
+            corner_1.y,
+            corner_0.z,
+            material.clone(),
         ));
 
         sides.add(XZRect::new(
-            corner_0.x, corner_1.x, corner_0.z, corner_1.z, corner_1.y, material,
+            corner_0.x,
+            corner_1.x,
+            corner_0.z,
+            corner_1.z,
+            corner_1.y,
+            material.clone(),
         ));
         sides.add(XZRect::new(
-            corner_0.x, corner_1.x, corner_0.z, corner_1.z, corner_0.y, material,
+            corner_0.x,
+            corner_1.x,
+            corner_0.z,
+            corner_1.z,
+            corner_0.y,
+            material.clone(),
         ));
 
         sides.add(YZRect::new(
-            corner_0.y, corner_1.y, corner_0.z, corner_1.z, corner_1.x, material,
+            corner_0.y,
+            corner_1.y,
+            corner_0.z,
+            corner_1.z,
+            corner_1.x,
+            material.clone(),
         ));
         sides.add(YZRect::new(
-            corner_0.y, corner_1.y, corner_0.z, corner_1.z, corner_0.x, material,
+            corner_0.y,
+            corner_1.y,
+            corner_0.z,
+            corner_1.z,
+            corner_0.x,
+            material.clone(),
         ));
 
         Hitable::Boxy(Boxy {
@@ -64,7 +93,7 @@ impl Boxy {
         ray: &Ray,
         distance_min: Float,
         distance_max: Float,
-        rng: ThreadRng,
+        rng: &mut RandomGenerator,
     ) -> Option<HitRecord> {
         self.sides.hit(ray, distance_min, distance_max, rng)
     }
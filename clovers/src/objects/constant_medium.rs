@@ -4,7 +4,7 @@ use crate::{
     materials::{isotropic::Isotropic, Material},
     ray::Ray,
     textures::Texture,
-    Float, Vec3, EPSILON_CONSTANT_MEDIUM,
+    Float, RandomGenerator, Vec3, EPSILON_CONSTANT_MEDIUM,
 };
 use rand::prelude::*;
 use serde::{Deserialize, Serialize};
@@ -49,7 +49,7 @@ impl ConstantMedium {
         ray: &Ray,
         distance_min: Float,
         distance_max: Float,
-        mut rng: ThreadRng,
+        rng: &mut RandomGenerator,
     ) -> Option<HitRecord> {
         let mut rec1: HitRecord;
         let mut rec2: HitRecord;
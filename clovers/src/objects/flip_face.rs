@@ -1,10 +1,9 @@
 use crate::{
     aabb::AABB,
-    hitable::{HitRecord, Hitable},
+    hitable::{HitRecord, Hitable, SurfaceSample},
     ray::Ray,
-    Float,
+    Float, RandomGenerator,
 };
-use rand::prelude::*;
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 
@@ -31,7 +30,7 @@ impl FlipFace {
         ray: &Ray,
         distance_min: Float,
         distance_max: Float,
-        rng: ThreadRng,
+        rng: &mut RandomGenerator,
     ) -> Option<HitRecord> {
         match self.object.hit(ray, distance_min, distance_max, rng) {
             Some(hit_record) => Some(HitRecord {
@@ -50,4 +49,9 @@ impl FlipFace {
     pub fn bounding_box(&self, t0: Float, t1: Float) -> Option<AABB> {
         self.object.bounding_box(t0, t1)
     }
+
+    /// Samples a point on the wrapped object. Only the facing of the surface is flipped, so the outward normal is unchanged.
+    pub fn sample_surface(&self, rng: &mut RandomGenerator) -> Option<SurfaceSample> {
+        self.object.sample_surface(rng)
+    }
 }
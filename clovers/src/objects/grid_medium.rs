@@ -0,0 +1,362 @@
+//! Heterogeneous participating media, with the density defined by a 3D grid.
+
+use crate::{
+    aabb::AABB,
+    hitable::{HitRecord, Hitable},
+    materials::{HenyeyGreenstein, Material},
+    perlin::Perlin,
+    ray::Ray,
+    textures::Texture,
+    Float, RandomGenerator, Vec3, EPSILON_CONSTANT_MEDIUM,
+};
+use rand::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    io::{Error, ErrorKind},
+    sync::Arc,
+};
+
+use super::Object;
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct GridMediumInit {
+    /// The boundary of the medium. The density grid is stretched over the bounding box of the boundary.
+    pub boundary: Box<Object>,
+    /// Source of the density values of the grid
+    pub grid: DensityGridInit,
+    /// Multiplier for the density values of the grid. Default value: 0.1
+    #[serde(default = "default_density")]
+    pub density: Float,
+    /// Scattering albedo of the medium
+    #[serde(default)]
+    pub texture: Texture,
+    /// Anisotropy of the [HenyeyGreenstein] phase function. Default value: 0.0, isotropic
+    #[serde(default)]
+    pub anisotropy: Float,
+}
+
+fn default_density() -> Float {
+    0.1
+}
+
+/// Sources for the density values of a [DensityGrid]
+#[derive(Serialize, Deserialize, Debug)]
+pub enum DensityGridInit {
+    /// Loads the grid from a voxel file, see [DensityGrid::load()]
+    Voxels {
+        /// Path to the voxel file, relative to the directory of the scene file
+        path: String,
+    },
+    /// Fills the grid with procedural [Perlin] turbulence
+    Perlin {
+        /// Number of voxels along each axis
+        resolution: [usize; 3],
+        /// Frequency of the noise: the number of noise features across the grid. Default value: 4.0
+        #[serde(default = "default_frequency")]
+        frequency: Float,
+        /// Number of octaves of the turbulence. Default value: 4
+        #[serde(default = "default_octaves")]
+        octaves: usize,
+    },
+}
+
+fn default_frequency() -> Float {
+    4.0
+}
+
+fn default_octaves() -> usize {
+    4
+}
+
+impl DensityGridInit {
+    /// Creates the density grid. Returns an error if the voxel file cannot be loaded, or if a grid dimension is zero.
+    pub fn build(&self) -> Result<DensityGrid, Error> {
+        match self {
+            DensityGridInit::Voxels { path } => DensityGrid::load(path).map_err(|err| {
+                Error::new(
+                    err.kind(),
+                    format!("failed to load voxel file {}: {}", path, err),
+                )
+            }),
+            DensityGridInit::Perlin {
+                resolution,
+                frequency,
+                octaves,
+            } => {
+                if resolution.contains(&0) {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        "the resolution of a Perlin density grid must be positive",
+                    ));
+                }
+                Ok(DensityGrid::perlin(*resolution, *frequency, *octaves))
+            }
+        }
+    }
+}
+
+/// A 3D grid of density values, with trilinear interpolation between the voxel centers. The grid covers the unit cube: positions are given in the `0.0..=1.0` range on each axis.
+pub struct DensityGrid {
+    resolution: [usize; 3],
+    /// Density values, with x varying fastest, then y, then z
+    values: Vec<Float>,
+    /// The largest value in the grid, the majorant for tracking
+    max: Float,
+}
+
+impl DensityGrid {
+    /// Creates a grid from the given values, with x varying fastest, then y, then z. Negative values are clamped to zero.
+    pub fn new(resolution: [usize; 3], values: Vec<Float>) -> DensityGrid {
+        assert_eq!(
+            values.len(),
+            resolution[0] * resolution[1] * resolution[2],
+            "The number of density values does not match the grid resolution"
+        );
+        let values: Vec<Float> = values.into_iter().map(|value| value.max(0.0)).collect();
+        let max = values.iter().copied().fold(0.0, Float::max);
+        DensityGrid {
+            resolution,
+            values,
+            max,
+        }
+    }
+
+    /// Loads a grid from a voxel file. The file is plain text: the three numbers of voxels along the x, y and z axes, followed by the density values, with x varying fastest, then y, then z. The numbers are separated by whitespace, and lines starting with `#` are comments.
+    pub fn load(path: &str) -> Result<DensityGrid, Error> {
+        let contents = fs::read_to_string(path)?;
+        let mut numbers = contents
+            .lines()
+            .filter(|line| !line.trim_start().starts_with('#'))
+            .flat_map(|line| line.split_whitespace());
+
+        let mut resolution = [0; 3];
+        for axis in resolution.iter_mut() {
+            *axis = match numbers.next().map(|number| number.parse::<usize>()) {
+                Some(Ok(size)) if size > 0 => size,
+                _ => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        "expected three positive grid dimensions",
+                    ))
+                }
+            };
+        }
+        let values = numbers
+            .map(|number| number.parse::<Float>())
+            .collect::<Result<Vec<Float>, _>>()
+            .map_err(|err| Error::new(ErrorKind::InvalidData, err))?;
+        if values.len() != resolution[0] * resolution[1] * resolution[2] {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "expected {} density values for a {}x{}x{} grid, found {}",
+                    resolution[0] * resolution[1] * resolution[2],
+                    resolution[0],
+                    resolution[1],
+                    resolution[2],
+                    values.len()
+                ),
+            ));
+        }
+
+        Ok(DensityGrid::new(resolution, values))
+    }
+
+    /// Creates a grid filled with [Perlin] turbulence, with the given number of noise features across the grid
+    pub fn perlin(resolution: [usize; 3], frequency: Float, octaves: usize) -> DensityGrid {
+        let perlin = Perlin::default();
+        let mut values = Vec::with_capacity(resolution[0] * resolution[1] * resolution[2]);
+        for z in 0..resolution[2] {
+            for y in 0..resolution[1] {
+                for x in 0..resolution[0] {
+                    let position = Vec3::new(
+                        (x as Float + 0.5) / resolution[0] as Float,
+                        (y as Float + 0.5) / resolution[1] as Float,
+                        (z as Float + 0.5) / resolution[2] as Float,
+                    );
+                    values.push(perlin.turbulence(frequency * position, octaves));
+                }
+            }
+        }
+        DensityGrid::new(resolution, values)
+    }
+
+    /// Returns the largest density in the grid
+    pub fn max(&self) -> Float {
+        self.max
+    }
+
+    /// Returns the interpolated density at the given position in the unit cube. Positions outside the cube get the density of the nearest voxel.
+    pub fn density(&self, position: Vec3) -> Float {
+        let mut cell = [0; 3];
+        let mut fraction = [0.0; 3];
+        for axis in 0..3 {
+            let size = self.resolution[axis];
+            let coordinate = (position[axis] * size as Float - 0.5).clamp(0.0, (size - 1) as Float);
+            let index = (coordinate.floor() as usize).min(size.saturating_sub(2));
+            cell[axis] = index;
+            fraction[axis] = coordinate - index as Float;
+        }
+
+        let mut density = 0.0;
+        for corner in 0..8 {
+            let mut weight = 1.0;
+            let mut index = 0;
+            let mut stride = 1;
+            for axis in 0..3 {
+                let offset = (corner >> axis) & 1;
+                let coordinate = (cell[axis] + offset).min(self.resolution[axis] - 1);
+                weight *= if offset == 1 {
+                    fraction[axis]
+                } else {
+                    1.0 - fraction[axis]
+                };
+                index += coordinate * stride;
+                stride *= self.resolution[axis];
+            }
+            density += weight * self.values[index];
+        }
+        density
+    }
+}
+
+/// A participating medium with a varying density, defined by a [DensityGrid] stretched over the bounding box of the boundary. Rays are tracked through the medium with [delta tracking](https://doi.org/10.1145/3084873.3084907): tentative collisions are sampled against the largest density in the grid, and accepted with the ratio of the local density to the largest density. Shadow rays estimate the [transmittance()](GridMedium::transmittance) with ratio tracking instead.
+pub struct GridMedium {
+    boundary: Arc<Hitable>,
+    grid: DensityGrid,
+    phase_function: Material,
+    /// Multiplier for the density values of the grid
+    density: Float,
+    /// The box the unit cube of the grid is stretched over
+    bounds: AABB,
+}
+
+impl GridMedium {
+    /// Creates a new medium. The grid is stretched over the bounding box of the boundary for the shutter interval from `time_0` to `time_1`. Panics if the boundary has no bounding box.
+    pub fn new(
+        boundary: Arc<Hitable>,
+        grid: DensityGrid,
+        density: Float,
+        texture: Texture,
+        anisotropy: Float,
+        time_0: Float,
+        time_1: Float,
+    ) -> Hitable {
+        let bounds = match boundary.bounding_box(time_0, time_1) {
+            Some(bounds) => bounds,
+            None => panic!("The boundary of a GridMedium must have a bounding box"),
+        };
+        Hitable::GridMedium(GridMedium {
+            boundary,
+            grid,
+            phase_function: HenyeyGreenstein::new(texture, anisotropy),
+            density,
+            bounds,
+        })
+    }
+
+    /// Returns the density of the medium at the given position in world space
+    pub fn density_at(&self, position: Vec3) -> Float {
+        let size = self.bounds.max - self.bounds.min;
+        let local = (position - self.bounds.min).component_div(&size);
+        self.density * self.grid.density(local)
+    }
+
+    /// Returns the distances along the ray where it enters and exits the boundary, clamped to the given range
+    fn segment(
+        &self,
+        ray: &Ray,
+        distance_min: Float,
+        distance_max: Float,
+        rng: &mut RandomGenerator,
+    ) -> Option<(Float, Float)> {
+        let enter = self
+            .boundary
+            .hit(ray, Float::NEG_INFINITY, Float::INFINITY, rng)?;
+        let exit = self.boundary.hit(
+            ray,
+            enter.distance + EPSILON_CONSTANT_MEDIUM,
+            Float::INFINITY,
+            rng,
+        )?;
+        let enter = enter.distance.max(distance_min).max(0.0);
+        let exit = exit.distance.min(distance_max);
+        if enter >= exit {
+            return None;
+        }
+        Some((enter, exit))
+    }
+
+    pub fn hit(
+        &self,
+        ray: &Ray,
+        distance_min: Float,
+        distance_max: Float,
+        rng: &mut RandomGenerator,
+    ) -> Option<HitRecord> {
+        let (enter, exit) = self.segment(ray, distance_min, distance_max, rng)?;
+        let majorant = self.density * self.grid.max();
+        if majorant <= 0.0 {
+            return None;
+        }
+
+        // Delta tracking: step through tentative collisions until a real one is accepted, or the ray leaves the medium
+        let ray_length: Float = ray.direction.norm();
+        let mut distance = enter;
+        loop {
+            let step = -(1.0 - rng.gen::<Float>()).ln() / majorant;
+            distance += step / ray_length;
+            if distance >= exit {
+                return None;
+            }
+            let position = ray.point_at_parameter(distance);
+            if rng.gen::<Float>() * majorant < self.density_at(position) {
+                return Some(HitRecord {
+                    distance,
+                    position,
+                    normal: Vec3::new(1.0, 0.0, 0.0), // arbitrary, volumes have no surface
+                    u: 0.5,
+                    v: 0.5,
+                    material: &self.phase_function,
+                    front_face: true,
+                });
+            }
+        }
+    }
+
+    /// Estimates the fraction of light that passes through the medium along the ray within the given range, with [ratio tracking](https://doi.org/10.1145/2661229.2661292). Unbiased, with less variance than testing for a collision with [hit()](GridMedium::hit). Used for shadow rays, see [Hitable::transmittance()].
+    pub fn transmittance(
+        &self,
+        ray: &Ray,
+        distance_min: Float,
+        distance_max: Float,
+        rng: &mut RandomGenerator,
+    ) -> Float {
+        let (enter, exit) = match self.segment(ray, distance_min, distance_max, rng) {
+            Some(segment) => segment,
+            None => return 1.0,
+        };
+        let majorant = self.density * self.grid.max();
+        if majorant <= 0.0 {
+            return 1.0;
+        }
+
+        let ray_length: Float = ray.direction.norm();
+        let mut distance = enter;
+        let mut transmittance = 1.0;
+        loop {
+            let step = -(1.0 - rng.gen::<Float>()).ln() / majorant;
+            distance += step / ray_length;
+            if distance >= exit {
+                return transmittance;
+            }
+            let position = ray.point_at_parameter(distance);
+            transmittance *= 1.0 - self.density_at(position) / majorant;
+        }
+    }
+
+    pub fn bounding_box(&self, t0: Float, t1: Float) -> Option<AABB> {
+        self.boundary.bounding_box(t0, t1)
+    }
+}
@@ -0,0 +1,134 @@
+//! Instancing: sharing the same geometry between many objects, each with its own transform and material.
+
+use crate::{
+    aabb::AABB,
+    bvhnode::SplitMethod,
+    hitable::{HitRecord, Hitable, HitableList},
+    materials::Material,
+    ray::Ray,
+    Float, RandomGenerator,
+};
+use nalgebra::Matrix4;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    io::{Error, ErrorKind},
+    sync::Arc,
+};
+
+use super::{transform_matrix, Object, Transform, TransformStep};
+
+/// A named geometry definition in the scene file, for referencing by [Instance] objects
+#[derive(Serialize, Deserialize, Debug)]
+pub struct GeometryInit {
+    /// Name of the geometry, referenced by the instances
+    pub name: String,
+    /// The objects of the geometry, in its own local space. Instances of the geometries defined before this one can be used too.
+    pub objects: Vec<Object>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct InstanceInit {
+    /// Name of the [GeometryInit] definition to instance
+    pub geometry: String,
+    /// The steps of the transform of the instance, applied in order. Default value: no transform
+    #[serde(default)]
+    pub transforms: Vec<TransformStep>,
+    /// Optional material used for the whole instance, instead of the materials of the geometry
+    #[serde(default)]
+    pub material: Option<Material>,
+}
+
+/// The built geometry definitions of a scene, by name. Each geometry is a bounding volume hierarchy, shared by all of its instances.
+#[derive(Default)]
+pub struct GeometryLibrary {
+    geometries: HashMap<String, Arc<Hitable>>,
+}
+
+impl GeometryLibrary {
+    /// Builds the geometry definitions in order, each into its own bounding volume hierarchy. Returns an error if a definition is empty, if a name is defined twice, or if an object can not be built.
+    pub fn new(
+        definitions: Vec<GeometryInit>,
+        time_0: Float,
+        time_1: Float,
+        split_method: SplitMethod,
+        rng: &mut RandomGenerator,
+    ) -> Result<GeometryLibrary, Error> {
+        let mut library = GeometryLibrary::default();
+        for definition in definitions {
+            if definition.objects.is_empty() {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("geometry {} has no objects", definition.name),
+                ));
+            }
+            if library.geometries.contains_key(&definition.name) {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("geometry {} is defined more than once", definition.name),
+                ));
+            }
+            let mut objects = HitableList::new();
+            for obj in definition.objects {
+                objects.add(obj.build(&library, time_0, time_1)?);
+            }
+            let bvh = objects.into_bvh(time_0, time_1, split_method, rng);
+            library.geometries.insert(definition.name, Arc::new(bvh));
+        }
+        Ok(library)
+    }
+
+    /// Returns the shared geometry with the given name
+    pub fn get(&self, name: &str) -> Option<Arc<Hitable>> {
+        self.geometries.get(name).cloned()
+    }
+}
+
+/// An instance of a shared geometry, with its own transform and optional material override. The geometry itself is not copied: any number of instances can reference the same bounding volume hierarchy.
+pub struct Instance {
+    transform: Transform,
+    material: Option<Material>,
+}
+
+impl Instance {
+    /// Creates a new instance of the geometry. Returns an error if the matrix is not invertible.
+    pub fn new(
+        geometry: Arc<Hitable>,
+        matrix: Matrix4<Float>,
+        material: Option<Material>,
+    ) -> Result<Hitable, Error> {
+        Ok(Hitable::Instance(Instance {
+            transform: Transform::build(geometry, matrix)?,
+            material,
+        }))
+    }
+
+    /// Creates a new instance from the scene file representation. Returns an error if the geometry is not defined in the library, or if the transform is not valid.
+    pub fn from_init(init: InstanceInit, library: &GeometryLibrary) -> Result<Hitable, Error> {
+        let geometry = library.get(&init.geometry).ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("instance of undefined geometry {}", init.geometry),
+            )
+        })?;
+        Instance::new(geometry, transform_matrix(&init.transforms)?, init.material)
+    }
+
+    pub fn hit(
+        &self,
+        ray: &Ray,
+        distance_min: Float,
+        distance_max: Float,
+        rng: &mut RandomGenerator,
+    ) -> Option<HitRecord> {
+        let mut hit_record = self.transform.hit(ray, distance_min, distance_max, rng)?;
+        if let Some(material) = &self.material {
+            hit_record.material = material;
+        }
+        Some(hit_record)
+    }
+
+    pub fn bounding_box(&self, t0: Float, t1: Float) -> Option<AABB> {
+        self.transform.bounding_box(t0, t1)
+    }
+}
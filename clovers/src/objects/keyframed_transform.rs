@@ -0,0 +1,237 @@
+//! Animated transforms of objects, interpolated between keyframes. Rays sample the transform at their own time, for motion blur.
+
+use crate::{
+    aabb::AABB,
+    hitable::{HitRecord, Hitable},
+    ray::Ray,
+    Float, RandomGenerator, Vec3,
+};
+use nalgebra::{Matrix4, Quaternion, Unit, UnitQuaternion};
+use serde::{Deserialize, Serialize};
+use std::{
+    io::{Error, ErrorKind},
+    sync::Arc,
+};
+
+use super::{AffineMap, Object};
+
+/// Number of poses sampled between each pair of keyframes when computing the bounding box
+const BOUNDING_BOX_STEPS: usize = 16;
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct KeyframedTransformInit {
+    pub object: Box<Object>,
+    /// The keyframes of the motion. At least one is required.
+    pub keyframes: Vec<Keyframe>,
+}
+
+/// The pose of an object at a given time. The object is scaled first, then rotated, then translated.
+#[derive(Copy, Clone, Serialize, Deserialize, Debug)]
+pub struct Keyframe {
+    pub time: Float,
+    /// Default value: no translation
+    #[serde(default = "default_translate")]
+    pub translate: Vec3,
+    /// Default value: no rotation
+    #[serde(default)]
+    pub rotate: Rotation,
+    /// Scaling factors along each axis. Default value: `[1.0, 1.0, 1.0]`
+    #[serde(default = "default_scale")]
+    pub scale: Vec3,
+}
+
+fn default_translate() -> Vec3 {
+    Vec3::new(0.0, 0.0, 0.0)
+}
+
+fn default_scale() -> Vec3 {
+    Vec3::new(1.0, 1.0, 1.0)
+}
+
+/// A rotation around the origin
+#[derive(Copy, Clone, Serialize, Deserialize, Debug)]
+pub enum Rotation {
+    /// Rotation around the given axis, by the given angle in degrees
+    AxisAngle { axis: Vec3, angle: Float },
+    /// Rotation by the given quaternion, in the order `[w, x, y, z]`. The quaternion is normalized.
+    Quaternion([Float; 4]),
+}
+
+impl Default for Rotation {
+    fn default() -> Self {
+        Rotation::Quaternion([1.0, 0.0, 0.0, 0.0])
+    }
+}
+
+impl Rotation {
+    /// Returns the rotation as a unit quaternion. Returns an error if the axis or the quaternion has zero length.
+    pub fn quaternion(&self) -> Result<UnitQuaternion<Float>, Error> {
+        match *self {
+            Rotation::AxisAngle { axis, angle } => {
+                let axis = Unit::try_new(axis, 0.0).ok_or_else(|| {
+                    Error::new(ErrorKind::InvalidData, "rotation axis must not be zero")
+                })?;
+                Ok(UnitQuaternion::from_axis_angle(&axis, angle.to_radians()))
+            }
+            Rotation::Quaternion([w, x, y, z]) => {
+                let quaternion = Quaternion::new(w, x, y, z);
+                if quaternion.norm() <= 0.0 {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        "rotation quaternion must not be zero",
+                    ));
+                }
+                Ok(UnitQuaternion::from_quaternion(quaternion))
+            }
+        }
+    }
+}
+
+/// Internal representation of a [Keyframe], ready for interpolation
+#[derive(Copy, Clone, Debug)]
+struct Pose {
+    translation: Vec3,
+    rotation: UnitQuaternion<Float>,
+    scale: Vec3,
+}
+
+impl Pose {
+    /// Interpolates between two poses: linearly for the translation and scale, and with spherical linear interpolation for the rotation
+    fn interpolate(&self, other: &Pose, t: Float) -> Pose {
+        Pose {
+            translation: self.translation.lerp(&other.translation, t),
+            rotation: self.rotation.slerp(&other.rotation, t),
+            scale: self.scale.lerp(&other.scale, t),
+        }
+    }
+
+    /// Returns the map from the local space of the object to world space. The inverse is built from the parts, avoiding a general matrix inversion for every ray.
+    fn map(&self) -> AffineMap {
+        let rotation = self.rotation.to_homogeneous();
+        let matrix = Matrix4::new_translation(&self.translation)
+            * rotation
+            * Matrix4::new_nonuniform_scaling(&self.scale);
+        let inverse_scale = Vec3::new(1.0, 1.0, 1.0).component_div(&self.scale);
+        let inverse = Matrix4::new_nonuniform_scaling(&inverse_scale)
+            * rotation.transpose()
+            * Matrix4::new_translation(&-self.translation);
+        AffineMap::with_inverse(matrix, inverse)
+    }
+}
+
+/// An object with a transform that changes over time, interpolated between keyframes. Each ray sees the object at the time of the ray, so sampling rays over the shutter interval of the camera produces motion blur. Before the first keyframe and after the last one, the object holds its pose.
+pub struct KeyframedTransform {
+    object: Arc<Hitable>,
+    /// Times of the keyframes, in increasing order
+    times: Vec<Float>,
+    poses: Vec<Pose>,
+}
+
+impl KeyframedTransform {
+    /// Creates a new keyframed transform. The keyframes are sorted by time. Returns an error if there are no keyframes, if a scale factor is zero, or if a rotation is not valid.
+    pub fn new(object: Arc<Hitable>, mut keyframes: Vec<Keyframe>) -> Result<Hitable, Error> {
+        if keyframes.is_empty() {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "KeyframedTransform requires at least one keyframe",
+            ));
+        }
+        if keyframes
+            .iter()
+            .any(|keyframe| keyframe.scale.iter().any(|&factor| factor == 0.0))
+        {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "KeyframedTransform scale factors must not be zero",
+            ));
+        }
+        keyframes.sort_by(|a, b| a.time.total_cmp(&b.time));
+        let poses = keyframes
+            .iter()
+            .map(|keyframe| {
+                Ok(Pose {
+                    translation: keyframe.translate,
+                    rotation: keyframe.rotate.quaternion()?,
+                    scale: keyframe.scale,
+                })
+            })
+            .collect::<Result<Vec<Pose>, Error>>()?;
+        Ok(Hitable::KeyframedTransform(KeyframedTransform {
+            object,
+            times: keyframes.iter().map(|keyframe| keyframe.time).collect(),
+            poses,
+        }))
+    }
+
+    /// Returns the interpolated pose at the given time
+    fn pose(&self, time: Float) -> Pose {
+        let last = self.times.len() - 1;
+        if time <= self.times[0] {
+            return self.poses[0];
+        }
+        if time >= self.times[last] {
+            return self.poses[last];
+        }
+        // Index of the first keyframe after the time; never the first one, due to the check above
+        let next = self.times.iter().position(|&t| t > time).unwrap_or(last);
+        let previous = next - 1;
+        let t = (time - self.times[previous]) / (self.times[next] - self.times[previous]);
+        self.poses[previous].interpolate(&self.poses[next], t)
+    }
+
+    pub fn hit(
+        &self,
+        ray: &Ray,
+        distance_min: Float,
+        distance_max: Float,
+        rng: &mut RandomGenerator,
+    ) -> Option<HitRecord> {
+        let map = self.pose(ray.time).map();
+        let local_ray = map.ray_to_local(ray);
+        let mut hit_record = self
+            .object
+            .hit(&local_ray, distance_min, distance_max, rng)?;
+        map.hit_to_world(&mut hit_record);
+        Some(hit_record)
+    }
+
+    /// Returns a box around the object over the whole time interval. The poses are sampled between the keyframes, and the boxes are padded to cover the arcs traced by the rotation between the samples.
+    pub fn bounding_box(&self, t0: Float, t1: Float) -> Option<AABB> {
+        let local = self.object.bounding_box(t0, t1)?;
+
+        // Sample times: the ends of the interval and the keyframes within, subdivided evenly
+        let mut keys: Vec<Float> = vec![t0];
+        keys.extend(self.times.iter().filter(|&&t| t > t0 && t < t1));
+        keys.push(t1);
+        let mut times: Vec<Float> = vec![t0];
+        for pair in keys.windows(2) {
+            for step in 1..=BOUNDING_BOX_STEPS {
+                let t = step as Float / BOUNDING_BOX_STEPS as Float;
+                times.push(pair[0] + t * (pair[1] - pair[0]));
+            }
+        }
+
+        // Farthest distance of the local box from the rotation center, for bounding the arcs
+        let extent = local.min.abs().sup(&local.max.abs());
+        let mut output_box: Option<AABB> = None;
+        let mut previous: Option<Pose> = None;
+        for time in times {
+            let pose = self.pose(time);
+            let mut bounding_box = pose.map().bounding_box(&local);
+            if let Some(previous) = previous {
+                // The sagitta of the arc traced by a point rotating between the two samples
+                let angle = previous.rotation.angle_to(&pose.rotation);
+                let radius = extent.component_mul(&pose.scale.abs().sup(&previous.scale.abs()));
+                let pad = radius.norm() * (1.0 - (angle / 2.0).cos());
+                let pad = Vec3::new(pad, pad, pad);
+                bounding_box = AABB::new(bounding_box.min - pad, bounding_box.max + pad);
+            }
+            output_box = Some(match output_box {
+                Some(old_box) => AABB::surrounding_box(old_box, bounding_box),
+                None => bounding_box,
+            });
+            previous = Some(pose);
+        }
+        output_box
+    }
+}
@@ -0,0 +1,200 @@
+use super::Triangle;
+use crate::{
+    aabb::AABB,
+    bvhnode::BVHNode,
+    flatbvh::FlatBVH,
+    hitable::{HitRecord, Hitable},
+    materials::Material,
+    ray::Ray,
+    Float, RandomGenerator, Vec3, EPSILON_SHADOW_ACNE,
+};
+use rand::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::{
+    io::{Error, ErrorKind},
+    sync::Arc,
+};
+
+/// Used for the scene files etc
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MeshInit {
+    /// Path to a Wavefront `.obj` file, relative to the directory of the scene file
+    pub path: String,
+    #[serde(default)]
+    pub material: Material,
+}
+
+/// A triangle mesh. The triangles are stored in their own internal [FlatBVH] for faster intersection tests, built with the [Surface Area Heuristic](crate::bvhnode::SplitMethod::Sah). For importance sampling, the mesh is sampled uniformly over its surface area.
+pub struct Mesh {
+    bvh: FlatBVH,
+    triangles: Vec<Arc<Hitable>>,
+    cumulative_areas: Vec<Float>,
+    area: Float,
+}
+
+impl Mesh {
+    /// Creates a new mesh from the triangles. Returns an error if there are no triangles.
+    pub fn new(triangles: Vec<Triangle>, time_0: Float, time_1: Float) -> Result<Hitable, Error> {
+        if triangles.is_empty() {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "cannot create a mesh with no triangles",
+            ));
+        }
+
+        let mut area: Float = 0.0;
+        let cumulative_areas: Vec<Float> = triangles
+            .iter()
+            .map(|triangle| {
+                area += triangle.area();
+                area
+            })
+            .collect();
+
+        let triangles: Vec<Arc<Hitable>> = triangles
+            .into_iter()
+            .map(|triangle| Arc::new(Hitable::Triangle(triangle)))
+            .collect();
+        let bvhnode = BVHNode::from_list_sah(triangles.clone(), time_0, time_1);
+        // Number the objects of the tree by their index in the list, for finding the hit triangle in pdf_value()
+        let bvh = FlatBVH::new(&bvhnode, time_0, time_1).with_object_ids(&triangles);
+
+        Ok(Hitable::Mesh(Mesh {
+            bvh,
+            triangles,
+            cumulative_areas,
+            area,
+        }))
+    }
+
+    pub fn hit(
+        &self,
+        ray: &Ray,
+        distance_min: Float,
+        distance_max: Float,
+        rng: &mut RandomGenerator,
+    ) -> Option<HitRecord<'_>> {
+        self.bvh.hit(ray, distance_min, distance_max, rng)
+    }
+
+    pub fn bounding_box(&self, t0: Float, t1: Float) -> Option<AABB> {
+        self.bvh.bounding_box(t0, t1)
+    }
+
+    pub fn pdf_value(
+        &self,
+        origin: Vec3,
+        vector: Vec3,
+        time: Float,
+        rng: &mut RandomGenerator,
+    ) -> Float {
+        match self.bvh.hit_object(
+            &Ray::new(origin, vector, time),
+            EPSILON_SHADOW_ACNE,
+            Float::INFINITY,
+            rng,
+        ) {
+            Some((index, hit_record)) => {
+                // The area density converts to solid angle with the geometric normal of the hit triangle, not the interpolated shading normal
+                let normal = match &*self.triangles[index] {
+                    Hitable::Triangle(triangle) => triangle.normal(),
+                    _ => hit_record.normal,
+                };
+                let distance_squared =
+                    hit_record.distance * hit_record.distance * vector.norm_squared();
+                let cosine = vector.dot(&normal).abs() / vector.norm();
+
+                distance_squared / (cosine * self.area)
+            }
+            None => 0.0,
+        }
+    }
+
+    pub fn random(&self, origin: Vec3, rng: &mut RandomGenerator) -> Vec3 {
+        // Pick a triangle with a probability proportional to its area
+        let target: Float = rng.gen::<Float>() * self.area;
+        let index = self
+            .cumulative_areas
+            .partition_point(|&cumulative| cumulative < target)
+            .min(self.triangles.len() - 1);
+        self.triangles[index].random(origin, rng)
+    }
+}
+
+/// Loads the triangles of all the models in a Wavefront `.obj` file. Faces are triangulated; vertex normals and texture coordinates are used if the file has them. Degenerate faces with zero area are skipped. All the triangles share the material.
+pub fn load_obj(path: &str, material: Material) -> Result<Vec<Triangle>, tobj::LoadError> {
+    let material = Arc::new(material);
+    let options = tobj::LoadOptions {
+        single_index: true,
+        triangulate: true,
+        ..Default::default()
+    };
+    let (models, _materials) = tobj::load_obj(path, &options)?;
+
+    let mut triangles: Vec<Triangle> = Vec::new();
+    for model in models {
+        let mesh = model.mesh;
+        let position = |i: usize| {
+            Vec3::new(
+                mesh.positions[3 * i],
+                mesh.positions[3 * i + 1],
+                mesh.positions[3 * i + 2],
+            )
+        };
+        let normal = |i: usize| {
+            Vec3::new(
+                mesh.normals[3 * i],
+                mesh.normals[3 * i + 1],
+                mesh.normals[3 * i + 2],
+            )
+        };
+        let uv = |i: usize| (mesh.texcoords[2 * i], mesh.texcoords[2 * i + 1]);
+
+        for face in mesh.indices.chunks_exact(3) {
+            let [a, b, c] = [face[0] as usize, face[1] as usize, face[2] as usize];
+            let vertices = [position(a), position(b), position(c)];
+            let normals = if mesh.normals.is_empty() {
+                None
+            } else {
+                Some([normal(a), normal(b), normal(c)])
+            };
+            let uvs = if mesh.texcoords.is_empty() {
+                None
+            } else {
+                Some([uv(a), uv(b), uv(c)])
+            };
+            let triangle = Triangle::from_vertices(vertices, normals, uvs, Arc::clone(&material));
+            // A triangle without area has no normal, and can never be hit or sampled
+            if triangle.area() > 0.0 {
+                triangles.push(triangle);
+            }
+        }
+    }
+
+    Ok(triangles)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn degenerate_faces_are_skipped() {
+        let path = std::env::temp_dir().join("clovers_degenerate_faces.obj");
+        std::fs::write(
+            &path,
+            "v 0 0 0\nv 1 0 0\nv 0 1 0\nv 2 0 0\nf 1 2 3\nf 1 2 4\nf 1 1 1\n",
+        )
+        .unwrap();
+        let triangles = load_obj(path.to_str().unwrap(), Material::default()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(triangles.len(), 1);
+        assert!((triangles[0].area() - 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn empty_mesh_is_an_error() {
+        let error = Mesh::new(Vec::new(), 0.0, 1.0).err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+}
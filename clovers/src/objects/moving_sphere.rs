@@ -3,10 +3,31 @@ use crate::{
     hitable::{HitRecord, Hitable},
     materials::Material,
     ray::Ray,
-    Float, Vec3, PI,
+    Float, RandomGenerator, Vec3, PI,
 };
-use rand::prelude::*;
 use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct MovingSphereInit {
+    /// Center of the sphere at `time_0`
+    pub center_0: Vec3,
+    /// Center of the sphere at `time_1`
+    pub center_1: Vec3,
+    /// Time of the first center. Default value: 0.0
+    #[serde(default)]
+    pub time_0: Float,
+    /// Time of the second center. Default value: 1.0
+    #[serde(default = "default_time_1")]
+    pub time_1: Float,
+    pub radius: Float,
+    #[serde(default)]
+    pub material: Material,
+}
+
+fn default_time_1() -> Float {
+    1.0
+}
+
 #[derive(Deserialize, Serialize, Debug)]
 pub struct MovingSphere {
     center_0: Vec3,
@@ -55,7 +76,7 @@ impl MovingSphere {
         ray: &Ray,
         distance_min: Float,
         distance_max: Float,
-        _rng: ThreadRng,
+        _rng: &mut RandomGenerator,
     ) -> Option<HitRecord> {
         let oc = ray.origin - self.center(ray.time);
         let a: Float = ray.direction.norm_squared();
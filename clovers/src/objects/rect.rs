@@ -1,16 +1,16 @@
 use crate::{
     aabb::AABB,
-    hitable::{HitRecord, Hitable},
+    hitable::{HitRecord, Hitable, SurfaceSample},
     materials::Material,
     ray::Ray,
-    Float, Vec3, EPSILON_RECT_THICKNESS, EPSILON_SHADOW_ACNE,
+    Float, RandomGenerator, Vec3, EPSILON_RECT_THICKNESS, EPSILON_SHADOW_ACNE,
 };
 use rand::prelude::*;
 use serde::{Deserialize, Serialize};
 
 // XY
 
-#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct XYRectInit {
     pub x0: Float,
     pub x1: Float,
@@ -21,7 +21,7 @@ pub struct XYRectInit {
     pub material: Material,
 }
 
-#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct XYRect {
     x0: Float,
     x1: Float,
@@ -55,7 +55,7 @@ impl XYRect {
         ray: &Ray,
         distance_min: Float,
         distance_max: Float,
-        _rng: ThreadRng,
+        _rng: &mut RandomGenerator,
     ) -> Option<HitRecord> {
         let t = (self.k - ray.origin.z) / ray.direction.z;
         if t < distance_min || t > distance_max {
@@ -91,7 +91,13 @@ impl XYRect {
         Some(output_box)
     }
 
-    pub fn pdf_value(&self, origin: Vec3, vector: Vec3, time: Float, rng: ThreadRng) -> Float {
+    pub fn pdf_value(
+        &self,
+        origin: Vec3,
+        vector: Vec3,
+        time: Float,
+        rng: &mut RandomGenerator,
+    ) -> Float {
         match self.hit(
             &Ray::new(origin, vector, time),
             EPSILON_SHADOW_ACNE,
@@ -110,7 +116,7 @@ impl XYRect {
         }
     }
 
-    pub fn random(&self, origin: Vec3, mut rng: ThreadRng) -> Vec3 {
+    pub fn random(&self, origin: Vec3, rng: &mut RandomGenerator) -> Vec3 {
         let random_point = Vec3::new(
             rng.gen_range(self.x0, self.x1),
             rng.gen_range(self.y0, self.y1),
@@ -118,11 +124,22 @@ impl XYRect {
         );
         random_point - origin
     }
+
+    /// Samples a point uniformly on the rectangle
+    pub fn sample_surface(&self, rng: &mut RandomGenerator) -> SurfaceSample {
+        let x = self.x0 + rng.gen::<Float>() * (self.x1 - self.x0);
+        let y = self.y0 + rng.gen::<Float>() * (self.y1 - self.y0);
+        SurfaceSample {
+            position: Vec3::new(x, y, self.k),
+            normal: Vec3::new(0.0, 0.0, 1.0),
+            area: ((self.x1 - self.x0) * (self.y1 - self.y0)).abs(),
+        }
+    }
 }
 
 // XZ
 
-#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct XZRectInit {
     pub x0: Float,
     pub x1: Float,
@@ -133,7 +150,7 @@ pub struct XZRectInit {
     pub material: Material,
 }
 
-#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct XZRect {
     x0: Float,
     x1: Float,
@@ -167,7 +184,7 @@ impl XZRect {
         ray: &Ray,
         distance_min: Float,
         distance_max: Float,
-        _rng: ThreadRng,
+        _rng: &mut RandomGenerator,
     ) -> Option<HitRecord> {
         let t = (self.k - ray.origin.y) / ray.direction.y;
         if t < distance_min || t > distance_max {
@@ -203,7 +220,13 @@ impl XZRect {
         Some(output_box)
     }
 
-    pub fn pdf_value(&self, origin: Vec3, vector: Vec3, time: Float, rng: ThreadRng) -> Float {
+    pub fn pdf_value(
+        &self,
+        origin: Vec3,
+        vector: Vec3,
+        time: Float,
+        rng: &mut RandomGenerator,
+    ) -> Float {
         match self.hit(
             &Ray::new(origin, vector, time),
             EPSILON_SHADOW_ACNE,
@@ -222,7 +245,7 @@ impl XZRect {
         }
     }
 
-    pub fn random(&self, origin: Vec3, mut rng: ThreadRng) -> Vec3 {
+    pub fn random(&self, origin: Vec3, rng: &mut RandomGenerator) -> Vec3 {
         let random_point = Vec3::new(
             rng.gen_range(self.x0, self.x1),
             self.k,
@@ -230,11 +253,22 @@ impl XZRect {
         );
         random_point - origin
     }
+
+    /// Samples a point uniformly on the rectangle
+    pub fn sample_surface(&self, rng: &mut RandomGenerator) -> SurfaceSample {
+        let x = self.x0 + rng.gen::<Float>() * (self.x1 - self.x0);
+        let z = self.z0 + rng.gen::<Float>() * (self.z1 - self.z0);
+        SurfaceSample {
+            position: Vec3::new(x, self.k, z),
+            normal: Vec3::new(0.0, 1.0, 0.0),
+            area: ((self.x1 - self.x0) * (self.z1 - self.z0)).abs(),
+        }
+    }
 }
 
 // YZ
 
-#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct YZRectInit {
     pub y0: Float,
     pub y1: Float,
@@ -245,7 +279,7 @@ pub struct YZRectInit {
     pub material: Material,
 }
 
-#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct YZRect {
     y0: Float,
     y1: Float,
@@ -279,7 +313,7 @@ impl YZRect {
         ray: &Ray,
         distance_min: Float,
         distance_max: Float,
-        _rng: ThreadRng,
+        _rng: &mut RandomGenerator,
     ) -> Option<HitRecord> {
         let t = (self.k - ray.origin.x) / ray.direction.x;
         if t < distance_min || t > distance_max {
@@ -315,7 +349,13 @@ impl YZRect {
         Some(output_box)
     }
 
-    pub fn pdf_value(&self, origin: Vec3, vector: Vec3, time: Float, rng: ThreadRng) -> Float {
+    pub fn pdf_value(
+        &self,
+        origin: Vec3,
+        vector: Vec3,
+        time: Float,
+        rng: &mut RandomGenerator,
+    ) -> Float {
         match self.hit(
             &Ray::new(origin, vector, time),
             EPSILON_SHADOW_ACNE,
@@ -334,7 +374,7 @@ impl YZRect {
         }
     }
 
-    pub fn random(&self, origin: Vec3, mut rng: ThreadRng) -> Vec3 {
+    pub fn random(&self, origin: Vec3, rng: &mut RandomGenerator) -> Vec3 {
         let random_point = Vec3::new(
             self.k,
             rng.gen_range(self.y0, self.y1),
@@ -342,4 +382,15 @@ impl YZRect {
         );
         random_point - origin
     }
+
+    /// Samples a point uniformly on the rectangle
+    pub fn sample_surface(&self, rng: &mut RandomGenerator) -> SurfaceSample {
+        let y = self.y0 + rng.gen::<Float>() * (self.y1 - self.y0);
+        let z = self.z0 + rng.gen::<Float>() * (self.z1 - self.z0);
+        SurfaceSample {
+            position: Vec3::new(self.k, y, z),
+            normal: Vec3::new(1.0, 0.0, 0.0),
+            area: ((self.y1 - self.y0) * (self.z1 - self.z0)).abs(),
+        }
+    }
 }
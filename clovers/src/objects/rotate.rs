@@ -2,9 +2,8 @@ use crate::{
     aabb::AABB,
     hitable::{HitRecord, Hitable},
     ray::Ray,
-    Float, Vec3,
+    Float, RandomGenerator, Vec3,
 };
-use rand::prelude::*;
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 
@@ -89,7 +88,7 @@ impl RotateY {
         ray: &Ray,
         distance_min: Float,
         distance_max: Float,
-        rng: ThreadRng,
+        rng: &mut RandomGenerator,
     ) -> Option<HitRecord> {
         let mut origin: Vec3 = ray.origin;
         let mut direction: Vec3 = ray.direction;
@@ -100,7 +99,7 @@ impl RotateY {
         direction[0] = self.cos_theta * ray.direction[0] - self.sin_theta * ray.direction[2];
         direction[2] = self.sin_theta * ray.direction[0] + self.cos_theta * ray.direction[2];
 
-        let rotated_r: Ray = Ray::new(origin, direction, ray.time);
+        let rotated_r: Ray = Ray::new(origin, direction, ray.time).with_wavelength(ray.wavelength);
 
         match self.object.hit(&rotated_r, distance_min, distance_max, rng) {
             // Did not hit rotated object, return None
@@ -1,16 +1,15 @@
 use crate::{
     aabb::AABB,
-    hitable::{HitRecord, Hitable},
+    hitable::{HitRecord, Hitable, SurfaceSample},
     materials::Material,
     onb::ONB,
-    random::random_to_sphere,
+    random::{random_to_sphere, random_unit_vector},
     ray::Ray,
-    Float, Vec3, EPSILON_SHADOW_ACNE, PI,
+    Float, RandomGenerator, Vec3, EPSILON_SHADOW_ACNE, PI,
 };
-use rand::prelude::*;
 use serde::{Deserialize, Serialize};
 
-#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct SphereInit {
     pub center: Vec3,
     pub radius: Float,
@@ -18,7 +17,7 @@ pub struct SphereInit {
     pub material: Material,
 }
 
-#[derive(Deserialize, Serialize, Debug, Copy, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct Sphere {
     center: Vec3,
     radius: Float,
@@ -49,7 +48,7 @@ impl Sphere {
         ray: &Ray,
         distance_min: Float,
         distance_max: Float,
-        _rng: ThreadRng,
+        _rng: &mut RandomGenerator,
     ) -> Option<HitRecord> {
         let oc: Vec3 = ray.origin - self.center;
         let a: Float = ray.direction.norm_squared();
@@ -107,7 +106,13 @@ impl Sphere {
         Some(output_box)
     }
 
-    pub fn pdf_value(&self, origin: Vec3, vector: Vec3, time: Float, rng: ThreadRng) -> Float {
+    pub fn pdf_value(
+        &self,
+        origin: Vec3,
+        vector: Vec3,
+        time: Float,
+        rng: &mut RandomGenerator,
+    ) -> Float {
         match self.hit(
             &Ray::new(origin, vector, time),
             EPSILON_SHADOW_ACNE,
@@ -126,10 +131,20 @@ impl Sphere {
         }
     }
 
-    pub fn random(&self, origin: Vec3, rng: ThreadRng) -> Vec3 {
+    pub fn random(&self, origin: Vec3, rng: &mut RandomGenerator) -> Vec3 {
         let direction: Vec3 = self.center - origin;
         let distance_squared: Float = direction.norm_squared();
         let uvw = ONB::build_from_w(direction);
         uvw.local(random_to_sphere(self.radius, distance_squared, rng))
     }
+
+    /// Samples a point uniformly on the sphere
+    pub fn sample_surface(&self, rng: &mut RandomGenerator) -> SurfaceSample {
+        let normal = random_unit_vector(rng);
+        SurfaceSample {
+            position: self.center + self.radius * normal,
+            normal,
+            area: 4.0 * PI * self.radius * self.radius,
+        }
+    }
 }
@@ -0,0 +1,207 @@
+//! Arbitrary affine transforms of objects: any combination of translation, rotation and scaling.
+
+use crate::{
+    aabb::AABB,
+    hitable::{HitRecord, Hitable},
+    ray::Ray,
+    Float, RandomGenerator, Vec3,
+};
+use nalgebra::{Matrix3, Matrix4, Point3, Quaternion, Unit, UnitQuaternion};
+use serde::{Deserialize, Serialize};
+use std::{
+    io::{Error, ErrorKind},
+    sync::Arc,
+};
+
+use super::Object;
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TransformInit {
+    pub object: Box<Object>,
+    /// The steps of the transform, applied to the object in order
+    pub transforms: Vec<TransformStep>,
+}
+
+/// A single step of a [Transform], for building the transform matrix in scene files
+#[derive(Copy, Clone, Serialize, Deserialize, Debug)]
+pub enum TransformStep {
+    /// Moves the object by the given offset
+    Translate(Vec3),
+    /// Rotates the object around the given axis through the origin, by the given angle in degrees. Positive angles rotate counterclockwise when looking against the axis.
+    Rotate { axis: Vec3, angle: Float },
+    /// Scales the object by the given factor along each axis. Negative factors mirror the object.
+    Scale(Vec3),
+    /// Rotates the object by the given quaternion, in the order `[w, x, y, z]`. The quaternion is normalized.
+    Quaternion([Float; 4]),
+}
+
+impl TransformStep {
+    /// Returns the 4x4 matrix of the step, operating on homogeneous coordinates. Returns an error if the axis of a rotation or the quaternion has zero length.
+    pub fn matrix(&self) -> Result<Matrix4<Float>, Error> {
+        match *self {
+            TransformStep::Translate(offset) => Ok(Matrix4::new_translation(&offset)),
+            TransformStep::Rotate { axis, angle } => {
+                let axis = Unit::try_new(axis, 0.0).ok_or_else(|| {
+                    Error::new(ErrorKind::InvalidData, "rotation axis must not be zero")
+                })?;
+                Ok(Matrix4::from_axis_angle(&axis, angle.to_radians()))
+            }
+            TransformStep::Scale(factors) => Ok(Matrix4::new_nonuniform_scaling(&factors)),
+            TransformStep::Quaternion([w, x, y, z]) => {
+                let quaternion = Quaternion::new(w, x, y, z);
+                if quaternion.norm() <= 0.0 {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        "rotation quaternion must not be zero",
+                    ));
+                }
+                Ok(UnitQuaternion::from_quaternion(quaternion).to_homogeneous())
+            }
+        }
+    }
+}
+
+/// Combines the steps into a single matrix. The first step is applied first. Returns an error if a step is not valid, see [TransformStep::matrix()].
+pub fn transform_matrix(steps: &[TransformStep]) -> Result<Matrix4<Float>, Error> {
+    steps.iter().try_fold(Matrix4::identity(), |matrix, step| {
+        Ok(step.matrix()? * matrix)
+    })
+}
+
+/// An object with an arbitrary affine transform, given as a 4x4 matrix. Rays are transformed into the local space of the object for the intersection tests, and the hit positions and normals are transformed back.
+pub struct Transform {
+    object: Arc<Hitable>,
+    map: AffineMap,
+}
+
+impl Transform {
+    /// Creates a new transformed object. Returns an error if the matrix is not invertible, e.g. when scaling by zero.
+    pub fn new(object: Arc<Hitable>, matrix: Matrix4<Float>) -> Result<Hitable, Error> {
+        Ok(Hitable::Transform(Transform::build(object, matrix)?))
+    }
+
+    /// Like [new()](Transform::new), but returns the bare struct for wrapping in other objects, e.g. [Instance](super::Instance)
+    pub(crate) fn build(object: Arc<Hitable>, matrix: Matrix4<Float>) -> Result<Transform, Error> {
+        Ok(Transform {
+            object,
+            map: AffineMap::new(matrix)?,
+        })
+    }
+
+    pub fn hit(
+        &self,
+        ray: &Ray,
+        distance_min: Float,
+        distance_max: Float,
+        rng: &mut RandomGenerator,
+    ) -> Option<HitRecord> {
+        let local_ray = self.map.ray_to_local(ray);
+        let mut hit_record = self
+            .object
+            .hit(&local_ray, distance_min, distance_max, rng)?;
+        self.map.hit_to_world(&mut hit_record);
+        Some(hit_record)
+    }
+
+    pub fn bounding_box(&self, t0: Float, t1: Float) -> Option<AABB> {
+        let bounding_box = self.object.bounding_box(t0, t1)?;
+        Some(self.map.bounding_box(&bounding_box))
+    }
+}
+
+/// An affine map between the local space of an object and world space, with the matrices needed for transforming rays, hitpoints and normals
+pub(crate) struct AffineMap {
+    /// From the local space of the object to world space
+    matrix: Matrix4<Float>,
+    /// From world space to the local space of the object
+    inverse: Matrix4<Float>,
+    /// Transforms the normals from local to world space: the inverse transpose of the linear part of the matrix
+    normal_matrix: Matrix3<Float>,
+}
+
+impl AffineMap {
+    /// Creates the map from the local to world matrix. Returns an error if the matrix is not invertible.
+    pub(crate) fn new(matrix: Matrix4<Float>) -> Result<AffineMap, Error> {
+        let inverse = matrix.try_inverse().ok_or_else(|| {
+            Error::new(ErrorKind::InvalidData, "transform matrix is not invertible")
+        })?;
+        Ok(AffineMap::with_inverse(matrix, inverse))
+    }
+
+    /// Creates the map from the local to world matrix and its known inverse
+    pub(crate) fn with_inverse(matrix: Matrix4<Float>, inverse: Matrix4<Float>) -> AffineMap {
+        let normal_matrix = Matrix3::from_fn(|row, column| inverse[(column, row)]);
+        AffineMap {
+            matrix,
+            inverse,
+            normal_matrix,
+        }
+    }
+
+    /// Transforms a world space ray into the local space. The direction is not normalized, so the distances along the ray stay the same in both spaces.
+    pub(crate) fn ray_to_local(&self, ray: &Ray) -> Ray {
+        Ray::new(
+            self.inverse
+                .transform_point(&Point3::from(ray.origin))
+                .coords,
+            self.inverse.transform_vector(&ray.direction),
+            ray.time,
+        )
+        .with_wavelength(ray.wavelength)
+    }
+
+    /// Transforms the position and normal of a local space hit back into world space
+    pub(crate) fn hit_to_world(&self, hit_record: &mut HitRecord) {
+        hit_record.position = self
+            .matrix
+            .transform_point(&Point3::from(hit_record.position))
+            .coords;
+        // The transformed normal keeps its side relative to the ray, so the front_face stays valid
+        hit_record.normal = (self.normal_matrix * hit_record.normal).normalize();
+    }
+
+    /// Returns the world space box around the transformed corners of the local space box
+    pub(crate) fn bounding_box(&self, bounding_box: &AABB) -> AABB {
+        let mut min = Vec3::new(Float::INFINITY, Float::INFINITY, Float::INFINITY);
+        let mut max = Vec3::new(
+            Float::NEG_INFINITY,
+            Float::NEG_INFINITY,
+            Float::NEG_INFINITY,
+        );
+        for corner in 0..8 {
+            let local = Vec3::from_fn(|axis, _| {
+                if (corner >> axis) & 1 == 0 {
+                    bounding_box.min[axis]
+                } else {
+                    bounding_box.max[axis]
+                }
+            });
+            let world = self.matrix.transform_point(&Point3::from(local)).coords;
+            min = min.inf(&world);
+            max = max.sup(&world);
+        }
+        AABB::new(min, max)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn zero_scale_is_an_error() {
+        let matrix = transform_matrix(&[TransformStep::Scale(Vec3::new(0.0, 1.0, 1.0))]).unwrap();
+        let error = AffineMap::new(matrix).err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn zero_rotation_axis_is_an_error() {
+        let step = TransformStep::Rotate {
+            axis: Vec3::new(0.0, 0.0, 0.0),
+            angle: 90.0,
+        };
+        let error = transform_matrix(&[step]).err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+}
@@ -2,9 +2,8 @@ use crate::{
     aabb::AABB,
     hitable::{HitRecord, Hitable},
     ray::Ray,
-    Float, Vec3,
+    Float, RandomGenerator, Vec3,
 };
-use rand::prelude::*;
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 
@@ -34,9 +33,10 @@ impl Translate {
         ray: &Ray,
         distance_min: Float,
         distance_max: Float,
-        rng: ThreadRng,
+        rng: &mut RandomGenerator,
     ) -> Option<HitRecord> {
-        let moved_ray: Ray = Ray::new(ray.origin - self.offset, ray.direction, ray.time);
+        let moved_ray: Ray = Ray::new(ray.origin - self.offset, ray.direction, ray.time)
+            .with_wavelength(ray.wavelength);
 
         match self.object.hit(&moved_ray, distance_min, distance_max, rng) {
             // Didn't hit anything, return None
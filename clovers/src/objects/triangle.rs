@@ -0,0 +1,312 @@
+use crate::{
+    aabb::AABB,
+    hitable::{HitRecord, Hitable, SurfaceSample},
+    materials::Material,
+    ray::Ray,
+    Float, RandomGenerator, Vec3, EPSILON_RECT_THICKNESS, EPSILON_SHADOW_ACNE,
+    EPSILON_TRIANGLE_DETERMINANT,
+};
+use rand::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct TriangleInit {
+    pub vertex_0: Vec3,
+    pub vertex_1: Vec3,
+    pub vertex_2: Vec3,
+    #[serde(default)]
+    pub material: Material,
+}
+
+/// A single triangle. Optionally has per-vertex normals for smooth shading and per-vertex texture coordinates, as loaded from a [Mesh](crate::objects::Mesh). The material is shared, so that the triangles of a mesh do not each hold a copy of it.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Triangle {
+    vertices: [Vec3; 3],
+    normals: Option<[Vec3; 3]>,
+    uvs: [(Float, Float); 3],
+    edge_1: Vec3,
+    edge_2: Vec3,
+    normal: Vec3,
+    area: Float,
+    material: Arc<Material>,
+}
+
+impl Triangle {
+    pub fn new(vertex_0: Vec3, vertex_1: Vec3, vertex_2: Vec3, material: Material) -> Hitable {
+        Hitable::Triangle(Triangle::from_vertices(
+            [vertex_0, vertex_1, vertex_2],
+            None,
+            None,
+            Arc::new(material),
+        ))
+    }
+
+    /// Creates a new triangle from its vertices, with optional per-vertex normals and texture coordinates. Without texture coordinates, the barycentric coordinates of the hitpoint are used as the U,V surface coordinates.
+    pub fn from_vertices(
+        vertices: [Vec3; 3],
+        normals: Option<[Vec3; 3]>,
+        uvs: Option<[(Float, Float); 3]>,
+        material: Arc<Material>,
+    ) -> Triangle {
+        let edge_1 = vertices[1] - vertices[0];
+        let edge_2 = vertices[2] - vertices[0];
+        let cross = edge_1.cross(&edge_2);
+        let area = 0.5 * cross.norm();
+        let normal = cross.normalize();
+        let uvs = uvs.unwrap_or([(0.0, 0.0), (1.0, 0.0), (0.0, 1.0)]);
+
+        Triangle {
+            vertices,
+            normals,
+            uvs,
+            edge_1,
+            edge_2,
+            normal,
+            area,
+            material,
+        }
+    }
+
+    /// Returns the surface area of the triangle. Zero for degenerate triangles, which have no valid normal.
+    pub fn area(&self) -> Float {
+        self.area
+    }
+
+    /// Returns the geometric normal of the triangle, following the winding order of the vertices
+    pub fn normal(&self) -> Vec3 {
+        self.normal
+    }
+
+    /// Ray-triangle intersection based on the [Möller–Trumbore algorithm](https://en.wikipedia.org/wiki/M%C3%B6ller%E2%80%93Trumbore_intersection_algorithm)
+    pub fn hit(
+        &self,
+        ray: &Ray,
+        distance_min: Float,
+        distance_max: Float,
+        _rng: &mut RandomGenerator,
+    ) -> Option<HitRecord<'_>> {
+        let pvec: Vec3 = ray.direction.cross(&self.edge_2);
+        let determinant: Float = self.edge_1.dot(&pvec);
+        // Ray is parallel to the plane of the triangle
+        if determinant.abs() < EPSILON_TRIANGLE_DETERMINANT {
+            return None;
+        }
+        let inverse_determinant: Float = 1.0 / determinant;
+
+        let tvec: Vec3 = ray.origin - self.vertices[0];
+        let beta: Float = tvec.dot(&pvec) * inverse_determinant;
+        if !(0.0..=1.0).contains(&beta) {
+            return None;
+        }
+
+        let qvec: Vec3 = tvec.cross(&self.edge_1);
+        let gamma: Float = ray.direction.dot(&qvec) * inverse_determinant;
+        if gamma < 0.0 || beta + gamma > 1.0 {
+            return None;
+        }
+
+        let distance: Float = self.edge_2.dot(&qvec) * inverse_determinant;
+        if distance < distance_min || distance > distance_max {
+            return None;
+        }
+        let alpha: Float = 1.0 - beta - gamma;
+
+        let position: Vec3 = ray.point_at_parameter(distance);
+        let u: Float = alpha * self.uvs[0].0 + beta * self.uvs[1].0 + gamma * self.uvs[2].0;
+        let v: Float = alpha * self.uvs[0].1 + beta * self.uvs[1].1 + gamma * self.uvs[2].1;
+
+        // Smooth shading: interpolate the vertex normals if we have them
+        let shading_normal: Vec3 = match self.normals {
+            Some(normals) => {
+                (alpha * normals[0] + beta * normals[1] + gamma * normals[2]).normalize()
+            }
+            None => self.normal,
+        };
+        // Front face is determined by the geometric normal, the shading normal follows it
+        let front_face: bool = ray.direction.dot(&self.normal) < 0.0;
+        let normal: Vec3 = if front_face {
+            shading_normal
+        } else {
+            -shading_normal
+        };
+
+        Some(HitRecord {
+            distance,
+            position,
+            normal,
+            u,
+            v,
+            material: &self.material,
+            front_face,
+        })
+    }
+
+    pub fn bounding_box(&self, _t0: Float, _t1: Float) -> Option<AABB> {
+        let mut min: Vec3 = self.vertices[0];
+        let mut max: Vec3 = self.vertices[0];
+        for vertex in self.vertices.iter().skip(1) {
+            for c in 0..3 {
+                min[c] = min[c].min(vertex[c]);
+                max[c] = max[c].max(vertex[c]);
+            }
+        }
+        // The bounding box must have non-zero width in each dimension, so pad all dimensions a small amount.
+        let padding = Vec3::new(
+            EPSILON_RECT_THICKNESS,
+            EPSILON_RECT_THICKNESS,
+            EPSILON_RECT_THICKNESS,
+        );
+        Some(AABB::new(min - padding, max + padding))
+    }
+
+    pub fn pdf_value(
+        &self,
+        origin: Vec3,
+        vector: Vec3,
+        time: Float,
+        rng: &mut RandomGenerator,
+    ) -> Float {
+        match self.hit(
+            &Ray::new(origin, vector, time),
+            EPSILON_SHADOW_ACNE,
+            Float::INFINITY,
+            rng,
+        ) {
+            Some(hit_record) => {
+                let distance_squared =
+                    hit_record.distance * hit_record.distance * vector.norm_squared();
+                let cosine = vector.dot(&self.normal).abs() / vector.norm();
+
+                distance_squared / (cosine * self.area)
+            }
+            None => 0.0,
+        }
+    }
+
+    pub fn random(&self, origin: Vec3, rng: &mut RandomGenerator) -> Vec3 {
+        // Uniform sampling: fold the points of the parallelogram back into the triangle
+        let mut beta: Float = rng.gen();
+        let mut gamma: Float = rng.gen();
+        if beta + gamma > 1.0 {
+            beta = 1.0 - beta;
+            gamma = 1.0 - gamma;
+        }
+        let random_point = self.vertices[0] + beta * self.edge_1 + gamma * self.edge_2;
+        random_point - origin
+    }
+
+    /// Samples a point uniformly on the triangle. The normal is the geometric normal, following the winding order of the vertices.
+    pub fn sample_surface(&self, rng: &mut RandomGenerator) -> SurfaceSample {
+        let origin = Vec3::new(0.0, 0.0, 0.0);
+        SurfaceSample {
+            position: self.random(origin, rng),
+            normal: self.normal,
+            area: self.area,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+
+    fn triangle() -> Triangle {
+        Triangle::from_vertices(
+            [
+                Vec3::new(0.0, 0.0, 0.0),
+                Vec3::new(1.0, 0.0, 0.0),
+                Vec3::new(0.0, 1.0, 0.0),
+            ],
+            None,
+            Some([(0.0, 0.0), (1.0, 0.0), (0.0, 1.0)]),
+            Arc::new(Material::default()),
+        )
+    }
+
+    #[test]
+    fn hit_front_face() {
+        let mut rng = RandomGenerator::seed_from_u64(0);
+        let ray = Ray::new(Vec3::new(0.25, 0.25, 1.0), Vec3::new(0.0, 0.0, -1.0), 0.0);
+        let triangle = triangle();
+        let hit_record = triangle
+            .hit(&ray, EPSILON_SHADOW_ACNE, Float::INFINITY, &mut rng)
+            .expect("ray through the triangle should hit");
+        assert!((hit_record.distance - 1.0).abs() < 1e-6);
+        assert!((hit_record.position - Vec3::new(0.25, 0.25, 0.0)).norm() < 1e-6);
+        assert!(hit_record.front_face);
+        assert!((hit_record.normal - Vec3::new(0.0, 0.0, 1.0)).norm() < 1e-6);
+    }
+
+    #[test]
+    fn hit_back_face() {
+        let mut rng = RandomGenerator::seed_from_u64(0);
+        let ray = Ray::new(Vec3::new(0.25, 0.25, -1.0), Vec3::new(0.0, 0.0, 1.0), 0.0);
+        let triangle = triangle();
+        let hit_record = triangle
+            .hit(&ray, EPSILON_SHADOW_ACNE, Float::INFINITY, &mut rng)
+            .expect("ray through the back of the triangle should hit");
+        assert!(!hit_record.front_face);
+        assert!((hit_record.normal - Vec3::new(0.0, 0.0, -1.0)).norm() < 1e-6);
+    }
+
+    #[test]
+    fn miss() {
+        let mut rng = RandomGenerator::seed_from_u64(0);
+        let triangle = triangle();
+        // Outside the hypotenuse
+        let outside = Ray::new(Vec3::new(0.75, 0.75, 1.0), Vec3::new(0.0, 0.0, -1.0), 0.0);
+        assert!(triangle
+            .hit(&outside, EPSILON_SHADOW_ACNE, Float::INFINITY, &mut rng)
+            .is_none());
+        // Parallel to the plane of the triangle
+        let parallel = Ray::new(Vec3::new(-1.0, 0.25, 0.0), Vec3::new(1.0, 0.0, 0.0), 0.0);
+        assert!(triangle
+            .hit(&parallel, EPSILON_SHADOW_ACNE, Float::INFINITY, &mut rng)
+            .is_none());
+        // Beyond the maximum distance
+        let far = Ray::new(Vec3::new(0.25, 0.25, 1.0), Vec3::new(0.0, 0.0, -1.0), 0.0);
+        assert!(triangle
+            .hit(&far, EPSILON_SHADOW_ACNE, 0.5, &mut rng)
+            .is_none());
+    }
+
+    #[test]
+    fn barycentric_uvs() {
+        let mut rng = RandomGenerator::seed_from_u64(0);
+        let triangle = Triangle::from_vertices(
+            [
+                Vec3::new(0.0, 0.0, 0.0),
+                Vec3::new(2.0, 0.0, 0.0),
+                Vec3::new(0.0, 2.0, 0.0),
+            ],
+            None,
+            Some([(0.5, 0.5), (1.0, 0.5), (0.5, 1.0)]),
+            Arc::new(Material::default()),
+        );
+        // Barycentric coordinates: alpha = 0.5, beta = 0.25, gamma = 0.25
+        let ray = Ray::new(Vec3::new(0.5, 0.5, 1.0), Vec3::new(0.0, 0.0, -1.0), 0.0);
+        let hit_record = triangle
+            .hit(&ray, EPSILON_SHADOW_ACNE, Float::INFINITY, &mut rng)
+            .unwrap();
+        assert!((hit_record.u - 0.625).abs() < 1e-6);
+        assert!((hit_record.v - 0.625).abs() < 1e-6);
+
+        // Without texture coordinates, the barycentric coordinates are used directly
+        let triangle =
+            Triangle::from_vertices(triangle.vertices, None, None, Arc::new(Material::default()));
+        let hit_record = triangle
+            .hit(&ray, EPSILON_SHADOW_ACNE, Float::INFINITY, &mut rng)
+            .unwrap();
+        assert!((hit_record.u - 0.25).abs() < 1e-6);
+        assert!((hit_record.v - 0.25).abs() < 1e-6);
+    }
+
+    #[test]
+    fn area_and_normal() {
+        let triangle = triangle();
+        assert!((triangle.area() - 0.5).abs() < 1e-6);
+        assert!((triangle.normal() - Vec3::new(0.0, 0.0, 1.0)).norm() < 1e-6);
+    }
+}
@@ -0,0 +1,238 @@
+//! Saving rendered pixel buffers to image files.
+//!
+//! High dynamic range formats store the linear radiance as 32-bit floats, scaled by the exposure but without any tone mapping operator, gamma correction or clamping. Other formats are saved as 8-bit images, after [tone mapping](crate::tonemap).
+
+use crate::{
+    aov::{Aov, AovBuffers},
+    color::Color,
+    tonemap::{ToneMapping, Transfer},
+    Float,
+};
+use image::{codecs::hdr::HdrEncoder, ImageBuffer, Rgb, RgbImage};
+use std::{
+    fs::File,
+    io::{BufWriter, Error},
+    path::Path,
+};
+
+/// The file formats supported for saving renders
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Format {
+    /// OpenEXR, 32-bit float per channel
+    Exr,
+    /// Radiance HDR, shared-exponent float
+    Hdr,
+    /// Any 8-bit format supported by the `image` crate, e.g. PNG
+    LowDynamicRange,
+}
+
+impl Format {
+    /// Picks the file format based on the extension of the given path
+    pub fn from_path(path: &str) -> Format {
+        let extension = Path::new(path)
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.to_lowercase());
+        match extension.as_deref() {
+            Some("exr") => Format::Exr,
+            Some("hdr") => Format::Hdr,
+            _ => Format::LowDynamicRange,
+        }
+    }
+
+    /// Returns true for formats that store the linear radiance without clamping
+    pub fn is_high_dynamic_range(&self) -> bool {
+        !matches!(self, Format::LowDynamicRange)
+    }
+}
+
+/// Saves a pixel buffer of linear colors to the given path. The file format is chosen by the extension of the path. The pixels are in row-major order, starting from the top left corner of the image. The tone mapping is only applied for low dynamic range formats; high dynamic range formats only get its exposure.
+pub fn save(
+    path: &str,
+    width: u32,
+    height: u32,
+    pixels: &[Color],
+    tone_mapping: ToneMapping,
+) -> Result<(), Error> {
+    match Format::from_path(path) {
+        Format::Exr => save_exr(path, width, height, &expose(pixels, tone_mapping)),
+        Format::Hdr => save_hdr(path, width, height, &expose(pixels, tone_mapping)),
+        Format::LowDynamicRange => save_ldr(path, width, height, pixels, tone_mapping),
+    }
+}
+
+/// Saves the linear colors as an OpenEXR file with 32-bit float channels
+pub fn save_exr(path: &str, width: u32, height: u32, pixels: &[Color]) -> Result<(), Error> {
+    let width = width as usize;
+    exr::prelude::write_rgb_file(path, width, height as usize, |x, y| {
+        let pixel = pixels[y * width + x];
+        (pixel.r, pixel.g, pixel.b)
+    })
+    .map_err(Error::other)
+}
+
+/// Saves the linear colors as an OpenEXR file, together with the given AOVs as extra channels. The channels of each AOV are prefixed with its name, e.g. `albedo.R` or `normal.X`, which most compositing software shows as separate layers. The colors are scaled by the exposure of the tone mapping; the AOVs are stored as they are.
+pub fn save_exr_with_aovs(
+    path: &str,
+    width: u32,
+    height: u32,
+    pixels: &[Color],
+    tone_mapping: ToneMapping,
+    aov_buffers: &AovBuffers,
+    aovs: &[Aov],
+) -> Result<(), Error> {
+    use exr::prelude::*;
+
+    let pixels = expose(pixels, tone_mapping);
+
+    let mut channels: SmallVec<[AnyChannel<FlatSamples>; 4]> = SmallVec::new();
+    channels.push(AnyChannel::new(
+        "R",
+        FlatSamples::F32(pixels.iter().map(|pixel| pixel.r).collect()),
+    ));
+    channels.push(AnyChannel::new(
+        "G",
+        FlatSamples::F32(pixels.iter().map(|pixel| pixel.g).collect()),
+    ));
+    channels.push(AnyChannel::new(
+        "B",
+        FlatSamples::F32(pixels.iter().map(|pixel| pixel.b).collect()),
+    ));
+    for &aov in aovs {
+        for (index, channel) in aov.channels().iter().enumerate() {
+            channels.push(AnyChannel::new(
+                format!("{}.{}", aov.name(), channel).as_str(),
+                FlatSamples::F32(aov_buffers.channel(aov, index)),
+            ));
+        }
+    }
+
+    let layer = Layer::new(
+        (width as usize, height as usize),
+        LayerAttributes::named("clovers"),
+        Encoding::FAST_LOSSLESS,
+        AnyChannels::sort(channels),
+    );
+    Image::from_layer(layer)
+        .write()
+        .to_file(path)
+        .map_err(std::io::Error::other)
+}
+
+/// Saves each of the given AOVs as a separate image next to the given path, named `<stem>_<aov>.<extension>`. High dynamic range formats store the raw values, other formats a [visualization](AovBuffers::visualize) of them. Returns the paths of the saved files.
+pub fn save_aovs(
+    path: &str,
+    width: u32,
+    height: u32,
+    aov_buffers: &AovBuffers,
+    aovs: &[Aov],
+) -> Result<Vec<String>, Error> {
+    let path = Path::new(path);
+    let stem = path
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or("render");
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or("png");
+    let format = Format::from_path(&format!("aov.{}", extension));
+    let linear = ToneMapping {
+        transfer: Transfer::Gamma(1.0),
+        ..ToneMapping::default()
+    };
+
+    let mut saved = Vec::new();
+    for &aov in aovs {
+        let target = path.with_file_name(format!("{}_{}.{}", stem, aov.name(), extension));
+        let target = target.to_string_lossy().into_owned();
+        let pixels = if format.is_high_dynamic_range() {
+            aov_buffers.colors(aov)
+        } else {
+            aov_buffers.visualize(aov)
+        };
+        save(&target, width, height, &pixels, linear)?;
+        saved.push(target);
+    }
+    Ok(saved)
+}
+
+/// Returns the path for a frame of an animation, numbered with the given number of digits: `<stem>_<frame>.<extension>`
+pub fn frame_path(path: &str, frame: u32, digits: usize) -> String {
+    let path = Path::new(path);
+    let stem = path
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or("render");
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or("png");
+    let target = path.with_file_name(format!(
+        "{}_{:0digits$}.{}",
+        stem,
+        frame,
+        extension,
+        digits = digits
+    ));
+    target.to_string_lossy().into_owned()
+}
+
+/// Scales the linear colors by the exposure of the tone mapping, for the high dynamic range formats
+fn expose(pixels: &[Color], tone_mapping: ToneMapping) -> Vec<Color> {
+    pixels
+        .iter()
+        .map(|&pixel| tone_mapping.expose(pixel))
+        .collect()
+}
+
+/// Saves the linear colors as a Radiance HDR file
+pub fn save_hdr(path: &str, width: u32, height: u32, pixels: &[Color]) -> Result<(), Error> {
+    let writer = BufWriter::new(File::create(path)?);
+    let data: Vec<Rgb<f32>> = pixels
+        .iter()
+        .map(|pixel| Rgb([pixel.r, pixel.g, pixel.b]))
+        .collect();
+    HdrEncoder::new(writer)
+        .encode(&data, width as usize, height as usize)
+        .map_err(Error::other)
+}
+
+/// Saves the colors as an 8-bit image, after tone mapping. The format is chosen by the `image` crate based on the extension of the path.
+pub fn save_ldr(
+    path: &str,
+    width: u32,
+    height: u32,
+    pixels: &[Color],
+    tone_mapping: ToneMapping,
+) -> Result<(), Error> {
+    let mut img: RgbImage = ImageBuffer::new(width, height);
+    img.enumerate_pixels_mut().for_each(|(x, y, pixel)| {
+        let index = y * width + x;
+        let color = tone_mapping.apply(pixels[index as usize]);
+        *pixel = Rgb(color.to_rgb_u8());
+    });
+    img.save(path).map_err(Error::other)
+}
+
+/// Saves a heatmap of the number of samples taken for each pixel. The counts are normalized so that the pixels with the most samples are white. The counts are in row-major order, starting from the top left corner of the image.
+pub fn save_sample_heatmap(
+    path: &str,
+    width: u32,
+    height: u32,
+    counts: &[u32],
+) -> Result<(), Error> {
+    let max = counts.iter().copied().max().unwrap_or(0).max(1) as Float;
+    let pixels: Vec<Color> = counts
+        .iter()
+        .map(|&count| {
+            let value = count as Float / max;
+            Color::new(value, value, value)
+        })
+        .collect();
+    let linear = ToneMapping {
+        transfer: Transfer::Gamma(1.0),
+        ..ToneMapping::default()
+    };
+    save(path, width, height, &pixels, linear)
+}
@@ -1,30 +1,49 @@
 //! Probability density functions
 
-use crate::{hitable::Hitable, onb::ONB, random::random_cosine_direction, Float, Vec3, PI};
+use crate::{
+    environment::EnvironmentMap,
+    hitable::Hitable,
+    materials::{henyey_greenstein, principled::ggx_distribution},
+    onb::ONB,
+    random::{random_cosine_direction, random_unit_vector},
+    Float, RandomGenerator, Vec3, PI,
+};
 use rand::prelude::*;
 use std::sync::Arc;
 
 pub enum PDF<'a> {
     CosinePDF(CosinePDF),
+    EnvironmentPDF(EnvironmentPDF<'a>),
     HitablePDF(HitablePDF<'a>),
     MixturePDF(MixturePDF<'a>),
+    MicrofacetPDF(MicrofacetPDF),
+    SpherePDF(SpherePDF),
+    HenyeyGreensteinPDF(HenyeyGreensteinPDF),
     ZeroPDF(ZeroPDF),
 }
 
 impl<'a> PDF<'a> {
-    pub fn value(&self, direction: Vec3, time: Float, rng: ThreadRng) -> Float {
+    pub fn value(&self, direction: Vec3, time: Float, rng: &mut RandomGenerator) -> Float {
         match self {
             PDF::CosinePDF(p) => p.value(direction, time, rng),
+            PDF::EnvironmentPDF(p) => p.value(direction, time, rng),
             PDF::HitablePDF(p) => p.value(direction, time, rng),
             PDF::MixturePDF(p) => p.value(direction, time, rng),
+            PDF::MicrofacetPDF(p) => p.value(direction, time, rng),
+            PDF::SpherePDF(p) => p.value(direction, time, rng),
+            PDF::HenyeyGreensteinPDF(p) => p.value(direction, time, rng),
             PDF::ZeroPDF(p) => p.value(direction, time, rng),
         }
     }
-    pub fn generate(&self, rng: ThreadRng) -> Vec3 {
+    pub fn generate(&self, rng: &mut RandomGenerator) -> Vec3 {
         match self {
             PDF::CosinePDF(p) => p.generate(rng),
+            PDF::EnvironmentPDF(p) => p.generate(rng),
             PDF::HitablePDF(p) => p.generate(rng),
             PDF::MixturePDF(p) => p.generate(rng),
+            PDF::MicrofacetPDF(p) => p.generate(rng),
+            PDF::SpherePDF(p) => p.generate(rng),
+            PDF::HenyeyGreensteinPDF(p) => p.generate(rng),
             PDF::ZeroPDF(p) => p.generate(rng),
         }
     }
@@ -41,7 +60,7 @@ impl<'a> CosinePDF {
         })
     }
 
-    pub fn value(&self, direction: Vec3, _time: Float, _rng: ThreadRng) -> Float {
+    pub fn value(&self, direction: Vec3, _time: Float, _rng: &mut RandomGenerator) -> Float {
         let cosine = direction.normalize().dot(&self.uvw.w);
         if cosine <= 0.0 {
             0.0
@@ -50,11 +69,30 @@ impl<'a> CosinePDF {
         }
     }
 
-    pub fn generate(&self, rng: ThreadRng) -> Vec3 {
+    pub fn generate(&self, rng: &mut RandomGenerator) -> Vec3 {
         self.uvw.local(random_cosine_direction(rng))
     }
 }
 
+/// Importance sampling of an [EnvironmentMap], for using the environment as a light source
+pub struct EnvironmentPDF<'a> {
+    map: &'a EnvironmentMap,
+}
+
+impl<'a> EnvironmentPDF<'a> {
+    pub fn new(map: &'a EnvironmentMap) -> PDF<'a> {
+        PDF::EnvironmentPDF(EnvironmentPDF { map })
+    }
+
+    pub fn value(&self, direction: Vec3, _time: Float, _rng: &mut RandomGenerator) -> Float {
+        self.map.pdf_value(direction)
+    }
+
+    pub fn generate(&self, rng: &mut RandomGenerator) -> Vec3 {
+        self.map.random(rng)
+    }
+}
+
 pub struct HitablePDF<'a> {
     origin: Vec3,
     hitable: &'a Hitable,
@@ -65,35 +103,40 @@ impl<'a> HitablePDF<'a> {
         PDF::HitablePDF(HitablePDF { origin, hitable })
     }
 
-    pub fn value(&self, direction: Vec3, time: Float, rng: ThreadRng) -> Float {
+    pub fn value(&self, direction: Vec3, time: Float, rng: &mut RandomGenerator) -> Float {
         self.hitable.pdf_value(self.origin, direction, time, rng)
     }
 
-    pub fn generate(&self, rng: ThreadRng) -> Vec3 {
+    pub fn generate(&self, rng: &mut RandomGenerator) -> Vec3 {
         self.hitable.random(self.origin, rng)
     }
 }
 
+/// A mixture of two PDFs: samples are generated from the first one with the probability `weight`, and from the second one otherwise
 pub struct MixturePDF<'a> {
     // Arc to prevent infinite size
     pdf1: Arc<PDF<'a>>,
     pdf2: Arc<PDF<'a>>,
+    /// Probability of sampling from `pdf1`
+    weight: Float,
 }
 
 impl<'a> MixturePDF<'a> {
-    pub fn new(pdf1: PDF<'a>, pdf2: PDF<'a>) -> PDF<'a> {
+    pub fn new(pdf1: PDF<'a>, pdf2: PDF<'a>, weight: Float) -> PDF<'a> {
         PDF::MixturePDF(MixturePDF {
             pdf1: Arc::new(pdf1),
             pdf2: Arc::new(pdf2),
+            weight,
         })
     }
 
-    pub fn value(&self, direction: Vec3, time: Float, rng: ThreadRng) -> Float {
-        0.5 * self.pdf1.value(direction, time, rng) + 0.5 * self.pdf2.value(direction, time, rng)
+    pub fn value(&self, direction: Vec3, time: Float, rng: &mut RandomGenerator) -> Float {
+        self.weight * self.pdf1.value(direction, time, rng)
+            + (1.0 - self.weight) * self.pdf2.value(direction, time, rng)
     }
 
-    pub fn generate(&self, mut rng: ThreadRng) -> Vec3 {
-        if rng.gen::<bool>() {
+    pub fn generate(&self, rng: &mut RandomGenerator) -> Vec3 {
+        if rng.gen::<Float>() < self.weight {
             self.pdf1.generate(rng)
         } else {
             self.pdf2.generate(rng)
@@ -101,6 +144,124 @@ impl<'a> MixturePDF<'a> {
     }
 }
 
+/// Sampling for the [Principled](crate::materials::Principled) material: a mixture of a cosine-weighted diffuse lobe and a GGX specular lobe, sampled by generating microfacet normals and reflecting the outgoing direction around them.
+pub struct MicrofacetPDF {
+    uvw: ONB,
+    outgoing: Vec3,
+    alpha: Float,
+    specular_probability: Float,
+}
+
+impl<'a> MicrofacetPDF {
+    pub fn new(normal: Vec3, outgoing: Vec3, alpha: Float, specular_probability: Float) -> PDF<'a> {
+        PDF::MicrofacetPDF(MicrofacetPDF {
+            uvw: ONB::build_from_w(normal),
+            outgoing,
+            alpha,
+            specular_probability,
+        })
+    }
+
+    /// Probability density of generating the `incoming` direction, given the surface normal and the `outgoing` direction. Both directions should be normalized.
+    pub fn density(
+        normal: Vec3,
+        outgoing: Vec3,
+        incoming: Vec3,
+        alpha: Float,
+        specular_probability: Float,
+    ) -> Float {
+        let n_dot_i = normal.dot(&incoming);
+        if n_dot_i <= 0.0 {
+            return 0.0;
+        }
+        let diffuse = n_dot_i / PI;
+
+        let halfway = (outgoing + incoming).normalize();
+        let o_dot_h = outgoing.dot(&halfway).abs();
+        let specular = if o_dot_h > 0.0 {
+            let n_dot_h = normal.dot(&halfway).max(0.0);
+            // Jacobian of the reflection mapping from halfway vectors to incoming directions
+            ggx_distribution(n_dot_h, alpha) * n_dot_h / (4.0 * o_dot_h)
+        } else {
+            0.0
+        };
+
+        specular_probability * specular + (1.0 - specular_probability) * diffuse
+    }
+
+    pub fn value(&self, direction: Vec3, _time: Float, _rng: &mut RandomGenerator) -> Float {
+        MicrofacetPDF::density(
+            self.uvw.w,
+            self.outgoing,
+            direction.normalize(),
+            self.alpha,
+            self.specular_probability,
+        )
+    }
+
+    pub fn generate(&self, rng: &mut RandomGenerator) -> Vec3 {
+        if rng.gen::<Float>() >= self.specular_probability {
+            return self.uvw.local(random_cosine_direction(rng));
+        }
+        // Sample a microfacet normal from the GGX distribution
+        let r1 = rng.gen::<Float>();
+        let r2 = rng.gen::<Float>();
+        let alpha2 = self.alpha * self.alpha;
+        let cos_theta = ((1.0 - r2) / (1.0 + (alpha2 - 1.0) * r2)).sqrt();
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * PI * r1;
+        let halfway = self.uvw.local(Vec3::new(
+            phi.cos() * sin_theta,
+            phi.sin() * sin_theta,
+            cos_theta,
+        ));
+        // Reflect the outgoing direction around the microfacet normal
+        2.0 * self.outgoing.dot(&halfway) * halfway - self.outgoing
+    }
+}
+
+/// Uniform sampling over all directions. The phase function of the [Isotropic](crate::materials::Isotropic) volume material.
+pub struct SpherePDF {}
+
+impl<'a> SpherePDF {
+    pub fn new() -> PDF<'a> {
+        PDF::SpherePDF(SpherePDF {})
+    }
+
+    pub fn value(&self, _direction: Vec3, _time: Float, _rng: &mut RandomGenerator) -> Float {
+        1.0 / (4.0 * PI)
+    }
+
+    pub fn generate(&self, rng: &mut RandomGenerator) -> Vec3 {
+        random_unit_vector(rng)
+    }
+}
+
+/// Sampling of the [Henyey-Greenstein](crate::materials::HenyeyGreenstein) phase function, around the direction of the incoming ray
+pub struct HenyeyGreensteinPDF {
+    /// Normalized direction of travel of the incoming ray
+    direction: Vec3,
+    anisotropy: Float,
+}
+
+impl<'a> HenyeyGreensteinPDF {
+    pub fn new(direction: Vec3, anisotropy: Float) -> PDF<'a> {
+        PDF::HenyeyGreensteinPDF(HenyeyGreensteinPDF {
+            direction: direction.normalize(),
+            anisotropy,
+        })
+    }
+
+    pub fn value(&self, direction: Vec3, _time: Float, _rng: &mut RandomGenerator) -> Float {
+        let cos_theta = self.direction.dot(&direction.normalize());
+        henyey_greenstein::phase(cos_theta, self.anisotropy)
+    }
+
+    pub fn generate(&self, rng: &mut RandomGenerator) -> Vec3 {
+        henyey_greenstein::sample_direction(self.direction, self.anisotropy, rng)
+    }
+}
+
 // TODO: this is an ugly hack due to tutorial saying `srec.pdf_ptr = 0;` in 12.2 Handling Specular for Metal
 pub struct ZeroPDF {}
 
@@ -109,11 +270,11 @@ impl<'a> ZeroPDF {
         PDF::ZeroPDF(ZeroPDF {})
     }
 
-    pub fn value(&self, _direction: Vec3, _time: Float, _rng: ThreadRng) -> Float {
+    pub fn value(&self, _direction: Vec3, _time: Float, _rng: &mut RandomGenerator) -> Float {
         0.0
     }
 
-    pub fn generate(&self, _rng: ThreadRng) -> Vec3 {
+    pub fn generate(&self, _rng: &mut RandomGenerator) -> Vec3 {
         Vec3::new(1.0, 0.0, 0.0)
     }
 }
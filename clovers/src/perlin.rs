@@ -1,6 +1,6 @@
 //! Perlin noise implementation. Used for e.g. [NoiseTexture](crate::textures::Texture::NoiseTexture)
 
-use crate::{Float, Vec3};
+use crate::{Float, RandomGenerator, Vec3};
 use rand::prelude::*;
 
 use std::fmt::Debug;
@@ -26,7 +26,7 @@ impl Debug for Perlin {
     }
 }
 
-fn perlin_generate_perm(rng: ThreadRng) -> [usize; 256] {
+fn perlin_generate_perm(rng: &mut RandomGenerator) -> [usize; 256] {
     let mut perm: [usize; 256] = [0; 256];
 
     for i in 0..256 {
@@ -37,7 +37,7 @@ fn perlin_generate_perm(rng: ThreadRng) -> [usize; 256] {
     perm
 }
 
-fn permute(p: &mut [usize; 256], mut rng: ThreadRng) {
+fn permute(p: &mut [usize; 256], rng: &mut RandomGenerator) {
     // For some reason the tutorial wants the reverse loop
     for i in (1..256).rev() {
         let target: usize = rng.gen_range(0, i);
@@ -69,7 +69,7 @@ fn perlin_interp(c: [[[Vec3; 2]; 2]; 2], u: Float, v: Float, w: Float) -> Float
 }
 
 impl Perlin {
-    pub fn new(mut rng: ThreadRng) -> Self {
+    pub fn new(rng: &mut RandomGenerator) -> Self {
         let mut random_vectors: [Vec3; 256] = [Vec3::new(0.0, 0.0, 0.0); 256];
         for i in 0..256 {
             random_vectors[i] = rng.gen::<Vec3>();
@@ -140,7 +140,8 @@ impl Perlin {
 
 impl Default for Perlin {
     fn default() -> Self {
-        let rng = thread_rng();
-        Perlin::new(rng)
+        // Fixed seed, so that scenes with noise textures render reproducibly
+        let mut rng = RandomGenerator::seed_from_u64(0);
+        Perlin::new(&mut rng)
     }
 }
@@ -0,0 +1,222 @@
+//! Post processing utilities for rendered pixel buffers.
+
+use crate::{color::Color, Float, Vec3};
+use rayon::prelude::*;
+
+/// The B3 spline kernel of the à-trous wavelet transform, for offsets `-2..=2`
+const KERNEL: [Float; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+
+/// Smallest albedo used when dividing out the albedo of the pixels, to avoid division by zero on black surfaces
+const ALBEDO_EPSILON: Float = 0.01;
+
+/// Settings for the [denoise()] function
+#[derive(Copy, Clone, Debug)]
+pub struct DenoiseSettings {
+    /// Number of filter passes. Each pass doubles the spacing of the filter taps, so five passes cover a radius of 62 pixels. Default value: 5
+    pub iterations: u32,
+    /// Sensitivity to luminance differences, in standard deviations of the estimated noise. Smaller values preserve more detail, but leave more noise. Default value: 4.0
+    pub sigma_color: Float,
+    /// Exponent of the cosine between the normals of two pixels. Larger values preserve geometric edges more strongly. Default value: 128.0
+    pub sigma_normal: Float,
+    /// Sensitivity to depth differences, relative to the depth of the pixel and the spacing of the filter taps. Smaller values preserve depth edges more strongly. Default value: 0.02
+    pub sigma_depth: Float,
+}
+
+impl Default for DenoiseSettings {
+    fn default() -> Self {
+        DenoiseSettings {
+            iterations: 5,
+            sigma_color: 4.0,
+            sigma_normal: 128.0,
+            sigma_depth: 0.02,
+        }
+    }
+}
+
+/// Per-pixel guide buffers for the [denoise()] function, in the same row-major order as the pixels. The albedo, normal and depth can be rendered with [AovBuffers](crate::aov::AovBuffers).
+pub struct Guides<'a> {
+    /// Color of the material at the first hit. Divided out before filtering, so that texture detail is not blurred.
+    pub albedo: &'a [Color],
+    /// Shading normal at the first hit, zero for rays that miss
+    pub normal: &'a [Vec3],
+    /// Distance to the first hit, infinite for rays that miss
+    pub depth: &'a [Float],
+    /// Estimated variance of the luminance of each pixel, e.g. from [Checkpoint::variances()](crate::checkpoint::Checkpoint::variances). Infinite values disable the luminance edge-stopping for the pixel.
+    pub variance: &'a [Float],
+}
+
+/// Denoises a rendered image with the edge-avoiding à-trous wavelet filter of [Dammertz et al. 2010](https://jo.dreggn.org/home/2010_atrous.pdf), with the variance-guided luminance weights of [SVGF](https://research.nvidia.com/publication/2017-07_spatiotemporal-variance-guided-filtering-real-time-reconstruction-path-traced). Neighboring pixels are averaged only when their normals, depths and luminances are similar, so that geometric edges and lighting features stay sharp.
+pub fn denoise(
+    width: u32,
+    height: u32,
+    pixels: &[Color],
+    guides: &Guides,
+    settings: DenoiseSettings,
+) -> Vec<Color> {
+    // Filter the incoming light instead of the final color, to keep the detail of the textures
+    let mut irradiance: Vec<Color> = pixels
+        .iter()
+        .zip(guides.albedo.iter())
+        .map(|(&color, &albedo)| demodulate(color, albedo))
+        .collect();
+    let mut variance: Vec<Float> = guides
+        .variance
+        .iter()
+        .zip(guides.albedo.iter())
+        .map(|(&variance, &albedo)| {
+            let luminance = albedo.luminance().max(ALBEDO_EPSILON);
+            variance / (luminance * luminance)
+        })
+        .collect();
+
+    for iteration in 0..settings.iterations {
+        let step = 1 << iteration;
+        let (filtered, filtered_variance) = filter_pass(
+            width,
+            height,
+            &irradiance,
+            &variance,
+            guides,
+            settings,
+            step,
+        );
+        irradiance = filtered;
+        variance = filtered_variance;
+    }
+
+    irradiance
+        .iter()
+        .zip(guides.albedo.iter())
+        .map(|(&irradiance, &albedo)| remodulate(irradiance, albedo))
+        .collect()
+}
+
+/// A single à-trous filter pass with the given spacing between the taps. Returns the filtered colors and their variances.
+fn filter_pass(
+    width: u32,
+    height: u32,
+    colors: &[Color],
+    variances: &[Float],
+    guides: &Guides,
+    settings: DenoiseSettings,
+    step: i64,
+) -> (Vec<Color>, Vec<Float>) {
+    // Pixels where all samples happened to be equal, e.g. all black, have a zero variance estimate. Blurring the variance for the edge-stopping lets their neighbors fill them in.
+    let deviations = blur_variance(width, height, variances);
+    let width = width as i64;
+    let height = height as i64;
+    (0..width * height)
+        .into_par_iter()
+        .map(|index| {
+            let x = index % width;
+            let y = index / width;
+            let p = index as usize;
+            let luminance_p = colors[p].luminance();
+            let deviation_p = deviations[p];
+            let normal_p = guides.normal[p].try_normalize(0.0);
+            let depth_p = guides.depth[p];
+
+            let mut color = Color::new(0.0, 0.0, 0.0);
+            let mut variance = 0.0;
+            let mut weights = 0.0;
+            for (j, kernel_y) in KERNEL.iter().enumerate() {
+                let qy = y + (j as i64 - 2) * step;
+                if qy < 0 || qy >= height {
+                    continue;
+                }
+                for (i, kernel_x) in KERNEL.iter().enumerate() {
+                    let qx = x + (i as i64 - 2) * step;
+                    if qx < 0 || qx >= width {
+                        continue;
+                    }
+                    let q = (qy * width + qx) as usize;
+
+                    let weight_normal = match (normal_p, guides.normal[q].try_normalize(0.0)) {
+                        (Some(normal_p), Some(normal_q)) => {
+                            normal_p.dot(&normal_q).max(0.0).powf(settings.sigma_normal)
+                        }
+                        (None, None) => 1.0,
+                        _ => 0.0,
+                    };
+                    let depth_q = guides.depth[q];
+                    let weight_depth = if depth_p.is_finite() && depth_q.is_finite() {
+                        let scale = settings.sigma_depth * depth_p * step as Float;
+                        (-(depth_p - depth_q).abs() / scale.max(Float::EPSILON)).exp()
+                    } else if depth_p.is_finite() == depth_q.is_finite() {
+                        1.0
+                    } else {
+                        0.0
+                    };
+                    let weight_color = (-(luminance_p - colors[q].luminance()).abs()
+                        / (settings.sigma_color * deviation_p + Float::EPSILON))
+                        .exp();
+
+                    let weight = kernel_x * kernel_y * weight_normal * weight_depth * weight_color;
+                    if weight <= 0.0 || !weight.is_finite() {
+                        continue;
+                    }
+                    color += colors[q] * weight;
+                    variance += weight * weight * variances[q];
+                    weights += weight;
+                }
+            }
+
+            // The center tap always has a positive weight, unless the pixel itself is not finite
+            if weights > 0.0 {
+                (color / weights, variance / (weights * weights))
+            } else {
+                (colors[p], variances[p])
+            }
+        })
+        .unzip()
+}
+
+/// Returns the standard deviations of a 3x3 gaussian blur of the variances
+fn blur_variance(width: u32, height: u32, variances: &[Float]) -> Vec<Float> {
+    const BLUR: [Float; 3] = [1.0 / 4.0, 1.0 / 2.0, 1.0 / 4.0];
+    let width = width as i64;
+    let height = height as i64;
+    (0..width * height)
+        .into_par_iter()
+        .map(|index| {
+            let x = index % width;
+            let y = index / width;
+            let mut variance = 0.0;
+            let mut weights = 0.0;
+            for (j, blur_y) in BLUR.iter().enumerate() {
+                let qy = y + j as i64 - 1;
+                if qy < 0 || qy >= height {
+                    continue;
+                }
+                for (i, blur_x) in BLUR.iter().enumerate() {
+                    let qx = x + i as i64 - 1;
+                    if qx < 0 || qx >= width {
+                        continue;
+                    }
+                    let weight = blur_x * blur_y;
+                    variance += weight * variances[(qy * width + qx) as usize].max(0.0);
+                    weights += weight;
+                }
+            }
+            (variance / weights).sqrt()
+        })
+        .collect()
+}
+
+/// Divides the albedo out of a color, leaving the incoming light
+fn demodulate(color: Color, albedo: Color) -> Color {
+    Color::new(
+        color.r / albedo.r.max(ALBEDO_EPSILON),
+        color.g / albedo.g.max(ALBEDO_EPSILON),
+        color.b / albedo.b.max(ALBEDO_EPSILON),
+    )
+}
+
+/// The inverse of [demodulate()]
+fn remodulate(irradiance: Color, albedo: Color) -> Color {
+    Color::new(
+        irradiance.r * albedo.r.max(ALBEDO_EPSILON),
+        irradiance.g * albedo.g.max(ALBEDO_EPSILON),
+        irradiance.b * albedo.b.max(ALBEDO_EPSILON),
+    )
+}
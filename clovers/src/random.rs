@@ -1,10 +1,10 @@
 //! Various internal helper functions for getting specific kinds of random values.
 
-use crate::{Float, Vec3, PI};
+use crate::{Float, RandomGenerator, Vec3, PI};
 use rand::prelude::*;
 
 /// Internal helper. Originally used for lambertian reflection with flaws
-pub fn random_in_unit_sphere(mut rng: ThreadRng) -> Vec3 {
+pub fn random_in_unit_sphere(rng: &mut RandomGenerator) -> Vec3 {
     let mut position: Vec3;
     // TODO: figure out a non-loop method
     // See https://github.com/RayTracing/raytracing.github.io/issues/765
@@ -17,7 +17,7 @@ pub fn random_in_unit_sphere(mut rng: ThreadRng) -> Vec3 {
 }
 
 /// Internal helper. Use this for the more correct "True Lambertian" reflection
-pub fn random_unit_vector(mut rng: ThreadRng) -> Vec3 {
+pub fn random_unit_vector(rng: &mut RandomGenerator) -> Vec3 {
     let a: Float = rng.gen_range(0.0, 2.0 * PI);
     let z: Float = rng.gen_range(-1.0, 1.0);
     let r: Float = (1.0 - z * z).sqrt();
@@ -25,7 +25,7 @@ pub fn random_unit_vector(mut rng: ThreadRng) -> Vec3 {
 }
 
 /// Internal helper.
-pub fn random_in_unit_disk(rng: &mut ThreadRng) -> Vec3 {
+pub fn random_in_unit_disk(rng: &mut RandomGenerator) -> Vec3 {
     let mut position: Vec3;
     // TODO: figure out a non-loop method
     // See https://github.com/RayTracing/raytracing.github.io/issues/765
@@ -39,7 +39,7 @@ pub fn random_in_unit_disk(rng: &mut ThreadRng) -> Vec3 {
 }
 
 /// Internal helper.
-pub fn random_cosine_direction(mut rng: ThreadRng) -> Vec3 {
+pub fn random_cosine_direction(rng: &mut RandomGenerator) -> Vec3 {
     let r1 = rng.gen::<Float>();
     let r2 = rng.gen::<Float>();
     let z = (1.0 - r2).sqrt();
@@ -52,7 +52,7 @@ pub fn random_cosine_direction(mut rng: ThreadRng) -> Vec3 {
 }
 
 /// Internal helper.
-pub fn random_to_sphere(radius: Float, distance_squared: Float, mut rng: ThreadRng) -> Vec3 {
+pub fn random_to_sphere(radius: Float, distance_squared: Float, rng: &mut RandomGenerator) -> Vec3 {
     let r1 = rng.gen::<Float>();
     let r2 = rng.gen::<Float>();
     let z = 1.0 + r2 * ((1.0 - radius * radius / distance_squared).sqrt() - 1.0);
@@ -63,3 +63,17 @@ pub fn random_to_sphere(radius: Float, distance_squared: Float, mut rng: ThreadR
 
     Vec3::new(x, y, z)
 }
+
+/// Creates a new [RandomGenerator] for a single sample of a single pixel. Every sample gets its own independent stream derived from the global seed, which makes the render reproducible regardless of thread count or the order in which the samples are taken.
+pub fn sample_rng(seed: u64, pixel_index: u64, sample_index: u64) -> RandomGenerator {
+    let hash = splitmix64(splitmix64(splitmix64(seed) ^ pixel_index) ^ sample_index);
+    RandomGenerator::seed_from_u64(hash)
+}
+
+/// Internal helper. The [SplitMix64](https://prng.di.unimi.it/splitmix64.c) mixing function, used for combining seeds.
+fn splitmix64(value: u64) -> u64 {
+    let mut z = value.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
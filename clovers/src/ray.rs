@@ -8,6 +8,8 @@ pub struct Ray {
     pub origin: Vec3,
     pub direction: Vec3,
     pub time: Float,
+    /// Wavelength of the ray in nanometers, in [spectral](crate::spectrum) mode. None in RGB mode.
+    pub wavelength: Option<Float>,
 }
 
 impl Ray {
@@ -17,9 +19,16 @@ impl Ray {
             origin,
             direction,
             time,
+            wavelength: None,
         }
     }
 
+    /// Returns the ray with the given wavelength. Rays that continue the path of another ray, e.g. scattered or transformed rays, should keep the wavelength of the original ray.
+    pub fn with_wavelength(mut self, wavelength: Option<Float>) -> Ray {
+        self.wavelength = wavelength;
+        self
+    }
+
     pub fn point_at_parameter(&self, t: Float) -> Vec3 {
         self.origin + t * self.direction
     }
@@ -1,16 +1,23 @@
 //! A collection of objects, camera, and other things necessary to describe the environment you wish to render.
 
 use crate::{
+    bidirectional::Emitters,
+    bvhnode::SplitMethod,
     camera::{Camera, CameraInit},
     color::Color,
+    colorize::RussianRoulette,
+    environment::{Environment, EnvironmentInit, EnvironmentMap},
     hitable::{Hitable, HitableList},
-    objects::Object,
-    Float,
+    objects::{GeometryInit, GeometryLibrary, Object},
+    textures::ImageCache,
+    tonemap::ToneMapping,
+    Float, RandomGenerator,
 };
 use rand::prelude::*;
 use serde::{Deserialize, Serialize};
 use std::fs::File;
-use std::io::prelude::*;
+use std::io::{prelude::*, Error, ErrorKind};
+use std::path::Path;
 
 // TODO: convert these to json or other
 // pub mod cornell;
@@ -28,10 +35,16 @@ use std::io::prelude::*;
 // pub mod two_spheres;
 
 pub struct Scene {
-    pub objects: Hitable, // BVHNode
+    pub objects: Hitable, // FlatBVH
     pub camera: Camera,
-    pub background_color: Color, // TODO: make into Texture or something?
+    pub environment: Environment,
     pub priority_objects: Hitable,
+    /// The emissive objects among the `priority_objects`, for the light paths of the [bidirectional path tracer](crate::bidirectional)
+    pub emitters: Emitters,
+    pub tone_mapping: ToneMapping,
+    pub russian_roulette: RussianRoulette,
+    /// The images used by the textures of the objects
+    pub images: ImageCache,
 }
 
 impl Scene {
@@ -41,14 +54,21 @@ impl Scene {
         camera: Camera,
         objects: HitableList,
         priority_objects: HitableList,
-        background_color: Color,
-        rng: ThreadRng,
+        environment: impl Into<Environment>,
+        rng: &mut RandomGenerator,
     ) -> Scene {
+        let objects = objects.into_bvh(time_0, time_1, SplitMethod::default(), rng);
+        let priority_objects = priority_objects.into_hitable();
+        let emitters = Emitters::new(&objects, &priority_objects, time_0, rng);
         Scene {
-            objects: objects.into_bvh(time_0, time_1, rng),
+            objects,
             camera,
-            background_color,
-            priority_objects: priority_objects.into_hitable(),
+            environment: environment.into(),
+            priority_objects,
+            emitters,
+            tone_mapping: ToneMapping::default(),
+            russian_roulette: RussianRoulette::default(),
+            images: ImageCache::default(),
         }
     }
 }
@@ -57,48 +77,179 @@ impl Scene {
 pub struct SceneFile {
     time_0: Float,
     time_1: Float,
+    /// Color of the background, used when there is no `environment`
+    #[serde(default)]
     background_color: Color,
+    /// Optional environment map. When given, it is used instead of the `background_color`
+    #[serde(default)]
+    environment: Option<EnvironmentInit>,
     camera: CameraInit,
+    /// Optional named geometry definitions, shared by the [Instance](crate::objects::Instance) objects referencing them
+    #[serde(default)]
+    geometry: Vec<GeometryInit>,
     objects: Vec<Object>,
     priority_objects: Vec<Object>,
+    /// Optional method for building the bounding volume hierarchy of the objects. Default value: [Median](SplitMethod::Median)
+    #[serde(default)]
+    bvh_split: SplitMethod,
+    /// Optional tone mapping settings for the low dynamic range output
+    #[serde(default)]
+    tone_mapping: ToneMapping,
+    /// Optional settings for terminating paths early with Russian roulette. Default value: enabled after 3 bounces
+    #[serde(default)]
+    russian_roulette: RussianRoulette,
+    /// Optional settings for rendering animations, see [initialize_frame()]
+    #[serde(default)]
+    animation: Animation,
 }
 
-pub fn initialize(mut file: File, width: u32, height: u32) -> Result<Scene, std::io::Error> {
+/// Timing settings for rendering animations. Frame `n` starts at the time `n / frame_rate`, in the same units as the times of the keyframes. Objects move during the shutter interval of each frame; the camera keyframes are evaluated once per frame, when the shutter opens.
+#[derive(Copy, Clone, Serialize, Deserialize, Debug)]
+pub struct Animation {
+    /// Number of frames per unit of time. Default value: 24.0
+    #[serde(default = "default_frame_rate")]
+    pub frame_rate: Float,
+    /// Fraction of the frame duration the shutter stays open, for motion blur. Must be greater than zero. Default value: 0.5
+    #[serde(default = "default_shutter")]
+    pub shutter: Float,
+}
+
+fn default_frame_rate() -> Float {
+    24.0
+}
+
+fn default_shutter() -> Float {
+    0.5
+}
+
+impl Default for Animation {
+    fn default() -> Self {
+        Animation {
+            frame_rate: default_frame_rate(),
+            shutter: default_shutter(),
+        }
+    }
+}
+
+impl Animation {
+    /// Returns the shutter interval of the given frame
+    pub fn shutter_interval(&self, frame: u32) -> (Float, Float) {
+        let time_0 = frame as Float / self.frame_rate;
+        let time_1 = time_0 + self.shutter / self.frame_rate;
+        (time_0, time_1)
+    }
+}
+
+/// Loads a still image scene from the scene file at the given path, with the shutter interval given by its `time_0` and `time_1`. The paths of external files in the scene file are relative to its directory.
+pub fn initialize(path: impl AsRef<Path>, width: u32, height: u32) -> Result<Scene, Error> {
+    let path = path.as_ref();
+    let scene_file = parse(path)?;
+    let time_0 = scene_file.time_0;
+    let time_1 = scene_file.time_1;
+    build(scene_file, directory(path), width, height, time_0, time_1)
+}
+
+/// Loads the scene for a frame of an animation from the scene file at the given path. The shutter interval of the frame is given by the [Animation] settings of the scene file, replacing its `time_0` and `time_1`.
+pub fn initialize_frame(
+    path: impl AsRef<Path>,
+    width: u32,
+    height: u32,
+    frame: u32,
+) -> Result<Scene, Error> {
+    let path = path.as_ref();
+    let scene_file = parse(path)?;
+    let animation = scene_file.animation;
+    if animation.frame_rate <= 0.0 || animation.shutter <= 0.0 {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "the animation frame rate and shutter must be greater than zero",
+        ));
+    }
+    let (time_0, time_1) = animation.shutter_interval(frame);
+    build(scene_file, directory(path), width, height, time_0, time_1)
+}
+
+fn parse(path: &Path) -> Result<SceneFile, Error> {
+    let mut file = File::open(path)?;
     let mut contents: String = String::new();
     file.read_to_string(&mut contents)?;
     let scene_file: SceneFile = serde_json::from_str(&contents)?;
-    let time_0 = scene_file.time_0;
-    let time_1 = scene_file.time_1;
-    let rng = thread_rng();
-    let background_color = scene_file.background_color;
-    let camera = Camera::new(
-        scene_file.camera.look_from,
-        scene_file.camera.look_at,
-        scene_file.camera.up,
-        scene_file.camera.vertical_fov,
-        width as Float / height as Float,
-        scene_file.camera.aperture,
-        scene_file.camera.focus_distance,
+    Ok(scene_file)
+}
+
+/// Returns the directory of the scene file, which the paths of the external files in it are relative to
+fn directory(path: &Path) -> &Path {
+    path.parent().unwrap_or_else(|| Path::new(""))
+}
+
+/// Resolves a path of an external file, relative to the directory of the scene file. Absolute paths are kept as they are.
+pub(crate) fn resolve_path(directory: &Path, path: &str) -> String {
+    directory.join(path).to_string_lossy().into_owned()
+}
+
+fn build(
+    mut scene_file: SceneFile,
+    directory: &Path,
+    width: u32,
+    height: u32,
+    time_0: Float,
+    time_1: Float,
+) -> Result<Scene, Error> {
+    // Scene construction only uses randomness for the BVH splits; a fixed seed keeps it reproducible
+    let mut rng = RandomGenerator::seed_from_u64(0);
+    let environment: Environment = match scene_file.environment {
+        Some(init) => EnvironmentMap::load(&resolve_path(directory, &init.path), init.intensity)?
+            .with_sampling_weight(init.sampling_weight)?
+            .into(),
+        None => scene_file.background_color.into(),
+    };
+    let mut images = ImageCache::new(directory);
+    for definition in scene_file.geometry.iter_mut() {
+        for obj in definition.objects.iter_mut() {
+            obj.resolve_paths(directory);
+            obj.load_images(&mut images)?;
+        }
+    }
+    for obj in scene_file
+        .objects
+        .iter_mut()
+        .chain(scene_file.priority_objects.iter_mut())
+    {
+        obj.resolve_paths(directory);
+        obj.load_images(&mut images)?;
+    }
+    let camera = scene_file
+        .camera
+        .build(width as Float / height as Float, time_0, time_1);
+    let library = GeometryLibrary::new(
+        scene_file.geometry,
         time_0,
         time_1,
-    );
+        scene_file.bvh_split,
+        &mut rng,
+    )?;
     let mut hitables = HitableList::new();
     for obj in scene_file.objects {
-        hitables.add(obj.into());
+        hitables.add(obj.build(&library, time_0, time_1)?);
     }
 
     let mut priority_objects = HitableList::new();
     for obj in scene_file.priority_objects {
-        priority_objects.add(obj.into());
+        priority_objects.add(obj.build(&library, time_0, time_1)?);
     }
 
-    Ok(Scene::new(
-        time_0,
-        time_1,
+    let objects = hitables.into_bvh(time_0, time_1, scene_file.bvh_split, &mut rng);
+    let priority_objects = priority_objects.into_hitable();
+    let emitters = Emitters::new(&objects, &priority_objects, time_0, &mut rng);
+
+    Ok(Scene {
+        objects,
         camera,
-        hitables,
+        environment,
         priority_objects,
-        background_color,
-        rng,
-    ))
+        emitters,
+        tone_mapping: scene_file.tone_mapping,
+        russian_roulette: scene_file.russian_roulette,
+        images,
+    })
 }
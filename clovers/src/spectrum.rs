@@ -0,0 +1,123 @@
+//! Spectral rendering: sampling wavelengths for rays, and converting between RGB colors and spectral values.
+//!
+//! In spectral mode, each camera ray carries a single wavelength, sampled uniformly over the visible range. The materials still use RGB colors: the RGB radiance found along the path is converted to a spectral value at the wavelength of the ray, and accumulated back to RGB with the CIE 1931 color matching functions. The conversions are normalized so that the round trip is exact on average, i.e. scenes without wavelength-dependent effects render the same as in RGB mode. Wavelength-dependent effects, like the [dispersion](crate::materials::dielectric::Dispersion) of glass, produce their colors through the color matching functions.
+
+use crate::{color::Color, Float, RandomGenerator};
+use nalgebra::{Matrix3, Vector3};
+use rand::prelude::*;
+
+/// Shortest wavelength sampled in spectral mode, in nanometers
+pub const WAVELENGTH_MIN: Float = 380.0;
+/// Longest wavelength sampled in spectral mode, in nanometers
+pub const WAVELENGTH_MAX: Float = 780.0;
+
+/// Number of steps used for integrating over the wavelength range
+const INTEGRATION_STEPS: usize = 400;
+
+/// The matrix from CIE XYZ to linear sRGB, with the D65 white point
+#[rustfmt::skip]
+const XYZ_TO_RGB: [[Float; 3]; 3] = [
+    [ 3.2406, -1.5372, -0.4986],
+    [-0.9689,  1.8758,  0.0415],
+    [ 0.0557, -0.2040,  1.0570],
+];
+
+/// Samples a wavelength uniformly over the visible range
+pub fn sample_wavelength(rng: &mut RandomGenerator) -> Float {
+    rng.gen_range(WAVELENGTH_MIN, WAVELENGTH_MAX)
+}
+
+/// Probability density of the wavelengths returned by [sample_wavelength()]
+pub fn wavelength_pdf() -> Float {
+    1.0 / (WAVELENGTH_MAX - WAVELENGTH_MIN)
+}
+
+/// Converts an RGB color into a spectral value at the given wavelength. The spectrum is a weighted sum of smooth blue, green and red basis spectra, which add up to one: white is a constant spectrum.
+pub fn rgb_to_spectral(color: Color, wavelength: Float) -> Float {
+    let [blue, green, red] = basis(wavelength);
+    color.r * red + color.g * green + color.b * blue
+}
+
+/// The CIE 1931 color matching functions at the given wavelength, using the multi-lobe gaussian fit of [Wyman, Sloan and Shirley 2013](https://jcgt.org/published/0002/02/01/)
+pub fn cie_xyz(wavelength: Float) -> Vector3<Float> {
+    let x = 1.056 * gaussian(wavelength, 599.8, 37.9, 31.0)
+        + 0.362 * gaussian(wavelength, 442.0, 16.0, 26.7)
+        - 0.065 * gaussian(wavelength, 501.1, 20.4, 26.2);
+    let y = 0.821 * gaussian(wavelength, 568.8, 46.9, 40.5)
+        + 0.286 * gaussian(wavelength, 530.9, 16.3, 31.1);
+    let z = 1.217 * gaussian(wavelength, 437.0, 11.8, 36.0)
+        + 0.681 * gaussian(wavelength, 459.0, 26.0, 13.8);
+    Vector3::new(x, y, z)
+}
+
+/// Converts spectral samples back to RGB colors
+#[derive(Copy, Clone, Debug)]
+pub struct SpectralConverter {
+    /// Maps the color matching responses to RGB, undoing the mixing of the basis spectra of [rgb_to_spectral()]
+    matrix: Matrix3<Float>,
+}
+
+impl SpectralConverter {
+    /// Creates a new converter. Integrates the color matching functions over the basis spectra of [rgb_to_spectral()], for normalizing the round trip from RGB to a spectrum and back.
+    pub fn new() -> SpectralConverter {
+        let xyz_to_rgb = xyz_to_rgb();
+        let step = (WAVELENGTH_MAX - WAVELENGTH_MIN) / INTEGRATION_STEPS as Float;
+        // Column j: the RGB response to the basis spectrum of channel j
+        let mut response: Matrix3<Float> = Matrix3::zeros();
+        for i in 0..INTEGRATION_STEPS {
+            let wavelength = WAVELENGTH_MIN + (i as Float + 0.5) * step;
+            let rgb = xyz_to_rgb * cie_xyz(wavelength) * step;
+            let [blue, green, red] = basis(wavelength);
+            for (channel, weight) in [red, green, blue].iter().enumerate() {
+                for row in 0..3 {
+                    response[(row, channel)] += rgb[row] * weight;
+                }
+            }
+        }
+        let inverse = match response.try_inverse() {
+            Some(inverse) => inverse,
+            None => panic!("The spectral basis responses are not invertible"),
+        };
+        SpectralConverter {
+            matrix: inverse * xyz_to_rgb,
+        }
+    }
+
+    /// Converts a spectral value at the given wavelength, sampled with [sample_wavelength()], into an RGB color. The average over many wavelengths converges to the color of the spectrum.
+    pub fn to_rgb(&self, value: Float, wavelength: Float) -> Color {
+        let rgb = self.matrix * cie_xyz(wavelength) * (value / wavelength_pdf());
+        Color::new(rgb.x, rgb.y, rgb.z)
+    }
+}
+
+impl Default for SpectralConverter {
+    fn default() -> Self {
+        SpectralConverter::new()
+    }
+}
+
+fn xyz_to_rgb() -> Matrix3<Float> {
+    let m = XYZ_TO_RGB;
+    Matrix3::new(
+        m[0][0], m[0][1], m[0][2], m[1][0], m[1][1], m[1][2], m[2][0], m[2][1], m[2][2],
+    )
+}
+
+/// The blue, green and red basis spectra at the given wavelength. Smooth steps between the bands, adding up to one at every wavelength.
+fn basis(wavelength: Float) -> [Float; 3] {
+    let blue = 1.0 - smoothstep(480.0, 510.0, wavelength);
+    let red = smoothstep(570.0, 600.0, wavelength);
+    [blue, 1.0 - blue - red, red]
+}
+
+fn smoothstep(edge_0: Float, edge_1: Float, x: Float) -> Float {
+    let t = ((x - edge_0) / (edge_1 - edge_0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+/// A gaussian with different widths on the left and right sides of the peak
+fn gaussian(x: Float, mean: Float, sigma_left: Float, sigma_right: Float) -> Float {
+    let sigma = if x < mean { sigma_left } else { sigma_right };
+    let t = (x - mean) / sigma;
+    (-0.5 * t * t).exp()
+}
@@ -1,32 +1,45 @@
 //! Textures enable different surface textures for colorizing objects in various ways.
 
 pub mod checkered;
+pub mod image_texture;
 pub mod noise_texture;
 pub mod solid_color;
 use serde::{Deserialize, Serialize};
 
 pub use checkered::*;
+pub use image_texture::*;
 // pub use noise_texture::*;
 pub use solid_color::*;
 
 use crate::{color::Color, Float, Vec3};
 use noise_texture::NoiseTexture;
+use std::io::Error;
 
-#[derive(Copy, Clone, Deserialize, Serialize, Debug)]
+#[derive(Clone, Deserialize, Serialize, Debug)]
 pub enum Texture {
     SpatialChecker(SpatialChecker),
     SurfaceChecker(SurfaceChecker),
     SolidColor(SolidColor),
     NoiseTexture(NoiseTexture),
+    ImageTexture(ImageTexture),
 }
 
 impl Texture {
     pub fn color(&self, u: Float, v: Float, position: Vec3) -> Color {
-        match *self {
-            Texture::SpatialChecker(c) => SpatialChecker::color(c, u, v, position),
-            Texture::SurfaceChecker(c) => SurfaceChecker::color(c, u, v, position),
-            Texture::SolidColor(s) => SolidColor::color(s, u, v, position),
-            Texture::NoiseTexture(n) => NoiseTexture::color(n, u, v, position),
+        match self {
+            Texture::SpatialChecker(c) => SpatialChecker::color(*c, u, v, position),
+            Texture::SurfaceChecker(c) => SurfaceChecker::color(*c, u, v, position),
+            Texture::SolidColor(s) => SolidColor::color(*s, u, v, position),
+            Texture::NoiseTexture(n) => NoiseTexture::color(*n, u, v, position),
+            Texture::ImageTexture(i) => i.color(u, v, position),
+        }
+    }
+
+    /// Loads the images of the texture through the cache, see [ImageTexture::load_images()]
+    pub fn load_images(&mut self, images: &mut ImageCache) -> Result<(), Error> {
+        match self {
+            Texture::ImageTexture(i) => i.load_images(images),
+            _ => Ok(()),
         }
     }
 }
@@ -60,3 +73,9 @@ impl From<NoiseTexture> for Texture {
         Texture::NoiseTexture(s)
     }
 }
+
+impl From<ImageTexture> for Texture {
+    fn from(s: ImageTexture) -> Self {
+        Texture::ImageTexture(s)
+    }
+}
@@ -0,0 +1,351 @@
+//! Image textures, loaded from PNG, JPEG or HDR files.
+
+use super::Texture;
+use crate::{color::Color, scenes::resolve_path, Float, Vec3};
+use image::codecs::hdr::HdrDecoder;
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt,
+    fs::File,
+    io::{BufReader, Error, ErrorKind},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+/// The scene file representation of an [ImageTexture]
+#[derive(Clone, Deserialize, Serialize, Debug)]
+pub struct ImageTextureInit {
+    /// Path to the image file, relative to the directory of the scene file. PNG, JPEG and other 8-bit formats are decoded from sRGB, HDR files are linear.
+    pub path: String,
+    /// Addressing of the texture outside the `0.0..=1.0` range of the surface coordinates. Default value: [Repeat](Wrap::Repeat)
+    #[serde(default)]
+    pub wrap: Wrap,
+    /// Treat 8-bit images as linear values instead of sRGB encoded colors, e.g. for masks and other data. Default value: false
+    #[serde(default)]
+    pub linear: bool,
+    /// Multiplier for the colors of the image. Default value: 1.0
+    #[serde(default = "default_intensity")]
+    pub intensity: Float,
+}
+
+fn default_intensity() -> Float {
+    1.0
+}
+
+/// Addressing modes for texture coordinates outside the image
+#[derive(Copy, Clone, Deserialize, Serialize, Debug, PartialEq, Default)]
+pub enum Wrap {
+    /// Tiles the image
+    #[default]
+    Repeat,
+    /// Extends the edge pixels of the image
+    Clamp,
+    /// Tiles the image, mirroring every other tile
+    Mirror,
+}
+
+impl Wrap {
+    /// Maps a pixel index into the range `0..size`. Returns zero for an empty range.
+    fn index(self, index: i64, size: usize) -> usize {
+        if size == 0 {
+            return 0;
+        }
+        let size = size as i64;
+        let index = match self {
+            Wrap::Repeat => index.rem_euclid(size),
+            Wrap::Clamp => index.clamp(0, size - 1),
+            Wrap::Mirror => {
+                let index = index.rem_euclid(2 * size);
+                if index < size {
+                    index
+                } else {
+                    2 * size - 1 - index
+                }
+            }
+        };
+        index as usize
+    }
+}
+
+/// Decoded pixels of an image file, as linear colors. An image that is not [loaded](ImageCache::load) yet has no pixels.
+pub struct Image {
+    path: String,
+    /// Whether 8-bit pixels were kept linear instead of decoded from sRGB
+    linear: bool,
+    width: usize,
+    height: usize,
+    /// Pixels in rows from the top of the image
+    pixels: Vec<Color>,
+}
+
+impl Image {
+    /// Loads and decodes an image file. Returns an error if the image has no pixels.
+    pub fn load(path: &str, linear: bool) -> Result<Image, Error> {
+        let extension = Path::new(path)
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.to_lowercase());
+
+        let (width, height, pixels) = if extension.as_deref() == Some("hdr") {
+            let reader = BufReader::new(File::open(path)?);
+            let decoder = HdrDecoder::new(reader).map_err(Error::other)?;
+            let metadata = decoder.metadata();
+            let pixels: Vec<Color> = decoder
+                .read_image_hdr()
+                .map_err(Error::other)?
+                .iter()
+                .map(|pixel| Color::new(pixel[0], pixel[1], pixel[2]))
+                .collect();
+            (metadata.width as usize, metadata.height as usize, pixels)
+        } else {
+            let image = image::open(path).map_err(Error::other)?.to_rgb8();
+            let pixels: Vec<Color> = image
+                .pixels()
+                .map(|pixel| {
+                    let color = Color::new(
+                        pixel[0] as Float / 255.0,
+                        pixel[1] as Float / 255.0,
+                        pixel[2] as Float / 255.0,
+                    );
+                    if linear {
+                        color
+                    } else {
+                        color.srgb_decode()
+                    }
+                })
+                .collect();
+            (image.width() as usize, image.height() as usize, pixels)
+        };
+        if width == 0 || height == 0 {
+            return Err(Error::new(ErrorKind::InvalidData, "image has no pixels"));
+        }
+
+        Ok(Image {
+            path: path.to_string(),
+            linear,
+            width,
+            height,
+            pixels,
+        })
+    }
+
+    /// Creates a placeholder for the image file, without loading it
+    fn unloaded(path: &str, linear: bool) -> Image {
+        Image {
+            path: path.to_string(),
+            linear,
+            width: 0,
+            height: 0,
+            pixels: Vec::new(),
+        }
+    }
+
+    fn pixel(&self, x: usize, y: usize) -> Color {
+        self.pixels[y * self.width + x]
+    }
+}
+
+/// The images used by the textures of a [Scene](crate::scenes::Scene). Each file is loaded once, and shared by all the textures using it.
+#[derive(Default)]
+pub struct ImageCache {
+    /// Directory the paths of the images are relative to. Empty for the current working directory.
+    directory: PathBuf,
+    images: Vec<Arc<Image>>,
+}
+
+impl ImageCache {
+    /// Creates an empty cache for images with paths relative to the given directory, usually the directory of the scene file
+    pub fn new(directory: &Path) -> ImageCache {
+        ImageCache {
+            directory: directory.to_path_buf(),
+            images: Vec::new(),
+        }
+    }
+
+    /// Returns the shared image for the file, loading it on first use. Relative paths are resolved against the directory of the cache.
+    pub fn load(&mut self, path: &str, linear: bool) -> Result<Arc<Image>, Error> {
+        let path = resolve_path(&self.directory, path);
+        let path = path.as_str();
+        if let Some(image) = self
+            .images
+            .iter()
+            .find(|image| image.path == path && image.linear == linear)
+        {
+            return Ok(image.clone());
+        }
+        let image = Image::load(path, linear).map_err(|err| {
+            Error::new(
+                err.kind(),
+                format!("failed to load image texture {}: {}", path, err),
+            )
+        })?;
+        let image = Arc::new(image);
+        self.images.push(image.clone());
+        Ok(image)
+    }
+}
+
+/// A texture from an image file, mapped to the `u` and `v` surface coordinates of the hitpoint, with bilinear filtering. The origin of the coordinates is at the bottom left corner of the image. Textures read from scene files are black until their images are loaded with [load_images()](ImageTexture::load_images).
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(from = "ImageTextureInit", into = "ImageTextureInit")]
+pub struct ImageTexture {
+    image: Arc<Image>,
+    wrap: Wrap,
+    intensity: Float,
+}
+
+impl ImageTexture {
+    /// Creates a new image texture, loading the image file through the cache
+    pub fn new(
+        path: &str,
+        wrap: Wrap,
+        linear: bool,
+        intensity: Float,
+        images: &mut ImageCache,
+    ) -> Result<Texture, Error> {
+        Ok(Texture::ImageTexture(ImageTexture {
+            image: images.load(path, linear)?,
+            wrap,
+            intensity,
+        }))
+    }
+
+    /// Loads the image of the texture through the cache, if it is not loaded yet
+    pub fn load_images(&mut self, images: &mut ImageCache) -> Result<(), Error> {
+        if self.image.pixels.is_empty() {
+            self.image = images.load(&self.image.path, self.image.linear)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the filtered color of the image at the surface coordinates. Non-finite coordinates are black.
+    pub fn color(&self, u: Float, v: Float, _position: Vec3) -> Color {
+        let image = &self.image;
+        if image.pixels.is_empty() || !u.is_finite() || !v.is_finite() {
+            return Color::new(0.0, 0.0, 0.0);
+        }
+        // Continuous pixel coordinates, with the pixel centers at half-integers
+        let x = u * image.width as Float - 0.5;
+        let y = (1.0 - v) * image.height as Float - 0.5;
+        let x_0 = x.floor();
+        let y_0 = y.floor();
+        let tx = x - x_0;
+        let ty = y - y_0;
+        // Huge coordinates saturate the conversion, don't overflow on the neighbors
+        let x_0 = x_0 as i64;
+        let y_0 = y_0 as i64;
+        let x_1 = x_0.saturating_add(1);
+        let y_1 = y_0.saturating_add(1);
+
+        let column = |x: i64| self.wrap.index(x, image.width);
+        let row = |y: i64| self.wrap.index(y, image.height);
+        let top = image.pixel(column(x_0), row(y_0)) * (1.0 - tx)
+            + image.pixel(column(x_1), row(y_0)) * tx;
+        let bottom = image.pixel(column(x_0), row(y_1)) * (1.0 - tx)
+            + image.pixel(column(x_1), row(y_1)) * tx;
+        (top * (1.0 - ty) + bottom * ty) * self.intensity
+    }
+}
+
+impl From<ImageTextureInit> for ImageTexture {
+    fn from(init: ImageTextureInit) -> Self {
+        ImageTexture {
+            image: Arc::new(Image::unloaded(&init.path, init.linear)),
+            wrap: init.wrap,
+            intensity: init.intensity,
+        }
+    }
+}
+
+impl From<ImageTexture> for ImageTextureInit {
+    fn from(texture: ImageTexture) -> Self {
+        ImageTextureInit {
+            path: texture.image.path.clone(),
+            wrap: texture.wrap,
+            linear: texture.image.linear,
+            intensity: texture.intensity,
+        }
+    }
+}
+
+impl fmt::Debug for ImageTexture {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ImageTexture")
+            .field("path", &self.image.path)
+            .field("wrap", &self.wrap)
+            .field("intensity", &self.intensity)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wrap_repeat() {
+        let index = |i| Wrap::Repeat.index(i, 4);
+        assert_eq!(
+            [-5, -4, -1, 0, 3, 4, 7, 8, 9].map(index),
+            [3, 0, 3, 0, 3, 0, 3, 0, 1]
+        );
+    }
+
+    #[test]
+    fn wrap_clamp() {
+        let index = |i| Wrap::Clamp.index(i, 4);
+        assert_eq!(
+            [i64::MIN, -5, -1, 0, 3, 4, 8, i64::MAX].map(index),
+            [0, 0, 0, 0, 3, 3, 3, 3]
+        );
+    }
+
+    #[test]
+    fn wrap_mirror() {
+        let index = |i| Wrap::Mirror.index(i, 4);
+        assert_eq!(
+            [-9, -8, -5, -4, -1, 0, 3, 4, 7, 8, 11, 12].map(index),
+            [0, 0, 3, 3, 0, 0, 3, 3, 0, 0, 3, 3]
+        );
+    }
+
+    #[test]
+    fn wrap_extremes_and_empty() {
+        for wrap in [Wrap::Repeat, Wrap::Clamp, Wrap::Mirror] {
+            assert!(wrap.index(i64::MIN, 4) < 4);
+            assert!(wrap.index(i64::MAX, 4) < 4);
+            assert_eq!(wrap.index(5, 0), 0);
+        }
+    }
+
+    #[test]
+    fn non_finite_coordinates() {
+        let gray = Color::new(0.5, 0.5, 0.5);
+        let image = Arc::new(Image {
+            path: String::new(),
+            linear: true,
+            width: 2,
+            height: 2,
+            pixels: vec![gray; 4],
+        });
+        for wrap in [Wrap::Repeat, Wrap::Clamp, Wrap::Mirror] {
+            let texture = ImageTexture {
+                image: Arc::clone(&image),
+                wrap,
+                intensity: 1.0,
+            };
+            let position = Vec3::new(0.0, 0.0, 0.0);
+            for (u, v) in [
+                (Float::NAN, 0.5),
+                (0.5, Float::INFINITY),
+                (Float::NEG_INFINITY, 0.5),
+            ] {
+                let color = texture.color(u, v, position);
+                assert_eq!((color.r, color.g, color.b), (0.0, 0.0, 0.0));
+            }
+            // Huge finite coordinates saturate, but still find a pixel
+            let color = texture.color(1e30, -1e30, position);
+            assert_eq!((color.r, color.g, color.b), (0.5, 0.5, 0.5));
+        }
+    }
+}
@@ -0,0 +1,242 @@
+//! Tone mapping: the transform from the linear high dynamic range radiance of a render into displayable colors.
+
+use crate::{color::Color, Float};
+use serde::{Deserialize, Serialize};
+use std::{fmt, str::FromStr};
+
+/// The tone mapping settings used when converting a render to a displayable, low dynamic range image. Used for the scene files etc
+#[derive(Copy, Clone, Debug, Deserialize, Serialize)]
+pub struct ToneMapping {
+    /// The tone mapping operator. Default value: [Clamp](ToneMapOperator::Clamp)
+    #[serde(default)]
+    pub operator: ToneMapOperator,
+    /// Exposure adjustment in stops (EV), applied before the operator. Each stop doubles the brightness. High dynamic range output formats are scaled by the exposure too. Default value: 0.0
+    #[serde(default)]
+    pub exposure: Float,
+    /// The luminance that gets mapped to pure white with the [ExtendedReinhard](ToneMapOperator::ExtendedReinhard) operator. Default value: 4.0
+    #[serde(default = "default_white_point")]
+    pub white_point: Float,
+    /// The transfer function used for encoding the tone mapped colors. Default value: gamma of 2.0
+    #[serde(default)]
+    pub transfer: Transfer,
+}
+
+fn default_white_point() -> Float {
+    4.0
+}
+
+impl Default for ToneMapping {
+    fn default() -> Self {
+        ToneMapping {
+            operator: ToneMapOperator::default(),
+            exposure: 0.0,
+            white_point: default_white_point(),
+            transfer: Transfer::default(),
+        }
+    }
+}
+
+impl ToneMapping {
+    /// Converts a linear color to a displayable color: applies the exposure, the tone mapping operator and the transfer function. The result still needs to be clamped to the `0.0..=1.0` range, e.g. with [to_rgb_u8()](Color::to_rgb_u8).
+    pub fn apply(&self, color: Color) -> Color {
+        let color = self.expose(color);
+        let color = self.operator.apply(color, self.white_point);
+        self.transfer.apply(color)
+    }
+
+    /// Scales a linear color by the exposure, keeping it linear. Used on its own for the high dynamic range output formats.
+    pub fn expose(&self, color: Color) -> Color {
+        color * self.exposure.exp2()
+    }
+}
+
+/// Tone mapping operators for compressing the high dynamic range of a render into the displayable range
+#[derive(Copy, Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+pub enum ToneMapOperator {
+    /// No tone mapping: values above 1.0 are clamped. Bright areas blow out to white.
+    #[default]
+    Clamp,
+    /// The simple [Reinhard](https://www-old.cs.utah.edu/docs/techreports/2002/pdf/UUCS-02-001.pdf) operator `L / (1 + L)` on the luminance. Never reaches pure white.
+    Reinhard,
+    /// The extended Reinhard operator, where the luminance at the white point is mapped to pure white
+    ExtendedReinhard,
+    /// [Krzysztof Narkowicz's curve fit](https://knarkowicz.wordpress.com/2016/01/06/aces-filmic-tone-mapping-curve/) of the ACES filmic tone mapping
+    Aces,
+}
+
+impl ToneMapOperator {
+    /// Applies the tone mapping operator to a linear color
+    pub fn apply(&self, color: Color, white_point: Float) -> Color {
+        match self {
+            ToneMapOperator::Clamp => color,
+            ToneMapOperator::Reinhard => {
+                scale_luminance(color, |luminance| luminance / (1.0 + luminance))
+            }
+            ToneMapOperator::ExtendedReinhard => {
+                let white_squared = white_point * white_point;
+                scale_luminance(color, |luminance| {
+                    luminance * (1.0 + luminance / white_squared) / (1.0 + luminance)
+                })
+            }
+            ToneMapOperator::Aces => Color::new(aces(color.r), aces(color.g), aces(color.b)),
+        }
+    }
+}
+
+impl FromStr for ToneMapOperator {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "clamp" | "none" => Ok(ToneMapOperator::Clamp),
+            "reinhard" => Ok(ToneMapOperator::Reinhard),
+            "extended-reinhard" | "extendedreinhard" => Ok(ToneMapOperator::ExtendedReinhard),
+            "aces" => Ok(ToneMapOperator::Aces),
+            _ => Err(format!(
+                "unknown tone mapping operator: {}. Valid values: clamp, reinhard, extended-reinhard, aces",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for ToneMapOperator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ToneMapOperator::Clamp => "clamp",
+            ToneMapOperator::Reinhard => "reinhard",
+            ToneMapOperator::ExtendedReinhard => "extended-reinhard",
+            ToneMapOperator::Aces => "aces",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Transfer functions for encoding linear colors for display
+#[derive(Copy, Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub enum Transfer {
+    /// A plain power curve with the given gamma value
+    Gamma(Float),
+    /// The piecewise sRGB transfer function, with a linear segment near black
+    Srgb,
+}
+
+impl Default for Transfer {
+    fn default() -> Self {
+        Transfer::Gamma(2.0)
+    }
+}
+
+impl Transfer {
+    /// Encodes a linear color with the transfer function
+    pub fn apply(&self, color: Color) -> Color {
+        match self {
+            Transfer::Gamma(gamma) => color.gamma_correction(*gamma),
+            Transfer::Srgb => color.srgb_encode(),
+        }
+    }
+}
+
+impl fmt::Display for Transfer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Transfer::Gamma(gamma) => write!(f, "gamma {}", gamma),
+            Transfer::Srgb => write!(f, "srgb"),
+        }
+    }
+}
+
+/// Scales the color so that its luminance matches the result of the given curve. Preserves the hue, unlike applying the curve per channel.
+fn scale_luminance(color: Color, curve: impl Fn(Float) -> Float) -> Color {
+    let luminance = color.luminance();
+    if luminance <= 0.0 {
+        return color;
+    }
+    color * (curve(luminance) / luminance)
+}
+
+/// The ACES filmic curve fit, for a single channel
+fn aces(x: Float) -> Float {
+    let a = 2.51;
+    let b = 0.03;
+    let c = 2.43;
+    let d = 0.59;
+    let e = 0.14;
+    ((x * (a * x + b)) / (x * (c * x + d) + e)).clamp(0.0, 1.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WHITE_POINT: Float = 4.0;
+
+    fn gray(value: Float) -> Color {
+        Color::new(value, value, value)
+    }
+
+    /// Returns the luminance of the operator applied to a gray of the given luminance
+    fn curve(operator: ToneMapOperator, luminance: Float) -> Float {
+        operator.apply(gray(luminance), WHITE_POINT).luminance()
+    }
+
+    #[test]
+    fn operators_map_black_to_black() {
+        for operator in [
+            ToneMapOperator::Reinhard,
+            ToneMapOperator::ExtendedReinhard,
+            ToneMapOperator::Aces,
+        ] {
+            assert_eq!(curve(operator, 0.0), 0.0, "{}", operator);
+        }
+    }
+
+    #[test]
+    fn operators_are_monotonic() {
+        for operator in [
+            ToneMapOperator::Reinhard,
+            ToneMapOperator::ExtendedReinhard,
+            ToneMapOperator::Aces,
+        ] {
+            // Up to the white point of the extended Reinhard operator, which only maps values below it
+            let mut previous = 0.0;
+            for step in 1..=400 {
+                let value = curve(operator, step as Float * WHITE_POINT / 400.0);
+                assert!(value >= previous, "{} at step {}", operator, step);
+                previous = value;
+            }
+        }
+    }
+
+    #[test]
+    fn operators_map_white_to_one() {
+        // The extended Reinhard operator reaches white exactly at the white point
+        let value = curve(ToneMapOperator::ExtendedReinhard, WHITE_POINT);
+        assert!((value - 1.0).abs() < 1e-5, "{}", value);
+        // The simple Reinhard operator only approaches white
+        let value = curve(ToneMapOperator::Reinhard, 1e6);
+        assert!(value < 1.0 && value > 1.0 - 1e-5, "{}", value);
+        // The ACES curve saturates to white a little above 7.2
+        assert_eq!(curve(ToneMapOperator::Aces, 8.0), 1.0);
+        assert!(curve(ToneMapOperator::Aces, 7.0) < 1.0);
+    }
+
+    #[test]
+    fn srgb_round_trip() {
+        for step in 0..=100 {
+            let value = step as Float / 100.0;
+            let encoded = Transfer::Srgb.apply(gray(value));
+            let decoded = encoded.srgb_decode();
+            assert!((decoded.r - value).abs() < 1e-5, "{}: {}", value, decoded.r);
+        }
+    }
+
+    #[test]
+    fn exposure_scales_by_stops() {
+        let tone_mapping = ToneMapping {
+            exposure: 2.0,
+            ..ToneMapping::default()
+        };
+        assert_eq!(tone_mapping.expose(gray(0.25)).r, 1.0);
+    }
+}